@@ -0,0 +1,31 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use carmen_core::gridstore::*;
+
+// Like decode_record, but through the matching path, which also runs the spatial filters over
+// the decoded coords. The first two bytes pick the filters to apply.
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let (opts, value) = data.split_at(2);
+    let match_opts = MatchOpts {
+        bbox: if opts[0] & 1 == 1 { Some([0, 0, opts[1] as u32, opts[1] as u32]) } else { None },
+        proximity: if opts[0] & 2 == 2 { Some([opts[1] as u32, 1]) } else { None },
+        zoom: 6,
+    };
+
+    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let mut db_key = Vec::new();
+    key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+
+    let mut storage = MemoryStorage::new();
+    storage.put(&db_key, value).unwrap();
+    let store = GridStore::from_storage(storage, 6, 0, 0., vec![], 0.).unwrap();
+
+    let match_key = MatchKey { match_phrase: MatchPhrase::Exact(1), lang_set: 1 };
+    if let Ok(entries) = store.streaming_get_matching(&match_key, &match_opts, 10) {
+        entries.for_each(drop);
+    }
+});
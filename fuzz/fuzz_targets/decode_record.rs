@@ -0,0 +1,20 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use carmen_core::gridstore::*;
+
+// Feeds arbitrary bytes to the decoder as the value of a single phrase record; decoding should
+// only ever fail with an error.
+fuzz_target!(|data: &[u8]| {
+    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let mut db_key = Vec::new();
+    key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+
+    let mut storage = MemoryStorage::new();
+    storage.put(&db_key, data).unwrap();
+    let store = GridStore::from_storage(storage, 6, 0, 0., vec![], 0.).unwrap();
+
+    if let Ok(Some(entries)) = store.get(&key) {
+        entries.for_each(drop);
+    }
+});
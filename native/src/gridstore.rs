@@ -1,7 +1,7 @@
-use carmen_core::gridstore::{coalesce, stack_and_coalesce, stackable};
+use carmen_core::gridstore::{coalesce, features_covering, stack_and_coalesce, stackable};
 use carmen_core::gridstore::{
-    CoalesceContext, GridEntry, GridKey, GridStore, GridStoreBuilder, MatchKey, MatchKeyWithId,
-    MatchOpts, PhrasematchSubquery,
+    BuildReport, CoalesceContext, FormatFeatures, GridEntry, GridKey, GridStore, GridStoreBuilder,
+    GridStoreOptions, MatchKey, MatchKeyWithId, MatchOpts, PhrasematchSubquery,
 };
 
 use failure::Error;
@@ -84,15 +84,34 @@ struct GridStoreOpts {
     pub zoom: u16,
     pub type_id: u16,
     pub coalesce_radius: f64,
-    pub bboxes: Vec<[u16; 4]>,
+    pub bboxes: Vec<[u32; 4]>,
     pub max_score: f64,
 }
 
+#[derive(Deserialize, Debug, Default)]
+struct GridStoreBuilderOpts {
+    #[serde(default)]
+    pub append: bool,
+}
+
 declare_types! {
     pub class JsGridStoreBuilder as JsGridStoreBuilder for Option<GridStoreBuilder> {
         init(mut cx) {
             let filename = cx.argument::<JsString>(0)?.value();
-            match GridStoreBuilder::new(filename) {
+            let opts: GridStoreBuilderOpts = match cx.argument_opt(1) {
+                Some(arg) => match neon_serde::from_value(&mut cx, arg) {
+                    Ok(v) => v,
+                    Err(e) => return cx.throw_type_error(e.to_string())
+                },
+                None => GridStoreBuilderOpts::default()
+            };
+            // appending starts from what's already in the store at the path, and replaces it
+            let builder = if opts.append {
+                GridStore::new(&filename).and_then(|store| GridStoreBuilder::from_store(&store))
+            } else {
+                GridStoreBuilder::new(filename)
+            };
+            match builder {
                 Ok(s) => Ok(Some(s)),
                 Err(e) => cx.throw_type_error(e.to_string())
             }
@@ -179,7 +198,7 @@ declare_types! {
             let id = cx.argument::<JsNumber>(3)?.value() as u32;
             let source_phrase_hash = cx.argument::<JsNumber>(4)?.value() as u8;
             let js_coords = cx.argument::<JsValue>(5)?;
-            let coords: Vec<(u16, u16)> = match neon_serde::from_value(&mut cx, js_coords) {
+            let coords: Vec<(u32, u32)> = match neon_serde::from_value(&mut cx, js_coords) {
                 Ok(v) => v,
                 Err(e) => return cx.throw_type_error(e.to_string())
             };
@@ -191,7 +210,9 @@ declare_types! {
                 let mut gridstore = this.borrow_mut(&lock);
                 match gridstore.as_mut() {
                     Some(builder) => {
-                        Ok(builder.compact_append(&key, relev, score, id, source_phrase_hash, &coords))
+                        builder
+                            .compact_append(&key, relev, score, id, source_phrase_hash, &coords)
+                            .map_err(|e| e.to_string())
                     }
                     None => {
                         Err("unable to insert()".to_string())
@@ -238,6 +259,39 @@ declare_types! {
             }
         }
 
+        method renumberFeatures(mut cx) {
+            let js_id_map = cx.argument::<JsArrayBuffer>(0)?;
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+
+                let borrow_result = match js_id_map.try_borrow(&lock) {
+                    Ok(data) => {
+                        let slice = data.as_slice::<u32>();
+
+                        let mut gridstore = this.borrow_mut(&lock);
+                        match gridstore.as_mut() {
+                            Some(builder) => {
+                                builder.renumber_features(slice).map_err(|e| e.to_string())
+                            }
+                            None => {
+                                Err("can't call renumberFeatures after finish()".to_owned())
+                            }
+                        }
+                    },
+                    Err(e) => Err(e.to_string())
+                };
+
+                borrow_result
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
         method loadBinBoundaries(mut cx) {
             let bin_boundaries = cx.argument::<JsArrayBuffer>(0)?;
             let mut this = cx.this();
@@ -271,33 +325,301 @@ declare_types! {
             }
         }
 
-        method finish(mut cx) {
+        method addTombstones(mut cx) {
+            let ids = cx.argument::<JsArrayBuffer>(0)?;
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+
+                let borrow_result = match ids.try_borrow(&lock) {
+                    Ok(data) => {
+                        let slice = data.as_slice::<u32>();
+
+                        let mut gridstore = this.borrow_mut(&lock);
+                        match gridstore.as_mut() {
+                            Some(builder) => {
+                                builder.add_tombstones(slice);
+                                Ok(())
+                            }
+                            None => {
+                                Err("can't call addTombstones after finish()".to_owned())
+                            }
+                        }
+                    },
+                    Err(e) => Err(e.to_string())
+                };
+
+                borrow_result
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method setFeatureIndex(mut cx) {
+            let enabled = cx.argument::<JsBoolean>(0)?.value();
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => {
+                        builder.set_feature_index(enabled);
+                        Ok(())
+                    }
+                    None => {
+                        Err("can't call setFeatureIndex after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method setTileIndex(mut cx) {
+            let enabled = cx.argument::<JsBoolean>(0)?.value();
             let mut this = cx.this();
 
-            let finish: Result<(), String> = {
+            let result: Result<(), String> = {
                 let lock = cx.lock();
                 let mut gridstore = this.borrow_mut(&lock);
-                match gridstore.take() {
+                match gridstore.as_mut() {
                     Some(builder) => {
-                        builder.finish().map_err(|e| e.to_string())
+                        builder.set_tile_index(enabled);
+                        Ok(())
                     }
                     None => {
-                        Err("unable to finish()".to_string())
+                        Err("can't call setTileIndex after finish()".to_owned())
                     }
                 }
             };
 
-            match finish {
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method setLossy(mut cx) {
+            let lossy = cx.argument::<JsBoolean>(0)?.value();
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => {
+                        builder.set_lossy(lossy);
+                        Ok(())
+                    }
+                    None => {
+                        Err("can't call setLossy after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method lossyReport(mut cx) {
+            let this = cx.this();
+
+            let report = {
+                let lock = cx.lock();
+                let gridstore = this.borrow(&lock);
+                gridstore.as_ref().map(|builder| builder.lossy_report().clone())
+            };
+
+            match report {
+                Some(report) => neon_serde::to_value(&mut cx, &report).or_else(|e| cx.throw_type_error(e.to_string())),
+                None => cx.throw_type_error("can't call lossyReport after finish()")
+            }
+        }
+
+        method setMemoryBudget(mut cx) {
+            let bytes = cx.argument::<JsNumber>(0)?.value() as usize;
+            let spill_dir = match cx.argument_opt(1) {
+                Some(arg) => Some(arg.downcast::<JsString>().or_throw(&mut cx)?.value()),
+                None => None
+            };
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => {
+                        builder.set_memory_budget(bytes);
+                        if let Some(spill_dir) = spill_dir {
+                            builder.set_spill_dir(spill_dir);
+                        }
+                        Ok(())
+                    }
+                    None => {
+                        Err("can't call setMemoryBudget after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method setAutoBins(mut cx) {
+            let max_bin_bytes = cx.argument::<JsNumber>(0)?.value() as usize;
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => {
+                        builder.set_auto_bins(max_bin_bytes);
+                        Ok(())
+                    }
+                    None => {
+                        Err("can't call setAutoBins after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method setFormatFeatures(mut cx) {
+            let js_features = cx.argument::<JsValue>(0)?;
+            let features: FormatFeatures = match neon_serde::from_value(&mut cx, js_features) {
+                Ok(v) => v,
+                Err(e) => return cx.throw_type_error(e.to_string())
+            };
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => builder.set_format_features(features).map_err(|e| e.to_string()),
+                    None => {
+                        Err("can't call setFormatFeatures after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
                 Ok(_) => Ok(JsUndefined::new().upcast()),
                 Err(e) => cx.throw_type_error(e)
             }
         }
+
+        method setMetadata(mut cx) {
+            let js_opts = cx.argument::<JsValue>(0)?;
+            let opts: GridStoreOptions = match neon_serde::from_value(&mut cx, js_opts) {
+                Ok(v) => v,
+                Err(e) => return cx.throw_type_error(e.to_string())
+            };
+            let source = match cx.argument_opt(1) {
+                Some(arg) => arg.downcast::<JsString>().or_throw(&mut cx)?.value(),
+                None => String::new()
+            };
+            let mut this = cx.this();
+
+            let result: Result<(), String> = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                match gridstore.as_mut() {
+                    Some(builder) => {
+                        builder.set_metadata(opts, &source);
+                        Ok(())
+                    }
+                    None => {
+                        Err("can't call setMetadata after finish()".to_owned())
+                    }
+                }
+            };
+
+            match result {
+                Ok(_) => Ok(JsUndefined::new().upcast()),
+                Err(e) => cx.throw_type_error(e)
+            }
+        }
+
+        method finish(mut cx) {
+            let progress = match cx.argument_opt(0) {
+                Some(arg) => Some(arg.downcast::<JsFunction>().or_throw(&mut cx)?),
+                None => None
+            };
+            let mut this = cx.this();
+
+            let builder = {
+                let lock = cx.lock();
+                let mut gridstore = this.borrow_mut(&lock);
+                gridstore.take()
+            };
+            let builder = match builder {
+                Some(builder) => builder,
+                None => return cx.throw_type_error("unable to finish()")
+            };
+
+            // the builder is out of `this` by now, so the callback can safely call back into it
+            let mut thrown = None;
+            let finish = match progress {
+                Some(callback) => builder.finish_with_progress(|event| {
+                    if thrown.is_some() {
+                        return;
+                    }
+                    let called = neon_serde::to_value(&mut cx, event)
+                        .or_else(|e| cx.throw_type_error(e.to_string()))
+                        .and_then(|js_event| {
+                            let js_this = cx.undefined();
+                            callback.call(&mut cx, js_this, vec![js_event])
+                        });
+                    if let Err(throw) = called {
+                        thrown = Some(throw);
+                    }
+                }),
+                None => builder.finish()
+            };
+            if let Some(throw) = thrown {
+                return Err(throw);
+            }
+
+            match finish {
+                Ok(report) => build_report_to_js(&mut cx, report),
+                Err(e) => cx.throw_type_error(e.to_string())
+            }
+        }
     }
 
     pub class JsGridStore as JsGridStore for ArcGridStore {
         init(mut cx) {
             let filename = cx.argument::<JsString>(0)?.value();
-            let store = match cx.argument_opt(1) {
+            // options may be left null to open with the recorded ones while still passing overlays
+            let js_opts = cx.argument_opt(1).filter(|arg| {
+                arg.downcast::<JsNull>().is_err() && arg.downcast::<JsUndefined>().is_err()
+            });
+            let overlay_paths: Vec<String> = match cx.argument_opt(2) {
+                Some(arg) => match neon_serde::from_value(&mut cx, arg) {
+                    Ok(v) => v,
+                    Err(e) => return cx.throw_type_error(e.to_string())
+                },
+                None => Vec::new()
+            };
+            let store = match js_opts {
                 Some(arg) => {
                     let opts: GridStoreOpts = match neon_serde::from_value(&mut cx, arg) {
                         Ok(v) => v,
@@ -315,6 +637,11 @@ declare_types! {
                 },
                 None => GridStore::new(filename)
             };
+            let store = store.and_then(|store| {
+                overlay_paths
+                    .iter()
+                    .try_fold(store, |store, path| store.with_overlay(GridStore::new(path)?))
+            });
             match store {
                 Ok(s) => Ok(Arc::new(s)),
                 Err(e) => cx.throw_type_error(e.to_string())
@@ -344,7 +671,9 @@ declare_types! {
                 let lock = cx.lock();
                 let grid_store = this.borrow_mut(&lock);
 
-                grid_store.get(&key).map(|option| option.map(|iter| iter.collect::<Vec<_>>()))
+                grid_store.get(&key).and_then(|option| {
+                    option.map(|iter| iter.collect::<Result<Vec<_>, _>>()).transpose()
+                })
             };
 
             match result {
@@ -353,6 +682,54 @@ declare_types! {
                 Err(e) => cx.throw_type_error(e.to_string())
             }
         }
+
+        method phrasesForFeature(mut cx) {
+            let id = cx.argument::<JsNumber>(0)?.value() as u32;
+            let mut this = cx.this();
+
+            let result = {
+                let lock = cx.lock();
+                let grid_store = this.borrow_mut(&lock);
+                grid_store.phrases_for_feature(id)
+            };
+
+            let keys = match result {
+                Ok(keys) => keys,
+                Err(e) => return cx.throw_type_error(e.to_string())
+            };
+            let out = JsArray::new(&mut cx, keys.len() as u32);
+            for (i, key) in keys.iter().enumerate() {
+                let js_gk = JsObject::new(&mut cx);
+
+                let phrase_id_label = JsString::new(&mut cx, "phrase_id");
+                let phrase_id_value = JsNumber::new(&mut cx, key.phrase_id);
+                js_gk.set(&mut cx, phrase_id_label, phrase_id_value)?;
+
+                let lang_set_label = JsString::new(&mut cx, "lang_set");
+                let lang_set_value = langset_to_langarray(&mut cx, key.lang_set);
+                js_gk.set(&mut cx, lang_set_label, lang_set_value)?;
+
+                out.set(&mut cx, i as u32, js_gk)?;
+            }
+            Ok(out.upcast())
+        }
+
+        method featuresAt(mut cx) {
+            let x = cx.argument::<JsNumber>(0)?.value() as u32;
+            let y = cx.argument::<JsNumber>(1)?.value() as u32;
+            let mut this = cx.this();
+
+            let result = {
+                let lock = cx.lock();
+                let grid_store = this.borrow_mut(&lock);
+                grid_store.features_at(x, y)
+            };
+
+            match result {
+                Ok(ids) => neon_serde::to_value(&mut cx, &ids).or_else(|e| cx.throw_type_error(e.to_string())),
+                Err(e) => cx.throw_type_error(e.to_string())
+            }
+        }
     }
 
     pub class JsGridKeyStoreKeyIterator as JsGridKeyStoreKeyIterator for KeyIterator {
@@ -463,6 +840,45 @@ fn langset_to_langarray<'j, C: Context<'j>>(cx: &mut C, lang_set: u128) -> Handl
     out
 }
 
+fn build_report_to_js<'j, C: Context<'j>>(cx: &mut C, mut report: BuildReport) -> JsResult<'j, JsValue> {
+    // the largest records have u128 lang sets, which are written out as language arrays instead
+    let largest_records = std::mem::take(&mut report.largest_records);
+    let out = match neon_serde::to_value(cx, &report) {
+        Ok(v) => v.downcast::<JsObject>().or_throw(cx)?,
+        Err(e) => return cx.throw_type_error(e.to_string())
+    };
+    let js_records = JsArray::new(cx, largest_records.len() as u32);
+    for (i, record) in largest_records.iter().enumerate() {
+        let js_record = JsObject::new(cx);
+
+        let marker_label = JsString::new(cx, "marker");
+        let marker_value = match neon_serde::to_value(cx, &record.marker) {
+            Ok(v) => v,
+            Err(e) => return cx.throw_type_error(e.to_string())
+        };
+        js_record.set(cx, marker_label, marker_value)?;
+
+        let js_key = JsObject::new(cx);
+        let phrase_id_label = JsString::new(cx, "phrase_id");
+        let phrase_id_value = JsNumber::new(cx, record.key.phrase_id);
+        js_key.set(cx, phrase_id_label, phrase_id_value)?;
+        let lang_set_label = JsString::new(cx, "lang_set");
+        let lang_set_value = langset_to_langarray(cx, record.key.lang_set);
+        js_key.set(cx, lang_set_label, lang_set_value)?;
+        let key_label = JsString::new(cx, "key");
+        js_record.set(cx, key_label, js_key)?;
+
+        let bytes_label = JsString::new(cx, "bytes");
+        let bytes_value = JsNumber::new(cx, record.bytes as f64);
+        js_record.set(cx, bytes_label, bytes_value)?;
+
+        js_records.set(cx, i as u32, js_record)?;
+    }
+    let records_label = JsString::new(cx, "largest_records");
+    out.set(cx, records_label, js_records)?;
+    Ok(out.upcast())
+}
+
 pub fn js_coalesce(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let js_phrase_subq = { cx.argument::<JsArray>(0)? };
     let js_match_ops = { cx.argument::<JsValue>(1)? };
@@ -544,7 +960,7 @@ where
         };
 
         let js_bounds = js_phrasematch.get(cx, "bounds")?;
-        let bounds: Option<[u16; 4]> = neon_serde::from_value(cx, js_bounds)?;
+        let bounds: Option<[u32; 4]> = neon_serde::from_value(cx, js_bounds)?;
 
         let js_non_overlapping_indexes = js_phrasematch.get(cx, "non_overlapping_indexes")?;
         let non_overlapping_indexes: Vec<u32> =
@@ -575,6 +991,31 @@ where
     Ok(phrasematches)
 }
 
+pub fn js_features_covering(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let js_stores = cx.argument::<JsArray>(0)?;
+    let zoom = cx.argument::<JsNumber>(1)?.value() as u16;
+    let x = cx.argument::<JsNumber>(2)?.value() as u32;
+    let y = cx.argument::<JsNumber>(3)?.value() as u32;
+
+    let mut stores: Vec<ArcGridStore> = Vec::with_capacity(js_stores.len() as usize);
+    for i in 0..js_stores.len() {
+        let js_gridstore = js_stores.get(&mut cx, i)?.downcast::<JsGridStore>().or_throw(&mut cx)?;
+        let gridstore = {
+            let guard = cx.lock();
+            // shallow clone of the Arc
+            let gridstore_clone = js_gridstore.borrow(&guard).clone();
+            gridstore_clone
+        };
+        stores.push(gridstore);
+    }
+
+    match features_covering(&stores, zoom, x, y) {
+        Ok(covering) => neon_serde::to_value(&mut cx, &covering)
+            .or_else(|e| cx.throw_type_error(e.to_string())),
+        Err(e) => cx.throw_type_error(e.to_string()),
+    }
+}
+
 pub fn js_stackable(mut cx: FunctionContext) -> JsResult<JsUndefined> {
     let js_phrasematch_result = { cx.argument::<JsArray>(0)? };
     let phrasematch_results: Vec<PhrasematchSubquery<ArcGridStore>> =
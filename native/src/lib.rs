@@ -16,6 +16,7 @@ register_module!(mut m, {
     m.export_function("coalesce", js_coalesce)?;
     m.export_function("stackable", js_stackable)?;
     m.export_function("stackAndCoalesce", js_stack_and_coalesce)?;
+    m.export_function("featuresCovering", js_features_covering)?;
 
     m.export_class::<JsFuzzyPhraseSetBuilder>("FuzzyPhraseSetBuilder")?;
     m.export_class::<JsFuzzyPhraseSet>("FuzzyPhraseSet")?;
@@ -1,36 +1,155 @@
+use std::cmp::Reverse;
 use std::collections::hash_map::Entry as HmEntry;
-use std::collections::{btree_map::Entry, BTreeMap, HashMap};
+use std::collections::{btree_map::Entry, BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use failure::{Error, Fail};
 use itertools::Itertools;
-use morton::interleave_morton;
-use rocksdb::{Options, DB};
+use rayon::prelude::*;
+use serde::Serialize;
 use smallvec::{smallvec, SmallVec};
 
 use crate::gridstore::common::*;
+use crate::gridstore::feature_index::{
+    encode_phrase_list, feature_index_key, index_ids, FeatureIndex, FEATURE_INDEX_KEY,
+};
 use crate::gridstore::gridstore_format;
+use crate::gridstore::metadata::{
+    write_format_version, FormatFeatures, GridStoreMetadata, GridStoreOptions,
+};
+use crate::gridstore::shared_values::{encode_reference, SharedValues, MAX_REFERENCE_LENGTH};
+use crate::gridstore::spatial::interleave_morton;
+use crate::gridstore::spill::{write_run, RecordSource, RunMerge, RunReader, RunRecord};
+use crate::gridstore::stats::{LargeRecord, LARGEST_RECORDS};
+use crate::gridstore::storage::{
+    GridStorageWriter, MmapStorageWriter, RocksDBBulkWriter, StorageFormat,
+};
+use crate::gridstore::store::GridStore;
+use crate::gridstore::tile_index::{
+    encode_id_list, index_tile, tile_index_key, TileIndex, TILE_INDEX_KEY,
+};
 
-type BuilderEntry = HashMap<u8, HashMap<u32, SmallVec<[u32; 4]>>>;
+/// Ids and morton-coded coords grouped under their packed relev/score, as made by
+/// `pack_relev_score`. Ids are packed with their source phrase hash as `id << 8 | hash`.
+pub(crate) type BuilderEntry = HashMap<u32, HashMap<u64, SmallVec<[u64; 4]>>>;
+
+/// Rough memory costs of the builder's data, for comparing against its memory budget: each key
+/// carries a couple of small hash maps, and each id a share of a coord entry.
+const APPROX_KEY_BYTES: usize = 256;
+const APPROX_ID_BYTES: usize = 16;
+
+/// How many records `finish` encodes in parallel at a time
+const ENCODE_BATCH_SIZE: usize = 4096;
+
+/// The largest id and score that fit in their share of the encoded values, without wide ids or
+/// high precision respectively
+const MAX_ID: u32 = (1 << 24) - 1;
+const MAX_SCORE: u8 = 15;
+
+/// Counts of the values a lossy builder has stored differently from how they were given
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LossyReport {
+    /// Relevs the encoding can't hold: anything but 0.4, 0.6, 0.8 and 1.0, which was stored as the
+    /// nearest of them, with values outside that range clamped, or with high precision, anything
+    /// outside 0 to 1, which was clamped
+    pub relevs_rounded: usize,
+    /// Scores over 15 that were stored as 15, without high precision
+    pub scores_clamped: usize,
+    /// Ids of 2^24 or more that were cut down to their lowest 24 bits, without wide ids
+    pub ids_truncated: usize,
+    /// Coordinates outside the extent of the store's zoom, which were kept anyway
+    pub coords_out_of_range: usize,
+}
+
+/// The stages `finish` goes through. Grouping, encoding and writing take turns a batch at a time,
+/// so progress events for those three are interleaved.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildPhase {
+    /// Merging spilled runs with the data in memory, and gathering phrases into their bins
+    Grouping,
+    Encoding,
+    Writing,
+    /// The storage backend putting the finished store together: RocksDB ingesting its sorted
+    /// files, or the single-file writer laying out its file
+    Compacting,
+}
+
+/// A progress event from `finish_with_progress`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildProgress {
+    pub phase: BuildPhase,
+    /// How many records the phase has got through so far: phrases for grouping, and phrase and
+    /// prefix bin records for the rest
+    pub records: usize,
+}
+
+/// A summary of what `finish` wrote
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BuildReport {
+    /// Single-phrase records
+    pub keys: usize,
+    pub prefix_bins: usize,
+    /// Combined size of every key and value handed to storage, index and metadata records included
+    pub bytes_written: u64,
+    /// The largest phrase and prefix bin records by encoded size, biggest first
+    pub largest_records: Vec<LargeRecord>,
+    /// Id lists that were written once and shared between coords with the same ids, summed over
+    /// every record
+    pub deduplicated_id_lists: usize,
+    /// Phrase records written as a reference to an identical earlier record, with shared values
+    pub shared_values: usize,
+    /// Roughly the most memory the records waiting to be encoded and the prefix bins being built
+    /// took up at once, by the same estimate as `set_memory_budget`
+    pub peak_bytes: usize,
+}
 
 pub struct GridStoreBuilder {
     path: PathBuf,
     data: BTreeMap<GridKey, BuilderEntry>,
-    bin_boundaries: Vec<u32>,
+    pub(crate) bin_boundaries: Vec<u32>,
+    /// The target bin size, if `finish` should pick bin boundaries itself
+    pub(crate) max_bin_bytes: Option<usize>,
+    storage_format: StorageFormat,
+    pub(crate) options: Option<GridStoreOptions>,
+    source: String,
+    tombstones: Vec<u32>,
+    pub(crate) feature_index: bool,
+    pub(crate) tile_index: bool,
+    memory_budget: Option<usize>,
+    /// A rough estimate of how much memory `data` is using
+    data_bytes: usize,
+    spill_parent: Option<PathBuf>,
+    spill_dir: Option<tempfile::TempDir>,
+    /// Runs spilled to `spill_dir`, oldest first
+    runs: Vec<PathBuf>,
+    runs_written: usize,
+    /// Keys `insert`ed since the last spill, whose entries replace what the runs hold for them
+    replacing: BTreeSet<GridKey>,
+    lossy: bool,
+    lossy_report: LossyReport,
+    pub(crate) format_features: FormatFeatures,
+    /// Whether `finish` is rewriting a store that already exists at `path`
+    replaces_store: bool,
 }
 
 /// Extends a BuildEntry with the given values.
-fn extend_entries(builder_entry: &mut BuilderEntry, values: Vec<GridEntry>) -> () {
+pub(crate) fn extend_entries(
+    builder_entry: &mut BuilderEntry,
+    values: Vec<GridEntry>,
+    high_precision: bool,
+) -> () {
     for (rs, rs_values) in somewhat_eager_groupby(values.into_iter(), |value| {
-        (relev_float_to_int(value.relev) << 4) | value.score
+        pack_relev_score(value.relev, value.score, high_precision)
     }) {
         let rs_entry =
             builder_entry.entry(rs).or_insert_with(|| HashMap::with_capacity(rs_values.len()));
         for (zcoord, zc_values) in
             &rs_values.into_iter().group_by(|value| interleave_morton(value.x, value.y))
         {
-            let id_phrases =
-                zc_values.map(|value| (value.id << 8) | (value.source_phrase_hash as u32));
+            let id_phrases = zc_values
+                .map(|value| (u64::from(value.id) << 8) | u64::from(value.source_phrase_hash));
             match rs_entry.entry(zcoord) {
                 HmEntry::Vacant(e) => {
                     e.insert(id_phrases.collect());
@@ -53,7 +172,287 @@ fn copy_entries(source_entry: &BuilderEntry, destination_entry: &mut BuilderEntr
     }
 }
 
-fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
+/// Moves everything in one entry into another.
+pub(crate) fn merge_entries(source: BuilderEntry, destination: &mut BuilderEntry) {
+    for (relev_score, coords) in source {
+        let rs_entry = destination.entry(relev_score).or_default();
+        for (coord, ids) in coords {
+            rs_entry.entry(coord).or_default().extend(ids);
+        }
+    }
+}
+
+/// Re-packs an entry's relev/scores in the given encoding, if any of them are in the other one.
+fn repack_entry(entry: BuilderEntry, high_precision: bool) -> BuilderEntry {
+    let packed_high = |relev_score: &u32| relev_score & HIGH_PRECISION_FLAG != 0;
+    if entry.keys().all(|relev_score| packed_high(relev_score) == high_precision) {
+        return entry;
+    }
+    let mut repacked = BuilderEntry::new();
+    for (relev_score, coords) in entry {
+        let (relev, score) = unpack_relev_score(relev_score);
+        let mut single = BuilderEntry::new();
+        single.insert(pack_relev_score(relev, score, high_precision), coords);
+        merge_entries(single, &mut repacked);
+    }
+    repacked
+}
+
+/// Roughly how big an entry is once encoded: mostly its coords, at 8 bytes each, and its ids, at 4.
+fn approx_encoded_bytes(entry: &BuilderEntry) -> usize {
+    entry
+        .values()
+        .map(|coords| coords.len() * 8 + coords.values().map(|ids| ids.len() * 4).sum::<usize>())
+        .sum()
+}
+
+/// Checks an entry's relev, score and id against what the format can hold, returning the values
+/// to store: the same ones, or if there's a lossy report to count the changes in, the nearest it
+/// can manage.
+pub(crate) fn check_values(
+    relev: f64,
+    score: u8,
+    id: u32,
+    features: &FormatFeatures,
+    mut lossy_report: Option<&mut LossyReport>,
+) -> Result<(f64, u8, u32), Error> {
+    let mut checked = (relev, score, id);
+    let high_precision = features.high_precision;
+    let supported = if high_precision {
+        (0.0..=1.0).contains(&relev)
+    } else {
+        [0.4, 0.6, 0.8, 1.0].contains(&relev)
+    };
+    if !supported {
+        match lossy_report.as_mut() {
+            Some(lossy_report) => lossy_report.relevs_rounded += 1,
+            None => return Err(BuildError::UnsupportedRelev { relev }.into()),
+        }
+        checked.0 = if !high_precision && !relev.is_nan() {
+            // the nearest of the four levels, 0.2 apart from 0.4 up; the cast saturates at 0
+            relev_int_to_float((((relev - 0.4) / 0.2).round() as u8).min(3))
+        } else if relev < 0. {
+            0.
+        } else {
+            1.
+        };
+    }
+    if score > MAX_SCORE && !high_precision {
+        match lossy_report.as_mut() {
+            Some(lossy_report) => lossy_report.scores_clamped += 1,
+            None => return Err(BuildError::ScoreOutOfRange { score }.into()),
+        }
+        checked.1 = MAX_SCORE;
+    }
+    if id > MAX_ID && !features.wide_ids {
+        match lossy_report.as_mut() {
+            Some(lossy_report) => lossy_report.ids_truncated += 1,
+            None => return Err(BuildError::IdOutOfRange { id }.into()),
+        }
+        checked.2 = id & MAX_ID;
+    }
+    Ok(checked)
+}
+
+/// Checks that a coordinate fits in the format and inside `zoom`, if there is one. Coordinates
+/// outside the zoom are only counted when there's a lossy report to count them in, but without
+/// wide coords, coordinates past 16 bits are an error even so, since there's no keeping them.
+pub(crate) fn check_coord(
+    x: u32,
+    y: u32,
+    zoom: Option<u16>,
+    features: &FormatFeatures,
+    lossy_report: Option<&mut LossyReport>,
+) -> Result<(), Error> {
+    let max_zoom = features.max_zoom();
+    let max_extent = 1u64 << max_zoom;
+    if u64::from(x) >= max_extent || u64::from(y) >= max_extent {
+        return Err(BuildError::CoordOutOfRange { x, y, zoom: max_zoom }.into());
+    }
+    let zoom = match zoom {
+        Some(zoom) => zoom,
+        None => return Ok(()),
+    };
+    // every coordinate that can be stored fits from the deepest zoom up
+    let extent = 1u64 << zoom.min(max_zoom);
+    if u64::from(x) >= extent || u64::from(y) >= extent {
+        match lossy_report {
+            Some(lossy_report) => lossy_report.coords_out_of_range += 1,
+            None => return Err(BuildError::CoordOutOfRange { x, y, zoom }.into()),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn count_ids(entry: &BuilderEntry) -> usize {
+    entry.values().flat_map(|coords| coords.values()).map(|ids| ids.len()).sum()
+}
+
+/// Records that `old_key` renumbers to `new_key`, failing if a different phrase already has.
+fn claim_renumbered_key(
+    sources: &mut BTreeMap<GridKey, u32>,
+    old_key: &GridKey,
+    new_key: &GridKey,
+) -> Result<(), Error> {
+    match sources.entry(new_key.clone()) {
+        Entry::Vacant(v) => {
+            v.insert(old_key.phrase_id);
+            Ok(())
+        }
+        Entry::Occupied(o) if *o.get() == old_key.phrase_id => Ok(()),
+        Entry::Occupied(_) => {
+            Err(BuildError::DuplicateRenumberEntry { target_id: new_key.phrase_id }.into())
+        }
+    }
+}
+
+/// Passes records through to the writer `finish_to` was given, keeping count of them for the
+/// build report and progress events.
+struct Tally<'w, W, F> {
+    writer: &'w mut W,
+    progress: F,
+    report: BuildReport,
+    largest: BinaryHeap<Reverse<(usize, TypeMarker, GridKey)>>,
+    grouped: usize,
+    encoded: usize,
+    /// The records written so far, if identical ones are to be shared
+    shared_values: Option<SharedValues>,
+}
+
+impl<'w, W: GridStorageWriter, F: FnMut(&BuildProgress)> Tally<'w, W, F> {
+    fn new(writer: &'w mut W, progress: F, features: &FormatFeatures) -> Self {
+        Tally {
+            writer,
+            progress,
+            report: BuildReport::default(),
+            largest: BinaryHeap::new(),
+            grouped: 0,
+            encoded: 0,
+            shared_values: if features.shared_values { Some(SharedValues::new()) } else { None },
+        }
+    }
+
+    fn report_progress(&mut self, phase: BuildPhase, records: usize) {
+        (self.progress)(&BuildProgress { phase, records });
+    }
+
+    fn grouped(&mut self, phrases: usize) {
+        self.grouped += phrases;
+        self.report_progress(BuildPhase::Grouping, self.grouped);
+    }
+
+    /// Notes roughly how much memory the records waiting to be written are taking up.
+    fn held(&mut self, bytes: usize) {
+        self.report.peak_bytes = self.report.peak_bytes.max(bytes);
+    }
+
+    /// Writes a bin's records, one for each lang_set found among its phrases.
+    fn write_bins(
+        &mut self,
+        bin: u32,
+        lang_set_map: HashMap<u128, BuilderEntry>,
+        features: &FormatFeatures,
+    ) -> Result<(), Error> {
+        let bins = lang_set_map
+            .into_iter()
+            .map(|(lang_set, entry)| (GridKey { phrase_id: bin, lang_set }, entry))
+            .collect();
+        self.write_encoded(TypeMarker::PrefixBin, bins, features)
+    }
+
+    /// Encodes a batch of records across the rayon pool, then hands them to the writer in key
+    /// order. With shared values, a phrase record identical to one already written is replaced
+    /// by a reference to it, unless the reference would be no smaller.
+    fn write_encoded(
+        &mut self,
+        marker: TypeMarker,
+        records: Vec<(GridKey, BuilderEntry)>,
+        features: &FormatFeatures,
+    ) -> Result<(), Error> {
+        let shared_values =
+            self.shared_values.as_ref().filter(|_| marker == TypeMarker::SinglePhrase);
+        let mut encoded = records
+            .into_par_iter()
+            .map(|(key, value)| {
+                let mut db_key = Vec::with_capacity(MAX_KEY_LENGTH);
+                key.write_to(marker, &mut db_key)?;
+                let (db_data, deduplicated) = encode_value(value, features)?;
+                let hash = shared_values
+                    .filter(|_| db_data.len() > MAX_REFERENCE_LENGTH)
+                    .map(|shared_values| shared_values.content_hash(&db_data));
+                Ok((db_key, db_data, key, deduplicated, hash))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.encoded += encoded.len();
+        self.report_progress(BuildPhase::Encoding, self.encoded);
+
+        encoded.sort_by(|(key_a, ..), (key_b, ..)| key_a.cmp(key_b));
+        for (db_key, db_data, key, deduplicated, hash) in encoded {
+            let reference = match (hash, self.shared_values.as_mut()) {
+                (Some(hash), Some(shared_values)) => {
+                    shared_values.find_or_insert(hash, &key).map(encode_reference).transpose()?
+                }
+                _ => None,
+            };
+            let bytes = match reference {
+                Some(reference) => {
+                    self.put(&db_key, &reference)?;
+                    self.report.shared_values += 1;
+                    reference.len()
+                }
+                None => {
+                    self.put(&db_key, &db_data)?;
+                    db_data.len()
+                }
+            };
+            if marker == TypeMarker::PrefixBin {
+                self.report.prefix_bins += 1;
+            } else {
+                self.report.keys += 1;
+            }
+            self.report.deduplicated_id_lists += deduplicated;
+            self.largest.push(Reverse((bytes, marker, key)));
+            if self.largest.len() > LARGEST_RECORDS {
+                self.largest.pop();
+            }
+        }
+        let written = self.report.keys + self.report.prefix_bins;
+        self.report_progress(BuildPhase::Writing, written);
+        Ok(())
+    }
+
+    fn finish(self) -> BuildReport {
+        let mut report = self.report;
+        for Reverse((bytes, marker, key)) in self.largest.into_sorted_vec() {
+            report.largest_records.push(LargeRecord { marker, key, bytes });
+        }
+        report
+    }
+}
+
+impl<'w, W: GridStorageWriter, F> GridStorageWriter for Tally<'w, W, F> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.report.bytes_written += (key.len() + value.len()) as u64;
+        self.writer.put(key, value)
+    }
+}
+
+fn chunk_records(
+    chunk: BTreeMap<GridKey, (bool, BuilderEntry)>,
+) -> impl Iterator<Item = RunRecord> {
+    chunk.into_iter().map(|(key, (replaces, entry))| RunRecord { key, replaces, entry })
+}
+
+pub(crate) fn get_encoded_value(
+    value: BuilderEntry,
+    features: &FormatFeatures,
+) -> Result<Vec<u8>, Error> {
+    Ok(encode_value(value, features)?.0)
+}
+
+/// Encodes an entry, also returning how many of its coords' id lists were shared with an
+/// identical list already written rather than written again.
+fn encode_value(value: BuilderEntry, features: &FormatFeatures) -> Result<(Vec<u8>, usize), Error> {
     let mut builder = gridstore_format::Writer::new();
 
     let mut items: Vec<(_, _)> = value.into_iter().collect();
@@ -64,6 +463,7 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
     let mut relevance_scores: Vec<_> = Vec::with_capacity(items.len());
 
     let mut id_lists: HashMap<_, gridstore_format::FixedVecOffset<u32>> = HashMap::new();
+    let mut deduplicated = 0;
 
     for (relevance_score, coord_group) in items.into_iter() {
         let mut inner_items: Vec<(_, _)> = coord_group.into_iter().collect();
@@ -76,13 +476,18 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
             ids.sort_by(|id_a, id_b| id_b.cmp(id_a));
             ids.dedup();
 
-            let encoded_ids =
-                id_lists.entry(ids.clone()).or_insert_with(|| builder.write_fixed_vec(&ids));
+            let encoded_ids = match id_lists.entry(ids.clone()) {
+                HmEntry::Occupied(e) => {
+                    deduplicated += 1;
+                    e.into_mut()
+                }
+                HmEntry::Vacant(e) => e.insert(builder.write_id_list(&ids, features)),
+            };
 
             let encoded_coord = gridstore_format::Coord { coord, ids: encoded_ids.clone() };
             coords.push(encoded_coord);
         }
-        let encoded_coords = builder.write_uniform_vec(&coords);
+        let encoded_coords = builder.write_coord_vec(&coords, features);
         let encoded_relevance_score =
             gridstore_format::RelevScore { relev_score: relevance_score, coords: encoded_coords };
         relevance_scores.push(encoded_relevance_score);
@@ -93,32 +498,109 @@ fn get_encoded_value(value: BuilderEntry) -> Result<Vec<u8>, Error> {
     let record = gridstore_format::PhraseRecord { relev_scores: encoded_relevance_scores };
     builder.write_fixed_scalar(record);
 
-    Ok(builder.finish())
+    Ok((builder.finish(), deduplicated))
 }
 
 impl GridStoreBuilder {
     /// Makes a new GridStoreBuilder with a particular filename.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        Ok(GridStoreBuilder {
-            path: path.as_ref().to_owned(),
+        Ok(GridStoreBuilder { path: path.as_ref().to_owned(), ..GridStoreBuilder::new_in_memory() })
+    }
+
+    /// Makes a new GridStoreBuilder that isn't tied to a path on disk, for use with `finish_to`.
+    pub fn new_in_memory() -> Self {
+        GridStoreBuilder {
+            path: PathBuf::new(),
             data: BTreeMap::new(),
             bin_boundaries: Vec::new(),
-        })
+            max_bin_bytes: None,
+            storage_format: StorageFormat::RocksDB,
+            options: None,
+            source: String::new(),
+            tombstones: Vec::new(),
+            feature_index: false,
+            tile_index: false,
+            memory_budget: None,
+            data_bytes: 0,
+            spill_parent: None,
+            spill_dir: None,
+            runs: Vec::new(),
+            runs_written: 0,
+            replacing: BTreeSet::new(),
+            lossy: false,
+            lossy_report: LossyReport::default(),
+            format_features: FormatFeatures::default(),
+            replaces_store: false,
+        }
+    }
+
+    /// Makes a builder that starts out holding everything in an existing store (though not any
+    /// overlays layered onto it), along with its bin boundaries, metadata, tombstones and indexes.
+    /// Further changes are made on top of those records, and `finish` then replaces the store at
+    /// its path with the result. The store shouldn't be read from once `finish` has been called,
+    /// and it has to have been opened from a path, since there'd be nowhere to put the result
+    /// otherwise.
+    pub fn from_store(store: &GridStore) -> Result<Self, Error> {
+        if store.path.as_os_str().is_empty() {
+            return Err(BuildError::StoreWithoutPath.into());
+        }
+        let mut builder = GridStoreBuilder::new(&store.path)?;
+        if store.path.is_file() {
+            builder.set_storage_format(StorageFormat::SingleFile);
+        }
+        builder.merge_stores(&[store])?;
+        builder.replaces_store = true;
+        Ok(builder)
+    }
+
+    /// Checks an entry's relev, score and id against what the format can hold, returning the
+    /// values to store.
+    fn check_values(&mut self, relev: f64, score: u8, id: u32) -> Result<(f64, u8, u32), Error> {
+        let lossy_report = if self.lossy { Some(&mut self.lossy_report) } else { None };
+        check_values(relev, score, id, &self.format_features, lossy_report)
+    }
+
+    /// Checks that a coordinate is inside the zoom given to `set_metadata`, if it's been called.
+    fn check_coord(&mut self, x: u32, y: u32) -> Result<(), Error> {
+        let zoom = self.options.as_ref().map(|options| options.zoom);
+        let lossy_report = if self.lossy { Some(&mut self.lossy_report) } else { None };
+        check_coord(x, y, zoom, &self.format_features, lossy_report)
+    }
+
+    fn check_entries(&mut self, values: Vec<GridEntry>) -> Result<Vec<GridEntry>, Error> {
+        values
+            .into_iter()
+            .map(|value| {
+                let (relev, score, id) = self.check_values(value.relev, value.score, value.id)?;
+                self.check_coord(value.x, value.y)?;
+                Ok(GridEntry { relev, score, id, ..value })
+            })
+            .collect()
     }
 
     /// Inserts a new GridStore entry with the given values.
     pub fn insert(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), Error> {
+        let values = self.check_entries(values)?;
+        self.data_bytes += APPROX_KEY_BYTES + values.len() * APPROX_ID_BYTES;
         let mut to_insert = BuilderEntry::new();
-        extend_entries(&mut to_insert, values);
+        extend_entries(&mut to_insert, values, self.format_features.high_precision);
+        if !self.runs.is_empty() {
+            self.replacing.insert(key.to_owned());
+        }
         self.data.insert(key.to_owned(), to_insert);
-        Ok(())
+        self.spill_if_over_budget()
     }
 
     ///  Appends a values to and existing GridStore entry.
     pub fn append(&mut self, key: &GridKey, values: Vec<GridEntry>) -> Result<(), Error> {
+        let values = self.check_entries(values)?;
+        self.data_bytes += values.len() * APPROX_ID_BYTES;
+        if !self.data.contains_key(key) {
+            self.data_bytes += APPROX_KEY_BYTES;
+        }
         let mut to_append = self.data.entry(key.to_owned()).or_insert_with(|| BuilderEntry::new());
-        extend_entries(&mut to_append, values);
-        Ok(())
+        extend_entries(&mut to_append, values, self.format_features.high_precision);
+        self.spill_if_over_budget()
     }
 
     pub fn compact_append(
@@ -128,13 +610,22 @@ impl GridStoreBuilder {
         score: u8,
         id: u32,
         source_phrase_hash: u8,
-        coords: &[(u16, u16)],
-    ) {
+        coords: &[(u32, u32)],
+    ) -> Result<(), Error> {
+        let (relev, score, id) = self.check_values(relev, score, id)?;
+        for &(x, y) in coords {
+            self.check_coord(x, y)?;
+        }
+
+        self.data_bytes += coords.len() * APPROX_ID_BYTES;
+        if !self.data.contains_key(key) {
+            self.data_bytes += APPROX_KEY_BYTES;
+        }
         let to_append =
             self.data.entry(key.to_owned()).or_insert_with(|| BuilderEntry::with_capacity(1));
 
-        let relev_score = (relev_float_to_int(relev) << 4) | score;
-        let id_hash = smallvec![(id << 8) | (source_phrase_hash as u32)];
+        let relev_score = pack_relev_score(relev, score, self.format_features.high_precision);
+        let id_hash = smallvec![(u64::from(id) << 8) | u64::from(source_phrase_hash)];
         let relevance_score_entry =
             to_append.entry(relev_score).or_insert_with(|| HashMap::with_capacity(coords.len()));
         for pair in coords {
@@ -148,26 +639,153 @@ impl GridStoreBuilder {
                 }
             }
         }
+
+        self.spill_if_over_budget()
+    }
+
+    /// Chooses whether values the format can't hold are an error (the default) or are stored as
+    /// best they can be. In lossy mode, relevs other than 0.4, 0.6, 0.8 and 1.0 are stored as the
+    /// nearest of them, with anything below 0.4 or above 1.0 clamped, and scores over 15 as 15;
+    /// with high precision, relevs are clamped between 0 and 1 instead. Ids without wide ids only
+    /// keep their lowest 24 bits, so that larger ones can collide, and coordinates outside the
+    /// zoom are kept as they are, so long as they fit in 16 bits or the store has wide coords.
+    /// Everything changed or let through is counted in `lossy_report`.
+    pub fn set_lossy(&mut self, lossy: bool) {
+        self.lossy = lossy;
+    }
+
+    /// Chooses the optional record encodings `finish` writes. Values are checked and packed for
+    /// the encodings as they're added, so these can't change once the builder has any data.
+    pub fn set_format_features(&mut self, features: FormatFeatures) -> Result<(), Error> {
+        if features != self.format_features && !(self.data.is_empty() && self.runs.is_empty()) {
+            return Err(BuildError::FormatFeaturesAfterData.into());
+        }
+        self.format_features = features;
+        Ok(())
+    }
+
+    /// What lossy mode has had to change so far.
+    pub fn lossy_report(&self) -> &LossyReport {
+        &self.lossy_report
+    }
+
+    /// Adds an already-encoded entry, as read back out of a finished store, to whatever the
+    /// builder has for its key.
+    pub(crate) fn append_entry(&mut self, key: &GridKey, entry: BuilderEntry) -> Result<(), Error> {
+        let entry = repack_entry(entry, self.format_features.high_precision);
+        self.data_bytes += count_ids(&entry) * APPROX_ID_BYTES;
+        if !self.data.contains_key(key) {
+            self.data_bytes += APPROX_KEY_BYTES;
+        }
+        merge_entries(entry, self.data.entry(key.to_owned()).or_default());
+        self.spill_if_over_budget()
+    }
+
+    /// Caps, roughly, how much memory the builder's data may take up. Once it's past the budget,
+    /// the builder writes what it has out to a sorted run in a temporary directory and starts
+    /// afresh, and `finish` merges the runs back together, encoding them in batches that keep to
+    /// the budget too (though a prefix bin still has to be put together in memory). Without a
+    /// budget, everything stays in memory until `finish`. The estimate is coarse, so leave some
+    /// headroom.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = Some(bytes);
+    }
+
+    /// Chooses the directory spilled runs are written under, instead of the system's temporary
+    /// directory. This has no effect once the builder has started spilling.
+    pub fn set_spill_dir<P: AsRef<Path>>(&mut self, dir: P) {
+        self.spill_parent = Some(dir.as_ref().to_owned());
+    }
+
+    fn spill_if_over_budget(&mut self) -> Result<(), Error> {
+        match self.memory_budget {
+            Some(budget) if self.data_bytes > budget => self.spill(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes everything in memory out to a new run.
+    fn spill(&mut self) -> Result<(), Error> {
+        let data = std::mem::take(&mut self.data);
+        let replacing = std::mem::take(&mut self.replacing);
+        self.data_bytes = 0;
+        self.write_run(data.into_iter().map(|(key, entry)| RunRecord {
+            replaces: replacing.contains(&key),
+            key,
+            entry,
+        }))
+    }
+
+    fn write_run<I: IntoIterator<Item = RunRecord>>(&mut self, records: I) -> Result<(), Error> {
+        if self.spill_dir.is_none() {
+            self.spill_dir = Some(match &self.spill_parent {
+                Some(parent) => tempfile::tempdir_in(parent)?,
+                None => tempfile::tempdir()?,
+            });
+        }
+        let path =
+            self.spill_dir.as_ref().unwrap().path().join(format!("run-{}", self.runs_written));
+        write_run(&path, records)?;
+        self.runs.push(path);
+        self.runs_written += 1;
+        Ok(())
     }
 
     /// In situations under which data has been inserted using temporary phrase IDs, renumber
     /// the data in the index to use final phrase IDs, given a temporary-to-final-ID mapping
     pub fn renumber(&mut self, tmp_phrase_ids_to_ids: &[u32]) -> Result<(), Error> {
-        let mut old_data: BTreeMap<GridKey, BuilderEntry> = BTreeMap::new();
-        std::mem::swap(&mut old_data, &mut self.data);
-
-        for (key, value) in old_data.into_iter() {
+        let renumber_key = |key: &GridKey| -> Result<GridKey, Error> {
             let new_phrase_id = tmp_phrase_ids_to_ids
                 .get(key.phrase_id as usize)
                 .ok_or_else(|| BuildError::OutOfBoundsRenumberEntry { tmp_id: key.phrase_id })?;
-            let new_key = GridKey { phrase_id: *new_phrase_id, ..key };
+            Ok(GridKey { phrase_id: *new_phrase_id, lang_set: key.lang_set })
+        };
+
+        // the same key can have data in several runs, so rather than just checking for a key
+        // that's already been renumbered to, check that it came from the same phrase
+        let mut sources: BTreeMap<GridKey, u32> = BTreeMap::new();
+        let runs = std::mem::take(&mut self.runs);
+        let had_runs = !runs.is_empty();
+        for run in runs {
+            // renumbering reorders the run, so it's re-sorted in budget-sized pieces; every key
+            // in a run is distinct, so the pieces don't need to be merged with each other
+            let mut chunk: BTreeMap<GridKey, (bool, BuilderEntry)> = BTreeMap::new();
+            let mut chunk_bytes = 0;
+            for record in RunReader::open(&run)? {
+                let record = record?;
+                let new_key = renumber_key(&record.key)?;
+                claim_renumbered_key(&mut sources, &record.key, &new_key)?;
+                chunk_bytes += APPROX_KEY_BYTES + count_ids(&record.entry) * APPROX_ID_BYTES;
+                chunk.insert(new_key, (record.replaces, record.entry));
+                if self.memory_budget.filter(|budget| chunk_bytes > *budget).is_some() {
+                    self.write_run(chunk_records(std::mem::take(&mut chunk)))?;
+                    chunk_bytes = 0;
+                }
+            }
+            if !chunk.is_empty() {
+                self.write_run(chunk_records(chunk))?;
+            }
+            std::fs::remove_file(&run)?;
+        }
+
+        let old_data = std::mem::take(&mut self.data);
+        let old_replacing = std::mem::take(&mut self.replacing);
+        for (key, value) in old_data.into_iter() {
+            let new_key = renumber_key(&key)?;
+            if had_runs {
+                claim_renumbered_key(&mut sources, &key, &new_key)?;
+            }
+            if old_replacing.contains(&key) {
+                self.replacing.insert(new_key.clone());
+            }
+            let new_phrase_id = new_key.phrase_id;
             match self.data.entry(new_key) {
                 Entry::Vacant(v) => {
                     v.insert(value);
                 }
                 Entry::Occupied(_) => {
                     return Err(Error::from(BuildError::DuplicateRenumberEntry {
-                        target_id: *new_phrase_id,
+                        target_id: new_phrase_id,
                     }))
                 }
             };
@@ -175,68 +793,382 @@ impl GridStoreBuilder {
         Ok(())
     }
 
+    /// Like `renumber`, but for feature ids: every id in every record, spilled or not, is replaced
+    /// by its entry in a temporary-to-final-ID mapping, keeping the `source_phrase_hash` packed in
+    /// alongside it. Two temporary ids in use mapping to the same final id is an error, as is a
+    /// final id of 2^24 or more without wide ids, even in lossy mode. The whole mapping is checked
+    /// before anything changes. Tombstones are left alone, since they name features in the stores
+    /// beneath this one.
+    pub fn renumber_features(&mut self, tmp_ids_to_ids: &[u32]) -> Result<(), Error> {
+        let wide_ids = self.format_features.wide_ids;
+        let mut sources: HashMap<u32, u32> = HashMap::new();
+        let mut check_entry = |entry: &BuilderEntry| -> Result<(), Error> {
+            for id_comp in entry.values().flat_map(|coords| coords.values()).flatten() {
+                let tmp_id = (id_comp >> 8) as u32;
+                let new_id = *tmp_ids_to_ids
+                    .get(tmp_id as usize)
+                    .ok_or(BuildError::OutOfBoundsRenumberEntry { tmp_id })?;
+                if new_id > MAX_ID && !wide_ids {
+                    return Err(BuildError::IdOutOfRange { id: new_id }.into());
+                }
+                match sources.entry(new_id) {
+                    HmEntry::Vacant(v) => {
+                        v.insert(tmp_id);
+                    }
+                    HmEntry::Occupied(o) if *o.get() == tmp_id => {}
+                    HmEntry::Occupied(_) => {
+                        return Err(BuildError::DuplicateRenumberEntry { target_id: new_id }.into())
+                    }
+                }
+            }
+            Ok(())
+        };
+        for run in &self.runs {
+            for record in RunReader::open(run)? {
+                check_entry(&record?.entry)?;
+            }
+        }
+        for entry in self.data.values() {
+            check_entry(entry)?;
+        }
+
+        let renumber_entry = |entry: &mut BuilderEntry| {
+            for ids in entry.values_mut().flat_map(|coords| coords.values_mut()) {
+                for id_comp in ids.iter_mut() {
+                    let new_id = tmp_ids_to_ids[(*id_comp >> 8) as usize];
+                    *id_comp = (u64::from(new_id) << 8) | (*id_comp & 0xff);
+                }
+            }
+        };
+        // keys don't change, so each run can be streamed into a new one in the same order
+        let runs = std::mem::take(&mut self.runs);
+        for run in runs {
+            let mut error = None;
+            let records = RunReader::open(&run)?.scan(&mut error, |error, record| match record {
+                Ok(mut record) => {
+                    renumber_entry(&mut record.entry);
+                    Some(record)
+                }
+                Err(e) => {
+                    **error = Some(e);
+                    None
+                }
+            });
+            self.write_run(records)?;
+            if let Some(e) = error {
+                return Err(e);
+            }
+            std::fs::remove_file(&run)?;
+        }
+        for entry in self.data.values_mut() {
+            renumber_entry(entry);
+        }
+        Ok(())
+    }
+
     pub fn load_bin_boundaries(&mut self, bin_boundaries: Vec<u32>) -> Result<(), Error> {
         self.bin_boundaries = bin_boundaries;
         Ok(())
     }
 
+    /// Has `finish` choose the prefix bin boundaries itself, rather than relying on ones from
+    /// `load_bin_boundaries`: phrases are taken in id order, and each bin closes once the next
+    /// phrase would push its records over roughly `max_bin_bytes` encoded. A single phrase bigger
+    /// than that gets a bin of its own. Boundaries that have been loaded take precedence.
+    pub fn set_auto_bins(&mut self, max_bin_bytes: usize) {
+        self.max_bin_bytes = Some(max_bin_bytes);
+    }
+
+    /// Records the options the store is meant to be opened with, plus a description of the data
+    /// it was built from. `finish` writes these to a metadata record alongside the build time;
+    /// without a call to this, no metadata record is written.
+    pub fn set_metadata(&mut self, options: GridStoreOptions, source: &str) {
+        self.options = Some(options);
+        self.source = source.to_owned();
+    }
+
+    /// Marks features as deleted, for building a store meant to be layered over another with
+    /// `GridStore::with_overlay`: entries for these ids in the stores beneath it are hidden.
+    /// Entries for them in this store are unaffected, so a feature can be deleted and re-added
+    /// with new data in the same overlay.
+    pub fn add_tombstones(&mut self, ids: &[u32]) {
+        self.tombstones.extend_from_slice(ids);
+    }
+
+    /// Chooses whether `finish` also writes a reverse index from each feature id to the phrases
+    /// that reference it, for `GridStore::phrases_for_feature`. It's off by default, since it
+    /// holds an extra copy of every key for each feature until `finish` is done.
+    pub fn set_feature_index(&mut self, enabled: bool) {
+        self.feature_index = enabled;
+    }
+
+    /// Chooses whether `finish` also writes an index from each tile to the features covering it,
+    /// for reverse lookups with `GridStore::features_at`. Like the feature index, it's off by
+    /// default and built up in memory during `finish`.
+    pub fn set_tile_index(&mut self, enabled: bool) {
+        self.tile_index = enabled;
+    }
+
+    /// Chooses whether `finish` writes a RocksDB directory (the default) or a single
+    /// memory-mappable file at the builder's path.
+    pub fn set_storage_format(&mut self, storage_format: StorageFormat) {
+        self.storage_format = storage_format;
+    }
+
     /// Writes data to disk.
-    pub fn finish(self) -> Result<(), Error> {
-        let mut opts = Options::default();
-        opts.set_disable_auto_compactions(true);
-        opts.create_if_missing(true);
+    pub fn finish(self) -> Result<BuildReport, Error> {
+        self.finish_with_progress(|_| {})
+    }
+
+    /// Writes data to disk like `finish`, calling `progress` as each batch of records goes
+    /// through each phase of the build.
+    pub fn finish_with_progress<F: FnMut(&BuildProgress)>(
+        mut self,
+        progress: F,
+    ) -> Result<BuildReport, Error> {
+        if !self.replaces_store {
+            return self.write_to_path(progress);
+        }
+
+        // build the replacement next to the old store, so that moving it into place is cheap
+        let store_path = self.path.clone();
+        let parent = match store_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let staging = tempfile::Builder::new().prefix(".gridstore").tempdir_in(parent)?;
+        self.path = staging.path().join("store");
+        let staged_path = self.path.clone();
+        let report = self.write_to_path(progress)?;
+        if store_path.is_dir() {
+            // a directory can't be renamed over another, so the old one is moved aside into the
+            // staging directory first, to be removed along with it. Between the two renames
+            // there's briefly nothing at the path, but never a partly written store, and if the
+            // second rename fails the old store is put back.
+            let old_path = staging.path().join("old");
+            fs::rename(&store_path, &old_path)?;
+            if let Err(e) = fs::rename(&staged_path, &store_path) {
+                fs::rename(&old_path, &store_path)?;
+                return Err(e.into());
+            }
+        } else {
+            fs::rename(&staged_path, &store_path)?;
+        }
+        Ok(report)
+    }
+
+    fn write_to_path<F: FnMut(&BuildProgress)>(
+        self,
+        mut progress: F,
+    ) -> Result<BuildReport, Error> {
+        let compacting = |report: &BuildReport| BuildProgress {
+            phase: BuildPhase::Compacting,
+            records: report.keys + report.prefix_bins,
+        };
+        match self.storage_format {
+            StorageFormat::RocksDB => {
+                let sst_options = RocksDBBulkWriter::sst_options();
+                let mut writer = RocksDBBulkWriter::create(&self.path, &sst_options)?;
+                let report = self.finish_to_with_progress(&mut writer, &mut progress)?;
+                progress(&compacting(&report));
+                writer.finish()?;
+                Ok(report)
+            }
+            StorageFormat::SingleFile => {
+                let mut writer = MmapStorageWriter::create(&self.path)?;
+                let report = self.finish_to_with_progress(&mut writer, &mut progress)?;
+                progress(&compacting(&report));
+                writer.finish()?;
+                Ok(report)
+            }
+        }
+    }
+
+    /// Writes data to an arbitrary storage backend, such as a `MemoryStorage`.
+    pub fn finish_to<W: GridStorageWriter>(self, writer: &mut W) -> Result<BuildReport, Error> {
+        self.finish_to_with_progress(writer, |_| {})
+    }
+
+    /// `finish_to`, with progress events as in `finish_with_progress`. Since the writer is left
+    /// to the caller to finish, there's no compacting phase.
+    pub fn finish_to_with_progress<W: GridStorageWriter, F: FnMut(&BuildProgress)>(
+        self,
+        writer: &mut W,
+        progress: F,
+    ) -> Result<BuildReport, Error> {
+        let features = self.format_features;
+        if let Some(options) = &self.options {
+            if options.zoom > features.max_zoom() {
+                return Err(BuildError::UnsupportedZoom {
+                    zoom: options.zoom,
+                    max_supported: features.max_zoom(),
+                }
+                .into());
+            }
+        }
+        let mut writer = Tally::new(writer, progress, &features);
+        let mut sources: Vec<RecordSource> = Vec::with_capacity(self.runs.len() + 1);
+        for run in self.runs.iter() {
+            sources.push(Box::new(RunReader::open(run)?));
+        }
+        let replacing = self.replacing;
+        sources.push(Box::new(self.data.into_iter().map(move |(key, entry)| {
+            Ok(RunRecord { replaces: replacing.contains(&key), key, entry })
+        })));
+        let mut merged = RunMerge::new(sources);
 
-        let db = DB::open(&opts, &self.path)?;
         let mut db_key: Vec<u8> = Vec::with_capacity(MAX_KEY_LENGTH);
+        let mut feature_index: Option<FeatureIndex> =
+            if self.feature_index { Some(FeatureIndex::new()) } else { None };
+        let mut tile_index: Option<TileIndex> =
+            if self.tile_index { Some(TileIndex::new()) } else { None };
+
+        // boundaries are only worked out automatically if none have been loaded
+        let max_bin_bytes = if self.bin_boundaries.is_empty() { self.max_bin_bytes } else { None };
+        let mut auto_boundaries: Vec<u32> = Vec::new();
+        let mut bin_bytes = 0;
+        let mut last_phrase_id = None;
 
         let mut bin_seq = self.bin_boundaries.iter().cloned().peekable();
-        let mut current_bin = None;
+        let mut loaded_bin = None;
         let mut next_boundary = 0u32;
-        let grouped = somewhat_eager_groupby(self.data.into_iter(), |(key, _value)| {
+        let mut bin_for = |key: &GridKey, value: &BuilderEntry| {
+            if let Some(max_bin_bytes) = max_bin_bytes {
+                // the first bin starts at 0 so that every phrase falls into one, and a bin is
+                // closed once the next phrase would take it over the limit
+                let bytes = approx_encoded_bytes(value);
+                if last_phrase_id.is_none() {
+                    auto_boundaries.push(0);
+                } else if last_phrase_id != Some(key.phrase_id) && bin_bytes + bytes > max_bin_bytes
+                {
+                    auto_boundaries.push(key.phrase_id);
+                    bin_bytes = 0;
+                }
+                bin_bytes += bytes;
+                last_phrase_id = Some(key.phrase_id);
+                return auto_boundaries.last().cloned();
+            }
+
             while key.phrase_id >= next_boundary {
-                current_bin = bin_seq.next();
+                loaded_bin = bin_seq.next();
                 next_boundary = *(bin_seq.peek().unwrap_or(&std::u32::MAX));
             }
+            loaded_bin
+        };
 
-            current_bin
-        });
+        // Records are streamed through one at a time: each goes into the batch waiting to be
+        // encoded and, if it's in a bin, is copied into the bin's per-lang_set unions, so that
+        // besides any secondary indexes, only the batch and the bin being built are held at once.
+        let memory_budget = self.memory_budget;
+        let mut current_bin: Option<u32> = None;
+        let mut lang_set_map: HashMap<u128, BuilderEntry> = HashMap::new();
+        let mut held_bin_bytes = 0;
+        let mut batch: Vec<(GridKey, BuilderEntry)> = Vec::new();
+        let mut batch_bytes = 0;
+        for (grid_key, value) in merged.by_ref() {
+            let bin = bin_for(&grid_key, &value);
+            let record_bytes = APPROX_KEY_BYTES + count_ids(&value) * APPROX_ID_BYTES;
 
-        for (group_id, group_value) in grouped {
-            let mut lang_set_map: HashMap<u128, BuilderEntry> = HashMap::new();
+            // batches only ever break between phrases, so that sorting each one puts the records
+            // in key order even though lang sets don't encode in numeric order
+            let new_phrase = batch.last().map(|(key, _)| key.phrase_id) != Some(grid_key.phrase_id);
+            let over_budget =
+                memory_budget.filter(|budget| batch_bytes + record_bytes > *budget).is_some();
+            if new_phrase && !batch.is_empty() && (batch.len() >= ENCODE_BATCH_SIZE || over_budget)
+            {
+                writer.grouped(batch.len());
+                writer.write_encoded(
+                    TypeMarker::SinglePhrase,
+                    std::mem::take(&mut batch),
+                    &features,
+                )?;
+                batch_bytes = 0;
+            }
 
-            for (grid_key, value) in group_value.into_iter() {
-                // figure out the key
-                db_key.clear();
-                grid_key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
-
-                let mut grouped_entry =
-                    lang_set_map.entry(grid_key.lang_set).or_insert_with(|| BuilderEntry::new());
-                copy_entries(&value, &mut grouped_entry);
-                // figure out the value
-                let db_data = get_encoded_value(value)?;
-                db.put(&db_key, &db_data)?;
+            if bin != current_bin {
+                if let Some(group_id) = current_bin {
+                    writer.write_bins(group_id, std::mem::take(&mut lang_set_map), &features)?;
+                }
+                held_bin_bytes = 0;
+                current_bin = bin;
+            }
+
+            if let Some(feature_index) = feature_index.as_mut() {
+                let id_comps = value.values().flat_map(|coords| coords.values()).flatten();
+                index_ids(feature_index, &grid_key, id_comps.cloned());
             }
-            if let Some(group_id) = group_id {
-                for (lang_set, builder_entry) in lang_set_map.into_iter() {
-                    db_key.clear();
-                    let group_key = GridKey { phrase_id: group_id, lang_set };
-                    group_key.write_to(TypeMarker::PrefixBin, &mut db_key)?;
-                    let grouped_db_data = get_encoded_value(builder_entry)?;
-                    db.put(&db_key, &grouped_db_data)?;
+            if let Some(tile_index) = tile_index.as_mut() {
+                for (tile, id_comps) in value.values().flatten() {
+                    index_tile(tile_index, *tile, id_comps.iter().cloned());
                 }
             }
+            if bin.is_some() {
+                let grouped_entry = lang_set_map.entry(grid_key.lang_set).or_insert_with(|| {
+                    held_bin_bytes += APPROX_KEY_BYTES;
+                    BuilderEntry::new()
+                });
+                copy_entries(&value, grouped_entry);
+                held_bin_bytes += record_bytes - APPROX_KEY_BYTES;
+            }
+
+            batch.push((grid_key, value));
+            batch_bytes += record_bytes;
+            writer.held(batch_bytes + held_bin_bytes);
+        }
+        if !batch.is_empty() {
+            writer.grouped(batch.len());
+            writer.write_encoded(TypeMarker::SinglePhrase, batch, &features)?;
+        }
+        if let Some(group_id) = current_bin {
+            writer.write_bins(group_id, lang_set_map, &features)?;
+        }
+
+        merged.finish()?;
+
+        if let Some(feature_index) = feature_index {
+            for (id, keys) in feature_index {
+                db_key.clear();
+                feature_index_key(id, &mut db_key);
+                writer.put(&db_key, &encode_phrase_list(&keys))?;
+            }
+            writer.put(FEATURE_INDEX_KEY, &[])?;
+        }
+        if let Some(tile_index) = tile_index {
+            for (tile, mut ids) in tile_index {
+                ids.sort();
+                ids.dedup();
+                db_key.clear();
+                tile_index_key(tile, features.wide_coords, &mut db_key);
+                writer.put(&db_key, &encode_id_list(&ids))?;
+            }
+            writer.put(TILE_INDEX_KEY, &[])?;
         }
 
         // bake the prefix boundaries
-        let mut encoded_boundaries: Vec<u8> = Vec::with_capacity(self.bin_boundaries.len() * 4);
-        for boundary in self.bin_boundaries {
+        let bin_boundaries =
+            if max_bin_bytes.is_some() { auto_boundaries } else { self.bin_boundaries };
+        let mut encoded_boundaries: Vec<u8> = Vec::with_capacity(bin_boundaries.len() * 4);
+        for boundary in bin_boundaries {
             encoded_boundaries.extend_from_slice(&boundary.to_le_bytes());
         }
-        db.put("~BOUNDS", &encoded_boundaries)?;
+        writer.put(b"~BOUNDS", &encoded_boundaries)?;
 
-        db.compact_range(None::<&[u8]>, None::<&[u8]>);
-        drop(db);
-        Ok(())
+        if !self.tombstones.is_empty() {
+            let mut tombstones = self.tombstones;
+            tombstones.sort();
+            tombstones.dedup();
+            let mut encoded_tombstones: Vec<u8> = Vec::with_capacity(tombstones.len() * 4);
+            for id in tombstones {
+                encoded_tombstones.extend_from_slice(&id.to_le_bytes());
+            }
+            writer.put(TOMBSTONES_KEY, &encoded_tombstones)?;
+        }
+        write_format_version(&mut writer, &features)?;
+
+        if let Some(options) = self.options {
+            GridStoreMetadata::new(options, &self.source).write_to(&mut writer)?;
+        }
+        Ok(writer.finish())
     }
 }
 
@@ -250,6 +1182,7 @@ fn extend_entry_test() {
     extend_entries(
         &mut entry,
         vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 7, source_phrase_hash: 2 }],
+        false,
     );
 
     // relev 3 (0011) with score 7 (0111) -> 55
@@ -339,17 +1272,368 @@ fn compact_append_test() {
         )
         .expect("Unable to insert record");
 
-    builder.compact_append(&key, 1., 1, 2, 0, &[(0, 0)]);
+    builder.compact_append(&key, 1., 1, 2, 0, &[(0, 0)]).unwrap();
     let entry = builder.data.get(&key);
     assert_ne!(entry, None);
     assert_eq!(entry.unwrap().len(), 1);
     builder.finish().unwrap();
 }
 
+#[test]
+fn validation_test() {
+    let key = GridKey { phrase_id: 1, lang_set: 1 };
+    let entry =
+        |id, x, relev, score| GridEntry { id, x, y: 1, relev, score, source_phrase_hash: 0 };
+    let options = GridStoreOptions { zoom: 6, ..GridStoreOptions::default() };
+
+    let mut builder = GridStoreBuilder::new_in_memory();
+    builder.set_metadata(options.clone(), "test");
+    assert!(builder.insert(&key, vec![entry(1, 1, 0.7, 1)]).is_err(), "relev 0.7 isn't a level");
+    assert!(builder.append(&key, vec![entry(1, 1, 1., 16)]).is_err(), "score needs 5 bits");
+    assert!(builder.append(&key, vec![entry(1 << 24, 1, 1., 1)]).is_err(), "id needs 25 bits");
+    assert!(builder.compact_append(&key, 1., 1, 1, 0, &[(1, 1), (64, 1)]).is_err());
+    assert!(builder.data.is_empty(), "nothing is added from a rejected call");
+    builder.insert(&key, vec![entry(1, 63, 0.4, 15)]).unwrap();
+
+    let mut lossy = GridStoreBuilder::new_in_memory();
+    lossy.set_metadata(options, "test");
+    lossy.set_lossy(true);
+    lossy.insert(&key, vec![entry(1, 1, 0.75, 16), entry((1 << 24) | 2, 1, 1., 1)]).unwrap();
+    lossy.append(&key, vec![entry(4, 2, 0.1, 1), entry(5, 3, 1.3, 1)]).unwrap();
+    lossy.compact_append(&key, 1., 1, 3, 0, &[(64, 1)]).unwrap();
+    assert_eq!(
+        *lossy.lossy_report(),
+        LossyReport {
+            relevs_rounded: 3,
+            scores_clamped: 1,
+            ids_truncated: 1,
+            coords_out_of_range: 1
+        }
+    );
+    let entry = lossy.data.get(&key).unwrap();
+    assert_eq!(
+        entry.get(&((2 << 4) | 15)).unwrap().values().next().unwrap().to_vec(),
+        vec![1 << 8],
+        "0.75 is stored as the nearest level, 0.8"
+    );
+    assert_eq!(
+        entry.get(&1).unwrap().values().next().unwrap().to_vec(),
+        vec![4 << 8],
+        "relevs below 0.4 are clamped to it"
+    );
+    assert_eq!(
+        entry.get(&((3 << 4) | 1)).unwrap().get(&interleave_morton(3, 1)).unwrap().to_vec(),
+        vec![5 << 8],
+        "and ones above 1 to 1"
+    );
+    assert_eq!(
+        entry.get(&((3 << 4) | 1)).unwrap().get(&interleave_morton(1, 1)).unwrap().to_vec(),
+        vec![2 << 8]
+    );
+}
+
+#[test]
+fn spill_test() {
+    use crate::gridstore::storage::{GridStorage, MemoryStorage};
+
+    let entry = |id, x, relev| GridEntry { id, x, y: x, relev, score: 1, source_phrase_hash: 0 };
+    let key = |phrase_id, lang_set| GridKey { phrase_id, lang_set };
+    let build = |builder: &mut GridStoreBuilder| {
+        builder.insert(&key(0, 1), vec![entry(1, 1, 1.)]).unwrap();
+        builder.append(&key(0, 1), vec![entry(2, 2, 1.)]).unwrap();
+        builder.insert(&key(2, 1), vec![entry(3, 3, 0.8)]).unwrap();
+        builder.compact_append(&key(1, 2), 1., 2, 4, 0, &[(4, 4), (5, 5)]).unwrap();
+        // replaces everything for the key, including what's already been spilled
+        builder.insert(&key(0, 1), vec![entry(5, 1, 1.)]).unwrap();
+        builder.append(&key(2, 1), vec![entry(6, 6, 0.8)]).unwrap();
+        builder.compact_append(&key(1, 2), 1., 2, 7, 0, &[(4, 4)]).unwrap();
+        builder.renumber(&[3, 1, 0]).unwrap();
+        builder.append(&key(3, 1), vec![entry(8, 8, 1.)]).unwrap();
+        builder.load_bin_boundaries(vec![0, 2]).unwrap();
+        builder.set_feature_index(true);
+    };
+    let contents = |storage: &MemoryStorage| -> Vec<(Vec<u8>, Vec<u8>)> {
+        storage
+            .iter_from(&[])
+            .map(Result::unwrap)
+            .map(|(key, value)| (key.to_vec(), value.as_ref().to_vec()))
+            .collect()
+    };
+
+    let mut in_memory = GridStoreBuilder::new_in_memory();
+    build(&mut in_memory);
+    assert!(in_memory.runs.is_empty(), "nothing spills without a budget");
+    let mut expected = MemoryStorage::new();
+    in_memory.finish_to(&mut expected).unwrap();
+
+    let spill_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut spilling = GridStoreBuilder::new_in_memory();
+    spilling.set_spill_dir(spill_dir.path());
+    spilling.set_memory_budget(1);
+    build(&mut spilling);
+    assert!(spilling.runs.len() > 1, "every change spills with a tiny budget");
+    assert!(spilling.data.is_empty());
+    let mut actual = MemoryStorage::new();
+    spilling.finish_to(&mut actual).unwrap();
+
+    assert_eq!(contents(&actual), contents(&expected), "spilled build matches in-memory build");
+    assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0, "runs are cleaned up");
+
+    // two phrases renumbered onto the same id are caught even when they're in different runs
+    let mut colliding = GridStoreBuilder::new_in_memory();
+    colliding.set_memory_budget(1);
+    colliding.insert(&key(0, 1), vec![entry(1, 1, 1.)]).unwrap();
+    colliding.insert(&key(1, 1), vec![entry(2, 2, 1.)]).unwrap();
+    assert!(colliding.renumber(&[5, 5]).is_err());
+}
+
+#[test]
+fn finish_memory_budget_test() {
+    use crate::gridstore::storage::MemoryStorage;
+
+    let budget = 16 * 1024;
+    let build = |memory_budget: Option<usize>| {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        if let Some(memory_budget) = memory_budget {
+            builder.set_memory_budget(memory_budget);
+        }
+        for phrase_id in 0..500 {
+            let entries = (0..4)
+                .map(|i| GridEntry {
+                    id: phrase_id * 4 + i,
+                    x: i,
+                    y: phrase_id,
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                })
+                .collect();
+            builder.insert(&GridKey { phrase_id, lang_set: 1 }, entries).unwrap();
+        }
+        let mut storage = MemoryStorage::new();
+        let report = builder.finish_to(&mut storage).unwrap();
+        (report, storage.len())
+    };
+
+    // without bins, finish only ever holds a batch of records at a time
+    let (report, records) = build(Some(budget));
+    assert_eq!(report.keys, 500);
+    assert_eq!(records, 502, "every phrase, plus the bounds and format records");
+    assert!(report.peak_bytes > 0);
+    assert!(report.peak_bytes <= budget, "peak of {} bytes is within budget", report.peak_bytes);
+
+    let (report, _) = build(None);
+    assert!(report.peak_bytes > budget, "without a budget, batches can be bigger");
+}
+
+#[test]
+fn renumber_features_test() {
+    use crate::gridstore::storage::{GridStorage, MemoryStorage};
+
+    let entry =
+        |id, x, hash| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: hash };
+    let key = |phrase_id| GridKey { phrase_id, lang_set: 1 };
+    let build = |builder: &mut GridStoreBuilder, ids: [u32; 3]| {
+        builder.insert(&key(0), vec![entry(ids[0], 1, 0), entry(ids[1], 1, 3)]).unwrap();
+        builder.insert(&key(1), vec![entry(ids[2], 2, 7)]).unwrap();
+        builder.compact_append(&key(1), 0.8, 2, ids[0], 1, &[(3, 3)]).unwrap();
+        builder.set_feature_index(true);
+    };
+    let contents = |builder: GridStoreBuilder| -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+        storage
+            .iter_from(&[])
+            .map(Result::unwrap)
+            .map(|(key, value)| (key.to_vec(), value.as_ref().to_vec()))
+            .collect()
+    };
+
+    let mut expected = GridStoreBuilder::new_in_memory();
+    build(&mut expected, [12, 10, 11]);
+    let expected = contents(expected);
+    for budget in &[None, Some(1)] {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        if let Some(budget) = budget {
+            builder.set_memory_budget(*budget);
+        }
+        build(&mut builder, [0, 1, 2]);
+        builder.renumber_features(&[12, 10, 11]).unwrap();
+        assert_eq!(contents(builder), expected, "renumbered build (budget {:?})", budget);
+    }
+
+    let mut builder = GridStoreBuilder::new_in_memory();
+    build(&mut builder, [0, 1, 2]);
+    assert!(builder.renumber_features(&[12, 10]).is_err(), "id 2 is out of bounds");
+    builder.renumber_features(&[12, 10, 11]).unwrap();
+    assert_eq!(contents(builder), expected, "a failed renumbering changes nothing");
+    let mut builder = GridStoreBuilder::new_in_memory();
+    build(&mut builder, [0, 1, 2]);
+    assert!(builder.renumber_features(&[12, 10, 12]).is_err(), "two ids map to 12");
+    let mut builder = GridStoreBuilder::new_in_memory();
+    build(&mut builder, [0, 1, 2]);
+    assert!(builder.renumber_features(&[12, 10, MAX_ID + 1]).is_err(), "too big to encode");
+    let mut builder = GridStoreBuilder::new_in_memory();
+    build(&mut builder, [0, 1, 1]);
+    assert!(builder.renumber_features(&[12, 10, 12]).is_ok(), "unused ids can collide");
+}
+
+#[test]
+fn bulk_write_test() {
+    use crate::gridstore::storage::{GridStorage, MemoryStorage, RocksDBStorage};
+
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let entry = |id, x| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: 0 };
+    let build = |builder: &mut GridStoreBuilder| {
+        // lang sets 2 and 256 encode as [2] and [1, 0], the reverse of their numeric order
+        for phrase_id in 0..3 {
+            builder.insert(&GridKey { phrase_id, lang_set: 2 }, vec![entry(1, 1)]).unwrap();
+            builder.insert(&GridKey { phrase_id, lang_set: 256 }, vec![entry(2, 2)]).unwrap();
+        }
+        builder.load_bin_boundaries(vec![0, 2]).unwrap();
+        builder.set_feature_index(true);
+        builder.set_tile_index(true);
+    };
+
+    let mut expected = MemoryStorage::new();
+    let mut in_memory = GridStoreBuilder::new_in_memory();
+    build(&mut in_memory);
+    in_memory.finish_to(&mut expected).unwrap();
+
+    let mut bulk = GridStoreBuilder::new(directory.path()).unwrap();
+    build(&mut bulk);
+    bulk.finish().unwrap();
+    let actual = RocksDBStorage::open_read_only(directory.path()).unwrap();
+
+    let contents = |storage: &dyn GridStorage| -> Vec<(Vec<u8>, Vec<u8>)> {
+        storage
+            .iter_from(&[])
+            .map(Result::unwrap)
+            .map(|(key, value)| (key.to_vec(), value.as_ref().to_vec()))
+            .collect()
+    };
+    assert_eq!(contents(&actual), contents(&expected), "ingested files match in-memory build");
+    let staged = std::fs::read_dir(directory.path())
+        .unwrap()
+        .filter(|file| file.as_ref().unwrap().file_name().to_string_lossy().starts_with("sst"))
+        .count();
+    assert_eq!(staged, 0, "staged files are cleaned up");
+}
+
+#[test]
+fn build_report_test() {
+    use crate::gridstore::storage::{GridStorage, MemoryStorage};
+
+    let entry = |id, x| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: 0 };
+    let build = |builder: &mut GridStoreBuilder| {
+        // two coords with the same single id share an id list
+        builder
+            .insert(&GridKey { phrase_id: 0, lang_set: 1 }, vec![entry(1, 1), entry(1, 2)])
+            .unwrap();
+        builder
+            .insert(
+                &GridKey { phrase_id: 1, lang_set: 1 },
+                vec![entry(2, 3), entry(3, 3), entry(4, 3)],
+            )
+            .unwrap();
+        builder.load_bin_boundaries(vec![0]).unwrap();
+    };
+
+    let mut builder = GridStoreBuilder::new_in_memory();
+    build(&mut builder);
+    let mut storage = MemoryStorage::new();
+    let report = builder.finish_to(&mut storage).unwrap();
+    assert_eq!((report.keys, report.prefix_bins), (2, 1));
+    assert_eq!(report.deduplicated_id_lists, 2, "once in phrase 0 and once in its bin");
+    let stored: usize = storage
+        .iter_from(&[])
+        .map(Result::unwrap)
+        .map(|(key, value)| key.len() + value.as_ref().len())
+        .sum();
+    assert_eq!(report.bytes_written, stored as u64);
+    assert_eq!(report.largest_records.len(), 3);
+    assert_eq!(report.largest_records[0].marker, TypeMarker::PrefixBin, "the bin holds everything");
+    assert!(report.largest_records.windows(2).all(|pair| pair[0].bytes >= pair[1].bytes));
+
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+    build(&mut builder);
+    let mut events = Vec::new();
+    let on_disk = builder.finish_with_progress(|event| events.push(event.clone())).unwrap();
+    assert_eq!(on_disk, report);
+    for phase in &[BuildPhase::Grouping, BuildPhase::Encoding, BuildPhase::Writing] {
+        assert!(events.iter().any(|event| event.phase == *phase), "reports {:?}", phase);
+    }
+    assert_eq!(events.last(), Some(&BuildProgress { phase: BuildPhase::Compacting, records: 3 }));
+}
+
+#[test]
+fn from_store_test() {
+    let entry = |id, x| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: 0 };
+    let key = |phrase_id| GridKey { phrase_id, lang_set: 1 };
+    let ids = |store: &GridStore, phrase_id| -> Vec<u32> {
+        match store.get(&key(phrase_id)).unwrap() {
+            Some(entries) => entries.map(|entry| entry.unwrap().id).collect(),
+            None => Vec::new(),
+        }
+    };
+
+    for storage_format in &[StorageFormat::RocksDB, StorageFormat::SingleFile] {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let path = directory.path().join("store");
+        let mut builder = GridStoreBuilder::new(&path).unwrap();
+        builder.set_storage_format(*storage_format);
+        builder.insert(&key(1), vec![entry(1, 1)]).unwrap();
+        builder.insert(&key(2), vec![entry(2, 2)]).unwrap();
+        builder.load_bin_boundaries(vec![0, 2]).unwrap();
+        builder.set_feature_index(true);
+        builder.finish().unwrap();
+
+        let store = GridStore::new(&path).unwrap();
+        let mut builder = GridStoreBuilder::from_store(&store).unwrap();
+        drop(store);
+        builder.append(&key(1), vec![entry(3, 3)]).unwrap();
+        builder.insert(&key(2), vec![entry(4, 4)]).unwrap();
+        builder.compact_append(&key(3), 1., 1, 5, 0, &[(5, 5)]).unwrap();
+        builder.finish().unwrap();
+
+        let store = GridStore::new(&path).unwrap();
+        assert_eq!(ids(&store, 1), vec![3, 1], "appends merge with the existing records");
+        assert_eq!(ids(&store, 2), vec![4], "inserts replace them");
+        assert_eq!(ids(&store, 3), vec![5]);
+        assert_eq!(store.bin_boundaries, [0, 2].iter().cloned().collect());
+        assert_eq!(store.phrases_for_feature(3).unwrap(), vec![key(1)], "indexes are kept");
+        assert!(store.verify().unwrap().is_ok());
+        let entries = std::fs::read_dir(directory.path()).unwrap().count();
+        assert_eq!(entries, 1, "the staged store is moved into place");
+    }
+
+    let storage = crate::gridstore::storage::MemoryStorage::new();
+    let store = GridStore::from_storage(storage, 6, 0, 0., vec![], 0.).unwrap();
+    assert!(GridStoreBuilder::from_store(&store).is_err(), "there's no path to replace");
+}
+
 #[derive(Debug, Fail)]
-enum BuildError {
+pub enum BuildError {
     #[fail(display = "duplicate rename entry: {}", target_id)]
     DuplicateRenumberEntry { target_id: u32 },
     #[fail(display = "out of bounds: {}", tmp_id)]
     OutOfBoundsRenumberEntry { tmp_id: u32 },
+    #[fail(display = "unsupported relev {}; relevs must be 0.4, 0.6, 0.8 or 1.0", relev)]
+    UnsupportedRelev { relev: f64 },
+    #[fail(display = "score {} is out of range; scores must be at most 15", score)]
+    ScoreOutOfRange { score: u8 },
+    #[fail(display = "id {} is out of range; ids must be less than 2^24", id)]
+    IdOutOfRange { id: u32 },
+    #[fail(display = "coordinate ({}, {}) is outside zoom {}", x, y, zoom)]
+    CoordOutOfRange { x: u32, y: u32, zoom: u16 },
+    #[fail(
+        display = "zoom {} is past the deepest zoom the format supports, {}",
+        zoom, max_supported
+    )]
+    UnsupportedZoom { zoom: u16, max_supported: u16 },
+    #[fail(display = "format features have to be chosen before any data is added")]
+    FormatFeaturesAfterData,
+    #[fail(display = "a store has to have been opened from a path to be rebuilt in place")]
+    StoreWithoutPath,
 }
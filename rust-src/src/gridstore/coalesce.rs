@@ -43,7 +43,7 @@ pub fn coalesce<T: Borrow<GridStore> + Clone + Debug>(
             if max_relevance - context.relev >= 0.25 {
                 break;
             }
-            let inserted = sets.insert(context.entries[0].tmp_id.into());
+            let inserted = sets.insert(context.entries[0].tmp_id);
             if inserted {
                 out.push(context);
             }
@@ -66,7 +66,7 @@ fn grid_to_coalesce_entry<T: Borrow<GridStore> + Clone>(
         grid_entry: GridEntry { relev: relevance, ..grid.grid_entry },
         matches_language: grid.matches_language,
         idx: subquery.idx,
-        tmp_id: ((subquery.idx as u32) << 24) + grid.grid_entry.id,
+        tmp_id: (u64::from(subquery.idx) << 32) + u64::from(grid.grid_entry.id),
         mask: subquery.mask,
         distance: grid.distance,
         scoredist: grid.scoredist,
@@ -95,6 +95,7 @@ fn coalesce_single<T: Borrow<GridStore> + Clone>(
     let mut coalesced: HashMap<u32, CoalesceEntry> = HashMap::new();
 
     for grid in grids {
+        let grid = grid?;
         let coalesce_entry = grid_to_coalesce_entry(&grid, subquery, match_opts, 0);
 
         // If it's the same feature as the last one, but a lower scoredist don't add it
@@ -183,7 +184,7 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
 ) -> Result<Vec<CoalesceContext>, Error> {
     stack.sort_by_key(|subquery| (subquery.store.borrow().zoom, subquery.idx));
 
-    let mut coalesced: HashMap<(u16, u16, u16), Vec<CoalesceContext>> = HashMap::new();
+    let mut coalesced: HashMap<(u16, u32, u32), Vec<CoalesceContext>> = HashMap::new();
     let mut contexts: Vec<CoalesceContext> = Vec::new();
 
     let mut max_relevance: f64 = 0.;
@@ -191,7 +192,7 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
     let mut zoom_adjusted_match_options = match_opts.clone();
 
     for (i, subquery) in stack.iter().enumerate() {
-        let mut to_add_to_coalesced: HashMap<(u16, u16, u16), Vec<CoalesceContext>> =
+        let mut to_add_to_coalesced: HashMap<(u16, u32, u32), Vec<CoalesceContext>> =
             HashMap::new();
         let compatible_zooms: Vec<u16> = stack
             .iter()
@@ -218,6 +219,7 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
         )?;
 
         for grid in grids.take(MAX_GRIDS_PER_PHRASE) {
+            let grid = grid?;
             let coalesce_entry =
                 grid_to_coalesce_entry(&grid, subquery, &zoom_adjusted_match_options, 0);
 
@@ -230,7 +232,7 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
             // See which other zooms are compatible.
             // These should all be lower zooms, so "zoom out" by dividing by 2^(difference in zooms)
             for other_zoom in compatible_zooms.iter() {
-                let scale_factor: u16 = 1 << (subquery.store.borrow().zoom - *other_zoom);
+                let scale_factor: u32 = 1 << (subquery.store.borrow().zoom - *other_zoom);
                 let other_zxy = (
                     *other_zoom,
                     entries[0].grid_entry.x / scale_factor,
@@ -340,12 +342,12 @@ fn coalesce_multi<T: Borrow<GridStore> + Clone>(
 
 struct TreeCoalesceState {
     contexts: Vec<CoalesceContext>,
-    bush: KDBush<u16>,
+    bush: KDBush<u32>,
 }
 
 impl TreeCoalesceState {
     fn new(contexts: Vec<CoalesceContext>) -> TreeCoalesceState {
-        let mut builder: KDBushBuilder<u16> = KDBushBuilder::new();
+        let mut builder: KDBushBuilder<u32> = KDBushBuilder::new();
         for context in contexts.iter() {
             let point = [context.entries[0].grid_entry.x, context.entries[0].grid_entry.y];
             builder.add(&point);
@@ -659,14 +661,15 @@ pub fn tree_coalesce<T: Borrow<GridStore> + Clone + Debug + Send + Sync>(
                             MAX_GRIDS_PER_PHRASE,
                         )?
                         .take(MAX_GRIDS_PER_PHRASE)
-                        .filter(|grid| {
-                            unique_ids.insert((
+                        .filter(|grid| match grid {
+                            Ok(grid) => unique_ids.insert((
                                 grid.grid_entry.x,
                                 grid.grid_entry.y,
                                 grid.grid_entry.id,
-                            ))
+                            )),
+                            Err(_) => true,
                         })
-                        .collect();
+                        .collect::<Result<_, _>>()?;
                     Ok(KeyFetchResult::Multi((key_step.key_id, data)))
                 }
             })
@@ -702,7 +705,7 @@ pub fn tree_coalesce<T: Borrow<GridStore> + Clone + Debug + Send + Sync>(
 
                     let mut phrasematch_contexts: Vec<CoalesceContext> = Vec::new();
 
-                    let scale_factor: u16 = 1 << (subquery.store.borrow().zoom - step.prev_zoom);
+                    let scale_factor: u32 = 1 << (subquery.store.borrow().zoom - step.prev_zoom);
 
                     let mut state_contexts: Vec<CoalesceContext> = Vec::new();
 
@@ -862,7 +865,10 @@ pub fn tree_coalesce<T: Borrow<GridStore> + Clone + Debug + Send + Sync>(
     Ok(contexts.into_vec_desc())
 }
 
-fn tree_coalesce_single<T: Borrow<GridStore> + Clone, U: Iterator<Item = MatchEntry>>(
+fn tree_coalesce_single<
+    T: Borrow<GridStore> + Clone,
+    U: Iterator<Item = Result<MatchEntry, Error>>,
+>(
     subquery: &PhrasematchSubquery<T>,
     match_opts: &MatchOpts,
     grids: U,
@@ -880,6 +886,7 @@ fn tree_coalesce_single<T: Borrow<GridStore> + Clone, U: Iterator<Item = MatchEn
     let mut coalesced: HashMap<u32, CoalesceEntry> = HashMap::new();
 
     for grid in grids {
+        let grid = grid?;
         let coalesce_entry = grid_to_coalesce_entry(&grid, &subquery, match_opts, phrasematch_id);
 
         // If it's the same feature as the last one, but a lower scoredist don't add it
@@ -5,16 +5,33 @@ use crate::gridstore::spatial::adjust_bbox_zoom;
 use crate::gridstore::store::GridStore;
 
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use failure::Error;
+use failure::{format_err, Error};
 use fixedbitset::FixedBitSet;
 use min_max_heap::MinMaxHeap;
 use ordered_float::OrderedFloat;
 use serde::{Deserialize, Serialize, Serializer};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
 pub enum TypeMarker {
     SinglePhrase = 0,
     PrefixBin = 1,
+    /// Reverse index records, keyed by feature id rather than by `GridKey`
+    FeatureIndex = 2,
+    /// Secondary index records, keyed by morton-coded tile
+    TileIndex = 3,
+}
+
+impl TypeMarker {
+    /// The marker a database key starts with, if it has one; the special `~` keys don't.
+    pub fn from_key(db_key: &[u8]) -> Option<Self> {
+        match db_key.first() {
+            Some(0) => Some(TypeMarker::SinglePhrase),
+            Some(1) => Some(TypeMarker::PrefixBin),
+            Some(2) => Some(TypeMarker::FeatureIndex),
+            Some(3) => Some(TypeMarker::TileIndex),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
@@ -42,6 +59,27 @@ impl GridKey {
         }
         Ok(())
     }
+
+    /// Reads a key back out of its database form, ignoring the type marker.
+    pub fn read_from(db_key: &[u8]) -> Result<Self, Error> {
+        if db_key.len() < 5 || db_key.len() > MAX_KEY_LENGTH {
+            return Err(format_err!("malformed key of length {}", db_key.len()));
+        }
+        let phrase_id = (&db_key[1..]).read_u32::<BigEndian>()?;
+
+        let key_lang_partial = &db_key[5..];
+        let lang_set: u128 = if key_lang_partial.is_empty() {
+            // 0-length language array is the shorthand for "matches everything"
+            u128::MAX
+        } else {
+            let mut key_lang_full = [0u8; 16];
+            key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+
+            (&key_lang_full[..]).read_u128::<BigEndian>()?
+        };
+
+        Ok(GridKey { phrase_id, lang_set })
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, Ord, PartialEq, Eq, Clone)]
@@ -107,8 +145,8 @@ impl MatchKey {
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct MatchOpts {
-    pub bbox: Option<[u16; 4]>,
-    pub proximity: Option<[u16; 2]>,
+    pub bbox: Option<[u32; 4]>,
+    pub proximity: Option<[u32; 2]>,
     pub zoom: u16,
 }
 
@@ -155,21 +193,21 @@ impl MatchOpts {
         }
     }
 
-    pub fn augment_bbox(&self, nearby_only: bool, bounds: Option<[u16; 4]>) -> MatchOpts {
+    pub fn augment_bbox(&self, nearby_only: bool, bounds: Option<[u32; 4]>) -> MatchOpts {
         let mut augmented = self.clone();
 
         let nearby_buffer = if nearby_only {
             match augmented.proximity {
                 None => None,
                 Some(prox) => {
-                    let miles_per_tile = EARTH_CIRC_IN_MILES / ((1 << augmented.zoom) as f64);
-                    let padding = (NEARBY_RADIUS / miles_per_tile).ceil() as u16;
+                    let miles_per_tile = EARTH_CIRC_IN_MILES / ((1u64 << augmented.zoom) as f64);
+                    let padding = (NEARBY_RADIUS / miles_per_tile).ceil() as u32;
 
                     Some([
                         if prox[0] < padding { 0 } else { prox[0] - padding }, // prevent overflows because this is unsigned
                         if prox[1] < padding { 0 } else { prox[1] - padding }, // ditto
-                        prox[0] + padding,
-                        prox[1] + padding,
+                        prox[0].saturating_add(padding),
+                        prox[1].saturating_add(padding),
                     ])
                 }
             }
@@ -193,7 +231,7 @@ impl MatchOpts {
         augmented
     }
 
-    fn bbox_intersect(left: [u16; 4], right: [u16; 4]) -> [u16; 4] {
+    fn bbox_intersect(left: [u32; 4], right: [u32; 4]) -> [u32; 4] {
         [
             std::cmp::max(left[0], right[0]),
             std::cmp::max(left[1], right[1]),
@@ -208,7 +246,7 @@ mod tests {
     use super::*;
     use once_cell::sync::Lazy;
 
-    fn matchopts_proximity_generator(point: [u16; 2], zoom: u16) -> MatchOpts {
+    fn matchopts_proximity_generator(point: [u32; 2], zoom: u16) -> MatchOpts {
         MatchOpts { proximity: Some(point), zoom: zoom, ..MatchOpts::default() }
     }
 
@@ -260,7 +298,7 @@ mod tests {
         assert_eq!(proximity_in_3z, [51, 51], "4/6/6 zoomed in to zoom 7 should be 7/51/51");
     }
 
-    fn matchopts_bbox_generator(bbox: [u16; 4], zoom: u16) -> MatchOpts {
+    fn matchopts_bbox_generator(bbox: [u32; 4], zoom: u16) -> MatchOpts {
         MatchOpts { bbox: Some(bbox), zoom: zoom, ..MatchOpts::default() }
     }
 
@@ -344,6 +382,17 @@ mod tests {
             [3, 1, 4, 2],
             "Multi-tile parent zoomed in one zoom level includes all the higher-zoom tiles"
         );
+
+        // Zooms past 16 need more than 16 bits per coordinate
+        let zoomed_in_18 = zoomed_in_16.adjust_to_zoom(18);
+        assert_eq!(
+            zoomed_in_18.bbox.unwrap(),
+            [262080, 262064, 262143, 261719],
+            "zooming into the right most tile past zoom 16 does not overflow"
+        );
+        assert_eq!(zoomed_in_18.adjust_to_zoom(16), zoomed_in_16);
+        let prox_18 = matchopts_proximity_generator([65535, 0], 16).adjust_to_zoom(18);
+        assert_eq!(prox_18.proximity.unwrap(), [262141, 1]);
     }
 
     #[test]
@@ -415,6 +464,10 @@ mod tests {
 // leading (in a big-endian sense/most-significant sense) zero bytes for compactness
 pub const MAX_KEY_LENGTH: usize = 1 + (32 / 8) + (128 / 8);
 
+// the ids of features an overlay store deletes from the stores beneath it, as a sorted list of
+// little-endian u32s; like the other `~` keys, it sorts after every phrase key
+pub const TOMBSTONES_KEY: &[u8] = b"~TOMBSTONES";
+
 // The max number of contexts to return from Coalesce
 pub const MAX_CONTEXTS: usize = 40;
 
@@ -428,12 +481,15 @@ pub const MAX_INDEXES: usize = 256;
 
 #[derive(Serialize, Deserialize, Debug, PartialOrd, PartialEq, Clone)]
 pub struct GridEntry {
-    // these will be truncated to 4 bits apiece
+    // relev is stored as one of four levels (0.4, 0.6, 0.8 or 1.0) and score in 4 bits, unless
+    // the store has high precision, which keeps relev in 255ths and scores up to 255;
+    // GridStoreBuilder rejects anything else unless it's in lossy mode
     pub relev: f64,
     pub score: u8,
-    pub x: u16,
-    pub y: u16,
-    // this will be truncated to 24 bits
+    // these have to fit in 16 bits unless the store has wide coords
+    pub x: u32,
+    pub y: u32,
+    // this has to fit in 24 bits unless the store has wide ids
     pub id: u32,
     pub source_phrase_hash: u8,
 }
@@ -451,7 +507,9 @@ pub struct CoalesceEntry {
     pub grid_entry: GridEntry,
     pub matches_language: bool,
     pub idx: u16,
-    pub tmp_id: u32,
+    /// `idx << 32` plus the feature id, which tells apart same-numbered features from different
+    /// subqueries
+    pub tmp_id: u64,
     pub mask: u32,
     pub distance: f64,
     pub scoredist: f64,
@@ -467,7 +525,7 @@ pub struct CoalesceContext {
 
 impl CoalesceContext {
     #[inline(always)]
-    fn sort_key(&self) -> (OrderedFloat<f64>, OrderedFloat<f64>, Reverse<u16>, u16, u16, u32) {
+    fn sort_key(&self) -> (OrderedFloat<f64>, OrderedFloat<f64>, Reverse<u16>, u32, u32, u32) {
         (
             OrderedFloat(self.relev),
             OrderedFloat(self.entries[0].scoredist),
@@ -507,7 +565,7 @@ pub struct MatchKeyWithId {
     pub id: u32,
     #[serde(default)]
     pub phrase_length: usize,
-    pub bounds: Option<[u16; 4]>,
+    pub bounds: Option<[u32; 4]>,
 }
 
 impl Default for MatchKeyWithId {
@@ -620,6 +678,40 @@ pub fn relev_int_to_float(relev: u8) -> f64 {
     }
 }
 
+/// Relevs in the high-precision encoding are kept in 255ths, which hits each of the four original
+/// levels exactly. Relevs outside [0, 1] saturate to the nearest end.
+#[inline]
+pub fn relev_float_to_byte(relev: f64) -> u8 {
+    (relev * 255.).round() as u8
+}
+
+/// Set in every relev/score value packed with the high-precision encoding. The original encoding
+/// only ever packs values below 64, so each value says which encoding it's in.
+pub const HIGH_PRECISION_FLAG: u32 = 1 << 16;
+
+/// Packs a relev and score into the value a record's entries are grouped and sorted under: in the
+/// original encoding, a two-bit relev level and a four-bit score; in the high-precision one, the
+/// flag, then relev in 255ths, then the whole score byte. Either way, higher relevs pack higher,
+/// then higher scores.
+#[inline]
+pub fn pack_relev_score(relev: f64, score: u8, high_precision: bool) -> u32 {
+    if high_precision {
+        HIGH_PRECISION_FLAG | (u32::from(relev_float_to_byte(relev)) << 8) | u32::from(score)
+    } else {
+        u32::from((relev_float_to_int(relev) << 4) | score)
+    }
+}
+
+#[inline]
+pub fn unpack_relev_score(relev_score: u32) -> (f64, u8) {
+    if relev_score & HIGH_PRECISION_FLAG != 0 {
+        (f64::from((relev_score >> 8) as u8) / 255., relev_score as u8)
+    } else {
+        // the relev level is above the least significant four bits, which hold the score
+        (relev_int_to_float((relev_score >> 4) as u8), (relev_score & 15) as u8)
+    }
+}
+
 // the groupby in itertools doesn't take ownership of the thing it's grouping, instead returning
 // groups that reference an unowned buffer -- this is tricky for lifetime purposes if you want to
 // return an iterator based on a groupby. This version makes a slightly different tradeoff -- it
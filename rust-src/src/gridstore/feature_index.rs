@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use failure::{format_err, Error};
+
+use crate::gridstore::common::*;
+use crate::gridstore::store::{GridStore, StoreError};
+
+/// Present in every store built with a feature index, even one without any features in it.
+pub const FEATURE_INDEX_KEY: &[u8] = b"~FEATURE_INDEX";
+
+/// Each entry in a feature index record: a phrase id and a lang set, little-endian.
+const ENTRY_SIZE: usize = 4 + 16;
+
+/// Reverse index from feature ids to the keys of the phrases whose records mention them, built up
+/// alongside the phrase records
+pub type FeatureIndex = BTreeMap<u32, Vec<GridKey>>;
+
+pub fn feature_index_key(id: u32, db_key: &mut Vec<u8>) {
+    db_key.push(TypeMarker::FeatureIndex as u8);
+    db_key.extend_from_slice(&id.to_be_bytes());
+}
+
+/// Reads the feature id back out of a feature index key.
+pub fn read_feature_index_key(db_key: &[u8]) -> Result<u32, Error> {
+    match db_key.get(1..) {
+        Some(id) if id.len() == 4 => Ok(u32::from_be_bytes(id.try_into().unwrap())),
+        _ => Err(format_err!("malformed feature index key of length {}", db_key.len())),
+    }
+}
+
+pub fn encode_phrase_list(keys: &[GridKey]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(keys.len() * ENTRY_SIZE);
+    for key in keys {
+        encoded.extend_from_slice(&key.phrase_id.to_le_bytes());
+        encoded.extend_from_slice(&key.lang_set.to_le_bytes());
+    }
+    encoded
+}
+
+pub fn decode_phrase_list(encoded: &[u8]) -> Result<Vec<GridKey>, Error> {
+    let entries = encoded.chunks_exact(ENTRY_SIZE);
+    if !entries.remainder().is_empty() {
+        return Err(format_err!("feature index record of length {} is malformed", encoded.len()));
+    }
+    Ok(entries
+        .map(|entry| GridKey {
+            phrase_id: u32::from_le_bytes(entry[..4].try_into().unwrap()),
+            lang_set: u128::from_le_bytes(entry[4..].try_into().unwrap()),
+        })
+        .collect())
+}
+
+/// Adds every feature mentioned in a builder record to the index. Records have to be added in key
+/// order, so that each feature's list comes out sorted.
+pub fn index_ids<I: IntoIterator<Item = u64>>(
+    index: &mut FeatureIndex,
+    key: &GridKey,
+    id_comps: I,
+) {
+    for id_comp in id_comps {
+        let keys = index.entry((id_comp >> 8) as u32).or_default();
+        if keys.last() != Some(key) {
+            keys.push(key.clone());
+        }
+    }
+}
+
+impl GridStore {
+    /// Lists the keys of every phrase whose record includes the given feature, in key order. This
+    /// needs the store to have been built with `GridStoreBuilder::set_feature_index`; it's an
+    /// error to ask a store (or any of its overlays) without one.
+    pub fn phrases_for_feature(&self, id: u32) -> Result<Vec<GridKey>, Error> {
+        let layers = self.layers();
+        let mut keys = Vec::new();
+        for (i, layer) in layers.iter().enumerate() {
+            let layer_keys = layer.layer_phrases_for_feature(id)?;
+            if !layers[i + 1..].iter().any(|above| above.tombstones.contains(&id)) {
+                keys.extend(layer_keys);
+            }
+        }
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+
+    pub(crate) fn layer_phrases_for_feature(&self, id: u32) -> Result<Vec<GridKey>, Error> {
+        let mut db_key = Vec::with_capacity(5);
+        feature_index_key(id, &mut db_key);
+        if let Some(value) = self.storage.get(&db_key)? {
+            return decode_phrase_list(value.as_ref());
+        }
+
+        // a missing record is only an answer if there's an index to be missing from
+        if self.storage.get(FEATURE_INDEX_KEY)?.is_some() {
+            Ok(Vec::new())
+        } else {
+            Err(StoreError::NoFeatureIndex.into())
+        }
+    }
+}
@@ -1,7 +1,85 @@
 use std::convert::TryInto;
 use std::marker::PhantomData;
 
+use failure::Fail;
 use integer_encoding::VarInt;
+use itertools::Either;
+
+use crate::gridstore::metadata::FormatFeatures;
+
+/// The ways in which an encoded record can turn out to be malformed
+#[derive(Debug, Fail, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    #[fail(
+        display = "record truncated: needed {} bytes at offset {}, but the record is {} bytes long",
+        needed, offset, record_len
+    )]
+    OutOfBounds { offset: usize, needed: usize, record_len: usize },
+    #[fail(display = "malformed varint at offset {}", offset)]
+    MalformedVarint { offset: usize },
+    #[fail(display = "invalid record size {} at offset {}", size, offset)]
+    InvalidRecordSize { offset: usize, size: usize },
+    #[fail(
+        display = "delta at offset {} runs past the range of the values it's applied to",
+        offset
+    )]
+    InvalidDelta { offset: usize },
+    #[fail(
+        display = "skip table of the packed vector at offset {} doesn't match its blocks",
+        offset
+    )]
+    InvalidSkipTable { offset: usize },
+}
+
+#[inline]
+fn check_bounds(data: &[u8], offset: usize, needed: usize) -> Result<(), DecodeError> {
+    match offset.checked_add(needed) {
+        Some(end) if end <= data.len() => Ok(()),
+        _ => Err(DecodeError::OutOfBounds { offset, needed, record_len: data.len() }),
+    }
+}
+
+/// Reads a varint, checking that it ends within the buffer and fits in a u32
+#[inline]
+fn read_var_u32(data: &[u8], offset: usize) -> Result<(u32, usize), DecodeError> {
+    check_bounds(data, offset, 1)?;
+    let bytes = &data[offset..];
+    // a u32 takes at most five bytes
+    let last = bytes
+        .iter()
+        .take(5)
+        .position(|b| b & 0x80 == 0)
+        .ok_or(DecodeError::MalformedVarint { offset })?;
+    let (value, len) = u64::decode_var(&bytes[..=last]);
+    if value > u64::from(u32::MAX) {
+        return Err(DecodeError::MalformedVarint { offset });
+    }
+    Ok((value as u32, len))
+}
+
+/// Reads a varint of up to 64 bits, checking that it ends within the buffer
+#[inline]
+fn read_var_u64(data: &[u8], offset: usize) -> Result<(u64, usize), DecodeError> {
+    check_bounds(data, offset, 1)?;
+    let bytes = &data[offset..];
+    let last = bytes
+        .iter()
+        .take(10)
+        .position(|b| b & 0x80 == 0)
+        .ok_or(DecodeError::MalformedVarint { offset })?;
+    // the tenth byte only has room for the top bit
+    if last == 9 && bytes[9] > 1 {
+        return Err(DecodeError::MalformedVarint { offset });
+    }
+    Ok(u64::decode_var(&bytes[..=last]))
+}
+
+#[inline]
+fn push_var(buffer: &mut Vec<u8>, value: u64) {
+    let mut buf = [0u8; 10];
+    let len = value.encode_var(&mut buf);
+    buffer.extend_from_slice(&buf[..len]);
+}
 
 #[derive(Copy, Clone)]
 pub struct VarScalarOffset<T: VarEncodable> {
@@ -50,6 +128,11 @@ impl<T: FixedEncodable> FixedVecOffset<T> {
         Self { addr, phantom: PhantomData }
     }
 
+    /// The same vector, read as holding a different type
+    pub fn cast<U: FixedEncodable>(self) -> FixedVecOffset<U> {
+        FixedVecOffset::new(self.addr)
+    }
+
     #[allow(dead_code)]
     fn from_fixed_pointer(data: &[u8], offset: usize) -> Self {
         let ptr = u32::from_le_bytes(data[offset..(offset + 4)].try_into().unwrap());
@@ -57,9 +140,9 @@ impl<T: FixedEncodable> FixedVecOffset<T> {
     }
 
     #[allow(dead_code)]
-    fn from_var_pointer(data: &[u8], offset: usize) -> (Self, usize) {
-        let (ptr, len_len) = u32::decode_var(&data[offset..]);
-        (Self::new(ptr as usize), len_len)
+    fn from_var_pointer(data: &[u8], offset: usize) -> Result<(Self, usize), DecodeError> {
+        let (ptr, len_len) = read_var_u32(data, offset)?;
+        Ok((Self::new(ptr as usize), len_len))
     }
 }
 
@@ -80,9 +163,9 @@ impl<T: VarEncodable> VarVecOffset<T> {
     }
 
     #[allow(dead_code)]
-    fn from_var_pointer(data: &[u8], offset: usize) -> (Self, usize) {
-        let (ptr, len_len) = u32::decode_var(&data[offset..]);
-        (Self::new(ptr as usize), len_len)
+    fn from_var_pointer(data: &[u8], offset: usize) -> Result<(Self, usize), DecodeError> {
+        let (ptr, len_len) = read_var_u32(data, offset)?;
+        Ok((Self::new(ptr as usize), len_len))
     }
 }
 
@@ -103,28 +186,39 @@ impl<T: UniformEncodable> UniformVecOffset<T> {
         Self::new(ptr as usize)
     }
 
-    fn from_var_pointer(data: &[u8], offset: usize) -> (Self, usize) {
-        let (ptr, len_len) = u32::decode_var(&data[offset..]);
-        (Self::new(ptr as usize), len_len)
+    fn from_var_pointer(data: &[u8], offset: usize) -> Result<(Self, usize), DecodeError> {
+        let (ptr, len_len) = read_var_u32(data, offset)?;
+        Ok((Self::new(ptr as usize), len_len))
     }
 }
 
 pub trait VarEncodable: Sized {
     fn write_to(&self, buffer: &mut Vec<u8>) -> usize;
-    fn read_from(buffer: &[u8], offset: VarScalarOffset<Self>) -> (Self, usize);
+    fn read_from(
+        buffer: &[u8],
+        offset: VarScalarOffset<Self>,
+    ) -> Result<(Self, usize), DecodeError>;
 }
 
 pub trait FixedEncodable: Sized {
     const SIZE: usize;
     fn write_fixed_to(&self, buffer: &mut Vec<u8>) -> ();
+    /// Callers are responsible for checking that `SIZE` bytes are available at `offset`
     fn read_fixed_from(buffer: &[u8], offset: FixedScalarOffset<Self>) -> Self;
 }
 
 pub trait UniformEncodable: Sized {
+    const MIN_SIZE: usize;
     const MAX_SIZE: usize;
     fn write_with_size_to(&self, size: usize, buffer: &mut Vec<u8>) -> ();
+    /// Callers are responsible for checking that `size` is between `MIN_SIZE` and `MAX_SIZE`, and
+    /// that `size` bytes are available at `offset`
     fn read_with_size_from(buffer: &[u8], size: usize, offset: UniformScalarOffset<Self>) -> Self;
     fn get_min_size(&self) -> usize;
+    /// The record size a vector of `items` gets written with; every item has to be readable at it
+    fn get_uniform_size(items: &[Self]) -> usize {
+        items.iter().map(|obj| obj.get_min_size()).max().unwrap_or(255)
+    }
 }
 
 pub struct Writer {
@@ -182,12 +276,87 @@ impl Writer {
         FixedVecOffset::new(loc)
     }
 
+    /// Writes a coord's packed ids, as u32s or, for stores with wide ids, as `WideId`s. Narrow
+    /// ids are expected to fit in a u32. With packed vectors, they're delta-encoded instead; see
+    /// `PackedIdList`.
+    pub fn write_id_list(&mut self, ids: &[u64], features: &FormatFeatures) -> FixedVecOffset<u32> {
+        if features.packed_vectors {
+            let loc = self.data.len();
+            push_var(&mut self.data, ids.len() as u64);
+            let mut prev = None;
+            for &id_comp in ids {
+                debug_assert!(
+                    prev.filter(|prev| *prev < id_comp).is_none(),
+                    "Expected descending sort"
+                );
+                push_var(&mut self.data, prev.map_or(id_comp, |prev| prev - id_comp));
+                prev = Some(id_comp);
+            }
+            FixedVecOffset::new(loc)
+        } else if features.wide_ids {
+            let ids: Vec<WideId> = ids.iter().map(|id_comp| WideId(*id_comp)).collect();
+            self.write_fixed_vec(&ids).cast()
+        } else {
+            let ids: Vec<u32> = ids.iter().map(|id_comp| *id_comp as u32).collect();
+            self.write_fixed_vec(&ids)
+        }
+    }
+
+    /// Writes a relev/score group's coords, in a `UniformVec` or, with packed vectors, a
+    /// `PackedCoordVec`. Either way they're expected in descending order.
+    pub fn write_coord_vec(
+        &mut self,
+        coords: &[Coord],
+        features: &FormatFeatures,
+    ) -> UniformVecOffset<Coord> {
+        if !features.packed_vectors {
+            return self.write_uniform_vec(coords);
+        }
+        let mut blocks = Vec::new();
+        let mut block_starts = Vec::new();
+        let mut prev = (0u64, 0i64);
+        for (i, coord) in coords.iter().enumerate() {
+            let ptr = coord.ids.addr as i64;
+            if i % PACKED_BLOCK_LEN == 0 {
+                block_starts.push(blocks.len());
+                push_var(&mut blocks, coord.coord);
+                push_var(&mut blocks, ptr as u64);
+            } else {
+                debug_assert!(prev.0 >= coord.coord, "Expected descending sort");
+                push_var(&mut blocks, prev.0 - coord.coord);
+                // consecutive coords' id lists are usually written one after another, so the
+                // pointer is zigzag-encoded as a difference from the last one
+                let mut buf = [0u8; 10];
+                let len = (ptr - prev.1).encode_var(&mut buf);
+                blocks.extend_from_slice(&buf[..len]);
+            }
+            prev = (coord.coord, ptr);
+        }
+
+        let loc = self.data.len();
+        push_var(&mut self.data, coords.len() as u64);
+        // the first block always starts right after the skip table, so it isn't listed
+        let skips = block_starts.get(1..).unwrap_or(&[]);
+        let skip_width = skips.last().map_or(1, |last| match last {
+            0..=255 => 1,
+            256..=65535 => 2,
+            65536..=16777215 => 3,
+            _ => 4,
+        });
+        self.data.push(skip_width as u8);
+        for skip in skips {
+            self.data.extend_from_slice(&(*skip as u32).to_le_bytes()[..skip_width]);
+        }
+        self.data.extend_from_slice(&blocks);
+        UniformVecOffset::new(loc)
+    }
+
     pub fn write_uniform_vec<T: UniformEncodable>(&mut self, s: &[T]) -> UniformVecOffset<T> {
         let loc = self.data.len();
         let mut len_buf = [0u8; 8];
         let len_len = (s.len() as u32).encode_var(&mut len_buf);
         self.data.extend_from_slice(&len_buf[..len_len]);
-        let rec_size: usize = s.iter().map(|obj| obj.get_min_size()).max().unwrap_or(255);
+        let rec_size: usize = T::get_uniform_size(s);
         debug_assert!(rec_size <= 255);
         self.data.push(rec_size as u8);
         for item in s {
@@ -210,93 +379,148 @@ impl<U: AsRef<[u8]>> Reader<U> {
         Reader { data }
     }
 
-    pub fn read_fixed_scalar<'a, T: FixedEncodable>(&'a self, offset: FixedScalarOffset<T>) -> T {
-        T::read_fixed_from(self.data.as_ref(), offset)
+    pub fn read_fixed_scalar<T: FixedEncodable>(
+        &self,
+        offset: FixedScalarOffset<T>,
+    ) -> Result<T, DecodeError> {
+        check_bounds(self.data.as_ref(), offset.addr, T::SIZE)?;
+        Ok(T::read_fixed_from(self.data.as_ref(), offset))
     }
 
     #[allow(dead_code)]
-    pub fn read_var_scalar<'a, T: VarEncodable>(
-        &'a self,
+    pub fn read_var_scalar<T: VarEncodable>(
+        &self,
         offset: VarScalarOffset<T>,
-    ) -> (T, usize) {
+    ) -> Result<(T, usize), DecodeError> {
         T::read_from(self.data.as_ref(), offset)
     }
 
     #[allow(dead_code)]
-    pub fn read_uniform_scalar<'a, T: UniformEncodable>(
-        &'a self,
+    pub fn read_uniform_scalar<T: UniformEncodable>(
+        &self,
         size: usize,
         offset: UniformScalarOffset<T>,
-    ) -> T {
-        T::read_with_size_from(self.data.as_ref(), size, offset)
+    ) -> Result<T, DecodeError> {
+        if size < T::MIN_SIZE || size > T::MAX_SIZE {
+            return Err(DecodeError::InvalidRecordSize { offset: offset.addr, size });
+        }
+        check_bounds(self.data.as_ref(), offset.addr, size)?;
+        Ok(T::read_with_size_from(self.data.as_ref(), size, offset))
     }
 
     #[allow(dead_code)]
-    pub fn read_fixed_vec<'a, T: FixedEncodable>(
-        &'a self,
+    pub fn read_fixed_vec<T: FixedEncodable>(
+        &self,
         offset: FixedVecOffset<T>,
-    ) -> FixedVec<'a, T> {
+    ) -> Result<FixedVec<&[u8], T>, DecodeError> {
         FixedVec::new(self.data.as_ref(), offset)
     }
 
     #[allow(dead_code)]
-    pub fn read_var_vec<'a, T: VarEncodable>(&'a self, offset: VarVecOffset<T>) -> VarVec<'a, T> {
+    pub fn read_var_vec<T: VarEncodable>(
+        &self,
+        offset: VarVecOffset<T>,
+    ) -> Result<VarVec<&[u8], T>, DecodeError> {
         VarVec::new(self.data.as_ref(), offset)
     }
 
     #[allow(dead_code)]
-    pub fn read_uniform_vec<'a, T: UniformEncodable>(
-        &'a self,
+    pub fn read_uniform_vec<T: UniformEncodable>(
+        &self,
         offset: UniformVecOffset<T>,
-    ) -> UniformVec<'a, T> {
+    ) -> Result<UniformVec<&[u8], T>, DecodeError> {
         UniformVec::new(self.data.as_ref(), offset)
     }
 
-    pub fn read_root<'a, T: FixedEncodable>(&'a self) -> T {
-        let offset = FixedScalarOffset::new(self.data.as_ref().len() - T::SIZE);
-        self.read_fixed_scalar(offset)
+    pub fn read_id_list(
+        &self,
+        offset: FixedVecOffset<u32>,
+        features: &FormatFeatures,
+    ) -> Result<IdList<&[u8]>, DecodeError> {
+        IdList::new(self.data.as_ref(), offset, features)
+    }
+
+    pub fn read_coord_vec(
+        &self,
+        offset: UniformVecOffset<Coord>,
+        features: &FormatFeatures,
+    ) -> Result<CoordVec<&[u8]>, DecodeError> {
+        CoordVec::new(self.data.as_ref(), offset, features)
+    }
+
+    pub fn read_root<T: FixedEncodable>(&self) -> Result<T, DecodeError> {
+        let len = self.data.as_ref().len();
+        if len < T::SIZE {
+            return Err(DecodeError::OutOfBounds { offset: 0, needed: T::SIZE, record_len: len });
+        }
+        self.read_fixed_scalar(FixedScalarOffset::new(len - T::SIZE))
     }
 }
 
-pub fn read_fixed_vec_raw<'a, T: FixedEncodable>(
-    buffer: &'a [u8],
+#[allow(dead_code)]
+pub fn read_fixed_vec_raw<B: AsRef<[u8]>, T: FixedEncodable>(
+    buffer: B,
     offset: FixedVecOffset<T>,
-) -> FixedVec<'a, T> {
+) -> Result<FixedVec<B, T>, DecodeError> {
     FixedVec::new(buffer, offset)
 }
 
-pub fn read_var_vec_raw<'a, T: VarEncodable>(
-    buffer: &'a [u8],
+pub fn read_id_list_raw<B: AsRef<[u8]>>(
+    buffer: B,
+    offset: FixedVecOffset<u32>,
+    features: &FormatFeatures,
+) -> Result<IdList<B>, DecodeError> {
+    IdList::new(buffer, offset, features)
+}
+
+pub fn read_coord_vec_raw<B: AsRef<[u8]>>(
+    buffer: B,
+    offset: UniformVecOffset<Coord>,
+    features: &FormatFeatures,
+) -> Result<CoordVec<B>, DecodeError> {
+    CoordVec::new(buffer, offset, features)
+}
+
+pub fn read_var_vec_raw<B: AsRef<[u8]>, T: VarEncodable>(
+    buffer: B,
     offset: VarVecOffset<T>,
-) -> VarVec<'a, T> {
+) -> Result<VarVec<B, T>, DecodeError> {
     VarVec::new(buffer, offset)
 }
 
-pub fn read_uniform_vec_raw<'a, T: UniformEncodable>(
-    buffer: &'a [u8],
+#[allow(dead_code)]
+pub fn read_uniform_vec_raw<B: AsRef<[u8]>, T: UniformEncodable>(
+    buffer: B,
     offset: UniformVecOffset<T>,
-) -> UniformVec<'a, T> {
+) -> Result<UniformVec<B, T>, DecodeError> {
     UniformVec::new(buffer, offset)
 }
 
+// The vector readers below check their whole extent when they're constructed, so that `get` and
+// iteration can read without any further checks. `B` is anything that can lend out the record's
+// bytes: a plain slice, or an owned handle when the reader needs to outlive a borrow.
+
 #[derive(Copy, Clone)]
-pub struct FixedVec<'a, T> {
-    data: &'a [u8],
+pub struct FixedVec<B, T> {
+    data: B,
     start: usize,
     len: usize,
-    phantom: PhantomData<&'a T>,
+    phantom: PhantomData<T>,
 }
 
-impl<'a, T: FixedEncodable> FixedVec<'a, T> {
-    pub fn new(data: &'a [u8], offset: FixedVecOffset<T>) -> Self {
-        let (len, len_len) = u32::decode_var(&data[(offset.addr as usize)..]);
-        let start = (offset.addr + len_len) as usize;
-        FixedVec { data, start, len: len.try_into().unwrap(), phantom: PhantomData }
+impl<B: AsRef<[u8]>, T: FixedEncodable> FixedVec<B, T> {
+    pub fn new(data: B, offset: FixedVecOffset<T>) -> Result<Self, DecodeError> {
+        let (len, len_len) = read_var_u32(data.as_ref(), offset.addr)?;
+        let start = offset.addr + len_len;
+        let len = len as usize;
+        check_bounds(data.as_ref(), start, len.saturating_mul(T::SIZE))?;
+        Ok(FixedVec { data, start, len, phantom: PhantomData })
     }
 
     pub fn get(&self, pos: usize) -> T {
+        debug_assert!(pos < self.len);
         let offset = self.start + (pos * T::SIZE);
-        T::read_fixed_from(self.data, FixedScalarOffset::new(offset))
+        T::read_fixed_from(self.data.as_ref(), FixedScalarOffset::new(offset))
     }
 
     #[allow(dead_code)]
@@ -309,24 +533,33 @@ impl<'a, T: FixedEncodable> FixedVec<'a, T> {
         (0..self.len).map(move |idx| self.get(idx))
     }
 
-    pub fn into_iter(self) -> impl Iterator<Item = T> + 'a {
+    #[allow(dead_code)]
+    pub fn into_iter(self) -> impl Iterator<Item = T> {
         (0..self.len).map(move |idx| self.get(idx))
     }
 }
 
 #[derive(Copy, Clone)]
-pub struct VarVec<'a, T> {
-    data: &'a [u8],
+pub struct VarVec<B, T> {
+    data: B,
     start: usize,
     len: usize,
-    phantom: PhantomData<&'a T>,
+    phantom: PhantomData<T>,
 }
 
-impl<'a, T: VarEncodable> VarVec<'a, T> {
-    pub fn new(data: &'a [u8], offset: VarVecOffset<T>) -> Self {
-        let (len, len_len) = u32::decode_var(&data[(offset.addr as usize)..]);
-        let start = (offset.addr + len_len) as usize;
-        VarVec { data, start, len: len.try_into().unwrap(), phantom: PhantomData }
+impl<B: AsRef<[u8]>, T: VarEncodable> VarVec<B, T> {
+    /// Unlike the other vectors, the extent of a VarVec can only be found by walking it, so this
+    /// decodes (and discards) every element.
+    pub fn new(data: B, offset: VarVecOffset<T>) -> Result<Self, DecodeError> {
+        let (len, len_len) = read_var_u32(data.as_ref(), offset.addr)?;
+        let start = offset.addr + len_len;
+        let len = len as usize;
+        let mut loc = start;
+        for _ in 0..len {
+            let (_, incr) = T::read_from(data.as_ref(), VarScalarOffset::new(loc))?;
+            loc += incr;
+        }
+        Ok(VarVec { data, start, len, phantom: PhantomData })
     }
 
     #[allow(dead_code)]
@@ -340,7 +573,8 @@ impl<'a, T: VarEncodable> VarVec<'a, T> {
         let mut i: usize = 0;
         std::iter::from_fn(move || {
             if i < self.len {
-                let (val, incr) = T::read_from(self.data, VarScalarOffset::new(loc));
+                let (val, incr) = T::read_from(self.data.as_ref(), VarScalarOffset::new(loc))
+                    .expect("elements are checked in VarVec::new");
                 i += 1;
                 loc += incr;
                 Some(val)
@@ -350,12 +584,13 @@ impl<'a, T: VarEncodable> VarVec<'a, T> {
         })
     }
 
-    pub fn into_iter(self) -> impl Iterator<Item = T> + 'a {
+    pub fn into_iter(self) -> impl Iterator<Item = T> {
         let mut loc: usize = self.start;
         let mut i: usize = 0;
         std::iter::from_fn(move || {
             if i < self.len {
-                let (val, incr) = T::read_from(self.data, VarScalarOffset::new(loc));
+                let (val, incr) = T::read_from(self.data.as_ref(), VarScalarOffset::new(loc))
+                    .expect("elements are checked in VarVec::new");
                 i += 1;
                 loc += incr;
                 Some(val)
@@ -367,25 +602,33 @@ impl<'a, T: VarEncodable> VarVec<'a, T> {
 }
 
 #[derive(Copy, Clone)]
-pub struct UniformVec<'a, T> {
-    data: &'a [u8],
+pub struct UniformVec<B, T> {
+    data: B,
     start: usize,
     rec_size: usize,
     len: usize,
-    phantom: PhantomData<&'a T>,
+    phantom: PhantomData<T>,
 }
 
-impl<'a, T: UniformEncodable> UniformVec<'a, T> {
-    pub fn new(data: &'a [u8], offset: UniformVecOffset<T>) -> Self {
-        let (len, len_len) = u32::decode_var(&data[(offset.addr as usize)..]);
-        let rec_size = data[offset.addr + len_len] as usize;
-        let start = (offset.addr + len_len + 1) as usize;
-        UniformVec { data, start, rec_size, len: len.try_into().unwrap(), phantom: PhantomData }
+impl<B: AsRef<[u8]>, T: UniformEncodable> UniformVec<B, T> {
+    pub fn new(data: B, offset: UniformVecOffset<T>) -> Result<Self, DecodeError> {
+        let (len, len_len) = read_var_u32(data.as_ref(), offset.addr)?;
+        check_bounds(data.as_ref(), offset.addr + len_len, 1)?;
+        let rec_size = data.as_ref()[offset.addr + len_len] as usize;
+        let start = offset.addr + len_len + 1;
+        let len = len as usize;
+        // empty vectors are written with a placeholder size, which is never used to read
+        if len > 0 && (rec_size < T::MIN_SIZE || rec_size > T::MAX_SIZE) {
+            return Err(DecodeError::InvalidRecordSize { offset: offset.addr, size: rec_size });
+        }
+        check_bounds(data.as_ref(), start, len.saturating_mul(rec_size))?;
+        Ok(UniformVec { data, start, rec_size, len, phantom: PhantomData })
     }
 
     pub fn get(&self, pos: usize) -> T {
+        debug_assert!(pos < self.len);
         let offset = self.start + (pos * self.rec_size);
-        T::read_with_size_from(self.data, self.rec_size, UniformScalarOffset::new(offset))
+        T::read_with_size_from(self.data.as_ref(), self.rec_size, UniformScalarOffset::new(offset))
     }
 
     pub fn len(&self) -> usize {
@@ -397,60 +640,417 @@ impl<'a, T: UniformEncodable> UniformVec<'a, T> {
         (0..self.len).map(move |idx| self.get(idx))
     }
 
-    pub fn into_iter(self) -> impl Iterator<Item = T> + 'a {
+    pub fn into_iter(self) -> impl Iterator<Item = T> {
         (0..self.len).map(move |idx| self.get(idx))
     }
 }
 
+/// A coord's packed ids, in whichever layout the store was built with
+pub enum IdList<B> {
+    Narrow(FixedVec<B, u32>),
+    Wide(FixedVec<B, WideId>),
+    Packed(PackedIdList<B>),
+}
+
+impl<B: AsRef<[u8]>> IdList<B> {
+    pub fn new(
+        data: B,
+        offset: FixedVecOffset<u32>,
+        features: &FormatFeatures,
+    ) -> Result<Self, DecodeError> {
+        Ok(if features.packed_vectors {
+            IdList::Packed(PackedIdList::new(data, offset)?)
+        } else if features.wide_ids {
+            IdList::Wide(FixedVec::new(data, offset.cast())?)
+        } else {
+            IdList::Narrow(FixedVec::new(data, offset)?)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            IdList::Narrow(ids) => ids.len(),
+            IdList::Wide(ids) => ids.len(),
+            IdList::Packed(ids) => ids.len,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        match self {
+            IdList::Narrow(ids) => Either::Left(Either::Left(ids.iter().map(u64::from))),
+            IdList::Wide(ids) => Either::Left(Either::Right(ids.iter().map(|id| id.0))),
+            IdList::Packed(ids) => Either::Right(ids.iter()),
+        }
+    }
+
+    pub fn into_iter(self) -> impl Iterator<Item = u64> {
+        match self {
+            IdList::Narrow(ids) => Either::Left(Either::Left(ids.into_iter().map(u64::from))),
+            IdList::Wide(ids) => Either::Left(Either::Right(ids.into_iter().map(|id| id.0))),
+            IdList::Packed(ids) => Either::Right(ids.into_iter()),
+        }
+    }
+}
+
+/// Descending packed ids as a varint count, then the first id, then the difference between each
+/// id and the one before it, all varints.
+pub struct PackedIdList<B> {
+    data: B,
+    start: usize,
+    len: usize,
+}
+
+impl<B: AsRef<[u8]>> PackedIdList<B> {
+    /// Like a VarVec, this has to walk the list to find its extent, checking every delta on the
+    /// way.
+    pub fn new(data: B, offset: FixedVecOffset<u32>) -> Result<Self, DecodeError> {
+        let (len, len_len) = read_var_u32(data.as_ref(), offset.addr)?;
+        let start = offset.addr + len_len;
+        let len = len as usize;
+        let mut loc = start;
+        let mut prev = None;
+        for _ in 0..len {
+            let (id_comp, incr) = read_packed_id(data.as_ref(), loc, prev)?;
+            prev = Some(id_comp);
+            loc += incr;
+        }
+        Ok(PackedIdList { data, start, len })
+    }
+
+    fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        let mut loc = self.start;
+        let mut prev = None;
+        (0..self.len).map(move |_| {
+            let (id_comp, incr) = read_packed_id(self.data.as_ref(), loc, prev)
+                .expect("ids are checked in PackedIdList::new");
+            prev = Some(id_comp);
+            loc += incr;
+            id_comp
+        })
+    }
+
+    fn into_iter(self) -> impl Iterator<Item = u64> {
+        let mut loc = self.start;
+        let mut prev = None;
+        (0..self.len).map(move |_| {
+            let (id_comp, incr) = read_packed_id(self.data.as_ref(), loc, prev)
+                .expect("ids are checked in PackedIdList::new");
+            prev = Some(id_comp);
+            loc += incr;
+            id_comp
+        })
+    }
+}
+
+#[inline]
+fn read_packed_id(
+    data: &[u8],
+    offset: usize,
+    prev: Option<u64>,
+) -> Result<(u64, usize), DecodeError> {
+    let (value, len) = read_var_u64(data, offset)?;
+    let id_comp = match prev {
+        Some(prev) => prev.checked_sub(value).ok_or(DecodeError::InvalidDelta { offset })?,
+        None => value,
+    };
+    Ok((id_comp, len))
+}
+
+/// How many coords each block of a `PackedCoordVec` holds
+const PACKED_BLOCK_LEN: usize = 16;
+
+/// A relev/score group's coords, in whichever layout the store was built with
+#[derive(Copy, Clone)]
+pub enum CoordVec<B> {
+    Uniform(UniformVec<B, Coord>),
+    Packed(PackedCoordVec<B>),
+}
+
+impl<B: AsRef<[u8]>> CoordVec<B> {
+    pub fn new(
+        data: B,
+        offset: UniformVecOffset<Coord>,
+        features: &FormatFeatures,
+    ) -> Result<Self, DecodeError> {
+        Ok(if features.packed_vectors {
+            CoordVec::Packed(PackedCoordVec::new(data, offset)?)
+        } else {
+            CoordVec::Uniform(UniformVec::new(data, offset)?)
+        })
+    }
+
+    pub fn get(&self, pos: usize) -> Coord {
+        match self {
+            CoordVec::Uniform(coords) => coords.get(pos),
+            CoordVec::Packed(coords) => coords.get(pos),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            CoordVec::Uniform(coords) => coords.len(),
+            CoordVec::Packed(coords) => coords.len,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Coord> + '_ {
+        match self {
+            CoordVec::Uniform(coords) => Either::Left(coords.iter()),
+            CoordVec::Packed(coords) => Either::Right(coords.iter()),
+        }
+    }
+
+    pub fn into_iter(self) -> impl Iterator<Item = Coord> {
+        match self {
+            CoordVec::Uniform(coords) => Either::Left(coords.into_iter()),
+            CoordVec::Packed(coords) => Either::Right(coords.into_iter()),
+        }
+    }
+}
+
+/// Descending coords, delta-encoded in blocks of `PACKED_BLOCK_LEN` so that they can still be
+/// binary searched. After a varint count comes a skip table: a byte giving its entries' width,
+/// then the little-endian offset of each block after the first, counting from the end of the
+/// table. Each block starts with its first coord and ids pointer as varints, and every coord after
+/// that is the varint difference from the one before, followed by its pointer's zigzag-encoded
+/// difference from the one before.
+#[derive(Copy, Clone)]
+pub struct PackedCoordVec<B> {
+    data: B,
+    len: usize,
+    skip_start: usize,
+    skip_width: usize,
+    blocks_start: usize,
+}
+
+impl<B: AsRef<[u8]>> PackedCoordVec<B> {
+    /// Walks every block up front, so that `get` and iteration can decode without further
+    /// checks.
+    pub fn new(data: B, offset: UniformVecOffset<Coord>) -> Result<Self, DecodeError> {
+        let buf = data.as_ref();
+        let (len, len_len) = read_var_u32(buf, offset.addr)?;
+        let len = len as usize;
+        check_bounds(buf, offset.addr + len_len, 1)?;
+        let skip_width = buf[offset.addr + len_len] as usize;
+        if !(1..=4).contains(&skip_width) {
+            return Err(DecodeError::InvalidRecordSize { offset: offset.addr, size: skip_width });
+        }
+        let skip_start = offset.addr + len_len + 1;
+        // every block but the first has an entry
+        let skips = len.saturating_sub(1) / PACKED_BLOCK_LEN;
+        check_bounds(buf, skip_start, skips * skip_width)?;
+        let blocks_start = skip_start + skips * skip_width;
+
+        let mut loc = blocks_start;
+        let mut prev = None;
+        for pos in 0..len {
+            if pos % PACKED_BLOCK_LEN == 0 {
+                let block = pos / PACKED_BLOCK_LEN;
+                if block_start(buf, skip_start, skip_width, blocks_start, block) != loc {
+                    return Err(DecodeError::InvalidSkipTable { offset: offset.addr });
+                }
+                prev = None;
+            }
+            let (coord, incr) = read_packed_coord(buf, loc, prev)?;
+            prev = Some(coord);
+            loc += incr;
+        }
+        Ok(PackedCoordVec { data, len, skip_start, skip_width, blocks_start })
+    }
+
+    pub fn get(&self, pos: usize) -> Coord {
+        debug_assert!(pos < self.len);
+        let block = pos / PACKED_BLOCK_LEN;
+        let buf = self.data.as_ref();
+        let mut loc = block_start(buf, self.skip_start, self.skip_width, self.blocks_start, block);
+        let mut coord = None;
+        for _ in 0..=(pos % PACKED_BLOCK_LEN) {
+            let (next, incr) = read_packed_coord(buf, loc, coord)
+                .expect("blocks are checked in PackedCoordVec::new");
+            coord = Some(next);
+            loc += incr;
+        }
+        coord.expect("every block has at least one coord")
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Coord> + '_ {
+        let mut loc = self.blocks_start;
+        let mut prev = None;
+        (0..self.len).map(move |pos| {
+            if pos % PACKED_BLOCK_LEN == 0 {
+                prev = None;
+            }
+            let (coord, incr) = read_packed_coord(self.data.as_ref(), loc, prev)
+                .expect("blocks are checked in PackedCoordVec::new");
+            prev = Some(coord);
+            loc += incr;
+            coord
+        })
+    }
+
+    fn into_iter(self) -> impl Iterator<Item = Coord> {
+        let mut loc = self.blocks_start;
+        let mut prev = None;
+        (0..self.len).map(move |pos| {
+            if pos % PACKED_BLOCK_LEN == 0 {
+                prev = None;
+            }
+            let (coord, incr) = read_packed_coord(self.data.as_ref(), loc, prev)
+                .expect("blocks are checked in PackedCoordVec::new");
+            prev = Some(coord);
+            loc += incr;
+            coord
+        })
+    }
+}
+
+#[inline]
+fn block_start(
+    data: &[u8],
+    skip_start: usize,
+    skip_width: usize,
+    blocks_start: usize,
+    block: usize,
+) -> usize {
+    if block == 0 {
+        return blocks_start;
+    }
+    let entry = skip_start + (block - 1) * skip_width;
+    let mut skip_buf = [0u8; 4];
+    skip_buf[..skip_width].clone_from_slice(&data[entry..(entry + skip_width)]);
+    blocks_start + u32::from_le_bytes(skip_buf) as usize
+}
+
+/// Reads a coord, applying its deltas to the one before it in its block if there is one
+#[inline]
+fn read_packed_coord(
+    data: &[u8],
+    offset: usize,
+    prev: Option<Coord>,
+) -> Result<(Coord, usize), DecodeError> {
+    let (coord, coord_len) = read_var_u64(data, offset)?;
+    let (ptr, ptr_len) = read_var_u64(data, offset + coord_len)?;
+    let len = coord_len + ptr_len;
+    let (coord, ptr) = match prev {
+        None if ptr > u64::from(u32::MAX) => {
+            return Err(DecodeError::MalformedVarint { offset: offset + coord_len })
+        }
+        None => (coord, ptr),
+        Some(prev) => {
+            // undo the zigzag encoding
+            let ptr_delta = ((ptr >> 1) as i64) ^ -((ptr & 1) as i64);
+            let coord =
+                prev.coord.checked_sub(coord).ok_or(DecodeError::InvalidDelta { offset })?;
+            let ptr = (prev.ids.addr as i64)
+                .checked_add(ptr_delta)
+                .filter(|ptr| (0..=i64::from(u32::MAX)).contains(ptr))
+                .ok_or(DecodeError::InvalidDelta { offset })?;
+            (coord, ptr as u64)
+        }
+    };
+    Ok((Coord { coord, ids: FixedVecOffset::new(ptr as usize) }, len))
+}
+
 pub struct RelevScore {
-    pub relev_score: u8,
+    /// The packed relev and score; see `pack_relev_score`. It's written as a varint, which for the
+    /// original encoding's values is the single byte it's always been.
+    pub relev_score: u32,
     pub coords: UniformVecOffset<Coord>,
 }
 
 impl VarEncodable for RelevScore {
     fn write_to(&self, buffer: &mut Vec<u8>) -> usize {
-        buffer.push(self.relev_score);
+        let mut rs_buf = [0u8; 8];
+        let rs_len = self.relev_score.encode_var(&mut rs_buf);
+        buffer.extend_from_slice(&rs_buf[..rs_len]);
         let mut addr_buf = [0u8; 8];
         let addr_len = (self.coords.addr as u32).encode_var(&mut addr_buf);
         buffer.extend_from_slice(&addr_buf[..addr_len]);
-        1 + addr_len
+        rs_len + addr_len
     }
 
-    fn read_from(buffer: &[u8], offset: VarScalarOffset<Self>) -> (Self, usize) {
-        let relev_score = buffer[offset.addr];
-        let (coords, addr_len) = UniformVecOffset::from_var_pointer(buffer, offset.addr + 1);
-        (RelevScore { relev_score, coords }, 1 + addr_len)
+    fn read_from(
+        buffer: &[u8],
+        offset: VarScalarOffset<Self>,
+    ) -> Result<(Self, usize), DecodeError> {
+        let (relev_score, rs_len) = read_var_u32(buffer, offset.addr)?;
+        let (coords, addr_len) = UniformVecOffset::from_var_pointer(buffer, offset.addr + rs_len)?;
+        Ok((RelevScore { relev_score, coords }, rs_len + addr_len))
     }
 }
 
+/// A morton-ordered tile and the ids on it. The coord takes four bytes, or eight in vectors where
+/// some coord doesn't fit in a u32 (stores with wide coords above zoom 16); the ids pointer takes
+/// whatever's left of the record size.
 #[derive(Copy, Clone)]
 pub struct Coord {
-    pub coord: u32,
+    pub coord: u64,
     pub ids: FixedVecOffset<u32>,
 }
 
+impl Coord {
+    fn coord_size(&self) -> usize {
+        if self.coord > u64::from(u32::MAX) {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn ptr_size(&self) -> usize {
+        match self.ids.addr {
+            0..=255 => 1,
+            256..=65535 => 2,
+            65536..=16777215 => 3,
+            _ => 4,
+        }
+    }
+
+    /// Records of up to 8 bytes have a four-byte coord, since the pointer never takes more than
+    /// four
+    fn coord_size_for(size: usize) -> usize {
+        if size > 8 {
+            8
+        } else {
+            4
+        }
+    }
+}
+
 impl UniformEncodable for Coord {
-    const MAX_SIZE: usize = 8;
+    const MIN_SIZE: usize = 5;
+    const MAX_SIZE: usize = 12;
     fn get_min_size(&self) -> usize {
-        match self.ids.addr {
-            0..=255 => 4 + 1,
-            256..=65535 => 4 + 2,
-            65536..=16777215 => 4 + 3,
-            _ => 4 + 4,
+        self.coord_size() + self.ptr_size()
+    }
+
+    fn get_uniform_size(items: &[Self]) -> usize {
+        // the coord width and pointer width are each the widest any item needs, which might come
+        // from different items
+        let coord_size = items.iter().map(Coord::coord_size).max();
+        let ptr_size = items.iter().map(Coord::ptr_size).max();
+        match (coord_size, ptr_size) {
+            (Some(coord_size), Some(ptr_size)) => coord_size + ptr_size,
+            _ => 255,
         }
     }
 
     fn write_with_size_to(&self, size: usize, buffer: &mut Vec<u8>) {
-        buffer.extend_from_slice(&(self.coord as u32).to_le_bytes());
-        buffer.extend_from_slice(&(self.ids.addr as u32).to_le_bytes()[..(size - 4)]);
+        let coord_size = Coord::coord_size_for(size);
+        buffer.extend_from_slice(&self.coord.to_le_bytes()[..coord_size]);
+        buffer.extend_from_slice(&(self.ids.addr as u32).to_le_bytes()[..(size - coord_size)]);
     }
 
     fn read_with_size_from(buffer: &[u8], size: usize, offset: UniformScalarOffset<Self>) -> Self {
-        let coord = u32::from_le_bytes(buffer[offset.addr..(offset.addr + 4)].try_into().unwrap());
-        let ptr_size = size - 4;
+        let coord_size = Coord::coord_size_for(size);
+        let mut coord_buf = [0u8; 8];
+        coord_buf[..coord_size].clone_from_slice(&buffer[offset.addr..(offset.addr + coord_size)]);
+        let coord = u64::from_le_bytes(coord_buf);
+        let ptr_size = size - coord_size;
         let mut ptr_buf = [0u8; 4];
-        ptr_buf[..ptr_size]
-            .clone_from_slice(&buffer[(offset.addr + 4)..(offset.addr + 4 + ptr_size)]);
+        ptr_buf[..ptr_size].clone_from_slice(
+            &buffer[(offset.addr + coord_size)..(offset.addr + coord_size + ptr_size)],
+        );
         let ptr = u32::from_le_bytes(ptr_buf);
         let ids = FixedVecOffset::<u32>::new(ptr as usize);
         Coord { coord, ids }
@@ -468,11 +1068,31 @@ impl FixedEncodable for u32 {
     }
 }
 
+/// A packed id from a store with wide ids: a 32-bit feature id above the 8-bit source phrase
+/// hash, in five little-endian bytes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WideId(pub u64);
+
+impl FixedEncodable for WideId {
+    const SIZE: usize = 5;
+    fn write_fixed_to(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.0.to_le_bytes()[..5]);
+    }
+
+    fn read_fixed_from(buffer: &[u8], offset: FixedScalarOffset<Self>) -> Self {
+        let mut id_buf = [0u8; 8];
+        id_buf[..5].clone_from_slice(&buffer[offset.addr..(offset.addr + 5)]);
+        WideId(u64::from_le_bytes(id_buf))
+    }
+}
+
 pub struct PhraseRecord {
     pub relev_scores: VarVecOffset<RelevScore>,
 }
 
-pub fn read_phrase_record_from<U: AsRef<[u8]>>(reader: &Reader<U>) -> PhraseRecord {
+pub fn read_phrase_record_from<U: AsRef<[u8]>>(
+    reader: &Reader<U>,
+) -> Result<PhraseRecord, DecodeError> {
     reader.read_root()
 }
 
@@ -496,8 +1116,8 @@ use itertools::Itertools;
 fn test_write() {
     #[derive(Debug, PartialOrd, PartialEq, Ord, Eq, Clone)]
     struct Grid {
-        relev_score: u8,
-        coord: u32,
+        relev_score: u32,
+        coord: u64,
         id: u32,
     }
 
@@ -516,6 +1136,7 @@ fn test_write() {
         Grid { relev_score: 65, coord: 65536, id: 8 },
         Grid { relev_score: 66, coord: 4, id: 8 },
         Grid { relev_score: 66, coord: 16777215, id: 8 },
+        Grid { relev_score: 0x1_cc03, coord: 7, id: 99 },
     ];
 
     grids.sort_by(|a, b| b.cmp(&a));
@@ -539,17 +1160,17 @@ fn test_write() {
     writer.write_fixed_scalar(record);
 
     let reader = Reader::new(writer.data);
-    let r_reader = read_phrase_record_from(&reader);
+    let r_reader = read_phrase_record_from(&reader).unwrap();
 
     let mut out_grids = Vec::new();
     let mut rs_count = 0;
-    let rses = reader.read_var_vec(r_reader.relev_scores);
+    let rses = reader.read_var_vec(r_reader.relev_scores).unwrap();
     for rs in rses.iter() {
         let mut coord_count = 0;
-        let coords = reader.read_uniform_vec(rs.coords);
+        let coords = reader.read_uniform_vec(rs.coords).unwrap();
         for coord in coords.iter() {
             let mut id_count = 0;
-            let ids = reader.read_fixed_vec(coord.ids);
+            let ids = reader.read_fixed_vec(coord.ids).unwrap();
             for id in ids.iter() {
                 out_grids.push(Grid { relev_score: rs.relev_score, coord: coord.coord, id });
                 id_count += 1;
@@ -565,3 +1186,202 @@ fn test_write() {
     let deduped_grids: Vec<_> = grids.iter().cloned().dedup().collect();
     assert_eq!(deduped_grids, out_grids);
 }
+
+#[cfg(test)]
+fn decode_all(data: &[u8]) -> Result<Vec<(u32, u64, u32)>, DecodeError> {
+    let reader = Reader::new(data);
+    let record = read_phrase_record_from(&reader)?;
+    let mut out = Vec::new();
+    for rs in reader.read_var_vec(record.relev_scores)?.iter() {
+        for coord in reader.read_uniform_vec(rs.coords)?.iter() {
+            for id in reader.read_fixed_vec(coord.ids)?.iter() {
+                out.push((rs.relev_score, coord.coord, id));
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_malformed() {
+    let mut writer = Writer::new();
+    let ids = writer.write_fixed_vec(&[300u32, 7]);
+    let coords = writer.write_uniform_vec(&[Coord { coord: 70000, ids }, Coord { coord: 3, ids }]);
+    let rses = writer.write_var_vec(&[RelevScore { relev_score: 49, coords }]);
+    writer.write_fixed_scalar(PhraseRecord { relev_scores: rses });
+    let data = writer.finish();
+
+    assert_eq!(
+        decode_all(&data).unwrap(),
+        vec![(49, 70000, 300), (49, 70000, 7), (49, 3, 300), (49, 3, 7)]
+    );
+
+    // every truncation and every single-byte corruption should either decode or return an
+    // error, but never panic
+    for len in 0..data.len() {
+        let _ = decode_all(&data[..len]);
+    }
+    for pos in 0..data.len() {
+        for byte in &[0u8, 1, 0x7f, 0x80, 0xff] {
+            let mut corrupt = data.clone();
+            corrupt[pos] = *byte;
+            let _ = decode_all(&corrupt);
+        }
+    }
+
+    assert_eq!(
+        decode_all(&[1, 2]),
+        Err(DecodeError::OutOfBounds { offset: 0, needed: 4, record_len: 2 })
+    );
+    // root pointing at an unterminated varint
+    assert_eq!(
+        decode_all(&[0x80, 0x80, 0x80, 0x80, 0x80, 0, 0, 0, 0]),
+        Err(DecodeError::MalformedVarint { offset: 0 })
+    );
+    // a coord vec claiming 13-byte records
+    let mut bad_size = data.clone();
+    let coords_addr = 1 + 8;
+    assert_eq!(bad_size[coords_addr], 2, "two coords");
+    bad_size[coords_addr + 1] = 13;
+    assert_eq!(
+        decode_all(&bad_size),
+        Err(DecodeError::InvalidRecordSize { offset: coords_addr, size: 13 })
+    );
+}
+
+#[test]
+fn test_wide_ids() {
+    let wide_features = FormatFeatures { wide_ids: true, ..FormatFeatures::default() };
+    let narrow_features = FormatFeatures::default();
+    let ids = [(u64::from(u32::MAX) << 8) | 0xab, 1 << 32, 300];
+    let mut writer = Writer::new();
+    let wide = writer.write_id_list(&ids, &wide_features);
+    let narrow = writer.write_id_list(&ids[2..], &narrow_features);
+    let data = writer.finish();
+    assert_eq!(data.len(), 1 + 3 * 5 + 1 + 4, "wide ids take five bytes each");
+
+    let reader = Reader::new(&data[..]);
+    assert_eq!(
+        reader.read_id_list(wide, &wide_features).unwrap().iter().collect::<Vec<_>>(),
+        ids.to_vec()
+    );
+    assert_eq!(
+        reader.read_id_list(narrow, &narrow_features).unwrap().iter().collect::<Vec<_>>(),
+        vec![300]
+    );
+    // a wide list needs all five bytes of its last id
+    assert!(IdList::new(&data[..1 + 3 * 5 - 1], wide, &wide_features).is_err());
+}
+
+#[test]
+fn test_packed_vectors() {
+    let packed = FormatFeatures { packed_vectors: true, ..FormatFeatures::default() };
+    let plain = FormatFeatures::default();
+
+    let ids = [1u64 << 40, 1000, 999, 3, 0];
+    let mut writer = Writer::new();
+    let packed_ids = writer.write_id_list(&ids, &packed);
+    let empty_ids = writer.write_id_list(&[], &packed);
+    let data = writer.finish();
+    // a count and a six-byte first id, then deltas of six, one, two and one bytes, then the
+    // empty list's count
+    assert_eq!(data.len(), 1 + 6 + 6 + 1 + 2 + 1 + 1);
+    let reader = Reader::new(&data[..]);
+    let read_ids = reader.read_id_list(packed_ids, &packed).unwrap();
+    assert_eq!(read_ids.len(), 5);
+    assert_eq!(read_ids.iter().collect::<Vec<_>>(), ids.to_vec());
+    assert_eq!(reader.read_id_list(empty_ids, &packed).unwrap().len(), 0);
+    // the last delta is cut off
+    assert!(IdList::new(&data[..1 + 6 + 6 + 1 + 2], packed_ids, &packed).is_err());
+    // a delta bigger than the id it's subtracted from
+    let mut bad_delta = data.clone();
+    bad_delta[1 + 6 + 6 + 1 + 2] = 5;
+    assert_eq!(
+        IdList::new(&bad_delta[..], packed_ids, &packed).err(),
+        Some(DecodeError::InvalidDelta { offset: 1 + 6 + 6 + 1 + 2 })
+    );
+
+    // enough coords for a few blocks, with their id lists in the order they were written
+    let mut writer = Writer::new();
+    let mut coords = Vec::new();
+    for i in (0..40u64).rev() {
+        let ids = writer.write_id_list(&[i], &packed);
+        coords.push(Coord { coord: (1 << 33) + i * 3, ids });
+    }
+    let packed_coords = writer.write_coord_vec(&coords, &packed);
+    let plain_coords = writer.write_coord_vec(&coords, &plain);
+    let empty_coords = writer.write_coord_vec(&[], &packed);
+    let data = writer.finish();
+    assert!(
+        plain_coords.addr - packed_coords.addr < (empty_coords.addr - plain_coords.addr) / 2,
+        "packed coords take less than half the space"
+    );
+
+    let reader = Reader::new(&data[..]);
+    for features in &[packed, plain] {
+        let offset = if features.packed_vectors { packed_coords } else { plain_coords };
+        let read_coords = reader.read_coord_vec(offset, features).unwrap();
+        assert_eq!(read_coords.len(), 40);
+        let all: Vec<_> = read_coords.iter().map(|c| (c.coord, c.ids.addr)).collect();
+        assert_eq!(all, coords.iter().map(|c| (c.coord, c.ids.addr)).collect::<Vec<_>>());
+        for (pos, coord) in coords.iter().enumerate() {
+            assert_eq!(read_coords.get(pos).coord, coord.coord, "get({})", pos);
+            assert_eq!(read_coords.get(pos).ids.addr, coord.ids.addr, "get({})", pos);
+        }
+    }
+    assert_eq!(reader.read_coord_vec(empty_coords, &packed).unwrap().len(), 0);
+
+    // the skip table sits after the count and its one-byte width
+    let skip_addr = packed_coords.addr + 1;
+    assert_eq!(data[skip_addr], 1, "blocks are close enough for one-byte skips");
+    let mut bad_skip = data.clone();
+    bad_skip[skip_addr + 1] += 1;
+    assert_eq!(
+        reader_coords(&bad_skip, packed_coords, &packed),
+        Err(DecodeError::InvalidSkipTable { offset: packed_coords.addr })
+    );
+    let mut bad_width = data.clone();
+    bad_width[skip_addr] = 5;
+    assert_eq!(
+        reader_coords(&bad_width, packed_coords, &packed),
+        Err(DecodeError::InvalidRecordSize { offset: packed_coords.addr, size: 5 })
+    );
+    assert!(reader_coords(&data[..plain_coords.addr - 1], packed_coords, &packed).is_err());
+}
+
+#[cfg(test)]
+fn reader_coords(
+    data: &[u8],
+    offset: UniformVecOffset<Coord>,
+    features: &FormatFeatures,
+) -> Result<usize, DecodeError> {
+    CoordVec::new(data, offset, features).map(|coords| coords.len())
+}
+
+#[test]
+fn test_wide_coords() {
+    let mut writer = Writer::new();
+    let near_ids = writer.write_fixed_vec(&[1u32]);
+    // push the second id list past a one-byte pointer
+    writer.write_fixed_vec(&[0u32; 80]);
+    let far_ids = writer.write_fixed_vec(&[2u32]);
+    let wide = 1u64 << 40;
+    let mixed = writer.write_uniform_vec(&[
+        Coord { coord: wide, ids: near_ids },
+        Coord { coord: 3, ids: far_ids },
+    ]);
+    let narrow = writer.write_uniform_vec(&[Coord { coord: 3, ids: far_ids }]);
+    let data = writer.finish();
+
+    let reader = Reader::new(&data[..]);
+    let coords = reader.read_uniform_vec(mixed).unwrap();
+    assert_eq!(data[mixed.addr + 1], 8 + 2, "an eight-byte coord and the widest pointer");
+    let decoded: Vec<_> = coords
+        .iter()
+        .map(|c| (c.coord, reader.read_fixed_vec(c.ids).unwrap().iter().collect::<Vec<_>>()))
+        .collect();
+    assert_eq!(decoded, vec![(wide, vec![1]), (3, vec![2])]);
+
+    assert_eq!(data[narrow.addr + 1], 4 + 2, "coords that fit in a u32 stay four bytes");
+    assert_eq!(reader.read_uniform_vec(narrow).unwrap().get(0).coord, 3);
+}
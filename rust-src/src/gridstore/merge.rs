@@ -0,0 +1,99 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+
+use failure::{Error, Fail};
+
+use crate::gridstore::builder::GridStoreBuilder;
+use crate::gridstore::feature_index::FEATURE_INDEX_KEY;
+use crate::gridstore::metadata::GridStoreOptions;
+use crate::gridstore::store::GridStore;
+use crate::gridstore::tile_index::TILE_INDEX_KEY;
+
+#[derive(Debug, Fail)]
+pub enum MergeError {
+    #[fail(display = "no stores to merge")]
+    NoStores,
+    #[fail(display = "store {} was built with different options from store 0", idx)]
+    OptionsMismatch { idx: usize },
+    #[fail(display = "feature {} appears in both store {} and store {}", id, first, second)]
+    DuplicateFeature { id: u32, first: usize, second: usize },
+}
+
+fn options_of(store: &GridStore) -> GridStoreOptions {
+    GridStoreOptions {
+        zoom: store.zoom,
+        type_id: store.type_id,
+        coalesce_radius: store.coalesce_radius,
+        bboxes: store.bboxes.clone(),
+        max_score: store.max_score,
+    }
+}
+
+impl GridStoreBuilder {
+    /// Loads every record from several finished stores into the builder, so that `finish` writes
+    /// them out as one store. The stores have to share their options, and phrase ids have to mean
+    /// the same thing in all of them; entries for the same key are unioned, and the prefix bins
+    /// are rebuilt from the combined records. A feature id turning up in more than one store is
+    /// taken to be two different features given the same id, and is an error.
+    ///
+    /// Settings are combined with whatever the builder already has rather than replacing it: the
+    /// builder takes on the tombstones of every store, the feature or tile index if every store
+    /// has one, every format feature any of them uses, and the bin boundaries of all of them,
+    /// unless `set_auto_bins` has been called. The first store's metadata is only taken on if
+    /// `set_metadata` hasn't been called. Only the stores themselves are read, not any overlays
+    /// layered onto them.
+    pub fn merge_stores<T: Borrow<GridStore>>(&mut self, stores: &[T]) -> Result<(), Error> {
+        let first = stores.first().ok_or(MergeError::NoStores)?.borrow();
+        let options = options_of(first);
+        for (idx, store) in stores.iter().enumerate().skip(1) {
+            if options_of(store.borrow()) != options {
+                return Err(MergeError::OptionsMismatch { idx }.into());
+            }
+        }
+
+        // any set of boundaries works, since the bins are rebuilt, so the stores' are unioned to
+        // keep each of their bins at least as small as it was
+        let mut bin_boundaries = self.bin_boundaries.clone();
+        let mut all_indexed = (true, true);
+        let mut format_features = self.format_features;
+        for store in stores {
+            let store = store.borrow();
+            bin_boundaries.extend(store.bin_boundaries.iter().cloned());
+            format_features = format_features.union(&store.format_features);
+            all_indexed.0 &= store.storage.get(FEATURE_INDEX_KEY)?.is_some();
+            all_indexed.1 &= store.storage.get(TILE_INDEX_KEY)?.is_some();
+        }
+        self.set_feature_index(self.feature_index || all_indexed.0);
+        self.set_tile_index(self.tile_index || all_indexed.1);
+        self.set_format_features(format_features)?;
+        if self.max_bin_bytes.is_none() {
+            bin_boundaries.sort();
+            bin_boundaries.dedup();
+            self.load_bin_boundaries(bin_boundaries)?;
+        }
+        if let (None, Some(metadata)) = (&self.options, &first.metadata) {
+            self.set_metadata(metadata.options.clone(), &metadata.source);
+        }
+
+        let mut owners: HashMap<u32, usize> = HashMap::new();
+        for (idx, store) in stores.iter().enumerate() {
+            let store = store.borrow();
+            let tombstones: Vec<u32> = store.tombstones.iter().cloned().collect();
+            self.add_tombstones(&tombstones);
+
+            for record in store.builder_entries() {
+                let (key, entry) = record?;
+                let id_comps = entry.values().flat_map(|coords| coords.values()).flatten();
+                for id_comp in id_comps {
+                    let id = (id_comp >> 8) as u32;
+                    let first = *owners.entry(id).or_insert(idx);
+                    if first != idx {
+                        return Err(MergeError::DuplicateFeature { id, first, second: idx }.into());
+                    }
+                }
+                self.append_entry(&key, entry)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::convert::TryInto;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::{format_err, Error};
+use serde::{Deserialize, Serialize};
+
+use crate::gridstore::storage::{GridStorage, GridStorageWriter};
+
+/// The key under which a store's metadata record is kept; like `~BOUNDS`, it sorts after every
+/// phrase key.
+pub const METADATA_KEY: &[u8] = b"~METADATA";
+
+/// The key under which the store's format version is kept.
+pub const FORMAT_VERSION_KEY: &[u8] = b"~FORMAT";
+
+/// The newest version of the key encoding and `gridstore_format` value layout that the builder
+/// writes. Bump this whenever either changes in a way an older reader couldn't cope with.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The version the builder writes for stores that use none of the optional features, which
+/// readers from before the features existed can still open.
+pub const BASE_FORMAT_VERSION: u32 = 1;
+
+/// The oldest format version this reader can still decode.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+
+/// Stores built before the format version record existed all share the version 1 layout.
+const UNVERSIONED_FORMAT_VERSION: u32 = 1;
+
+/// Optional record encodings chosen when a store is built. A store using any of them is written
+/// as version 2, whose format record carries a bitset of these after the version, and a reader
+/// refuses any store using one it doesn't know.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatFeatures {
+    /// Relevs to within 1/255 and scores of up to 255, instead of four relev levels and
+    /// four-bit scores
+    #[serde(default)]
+    pub high_precision: bool,
+    /// Feature ids of up to 32 bits, stored in five bytes with their source phrase hash, instead
+    /// of 24-bit ids in four
+    #[serde(default)]
+    pub wide_ids: bool,
+    /// Tile coordinates of up to 32 bits, so the store can be built at zooms above 16; coords
+    /// whose morton code doesn't fit in a u32 are stored in eight bytes instead of four
+    #[serde(default)]
+    pub wide_coords: bool,
+    /// Coord vectors and id lists delta-encoded as varints, with coords in blocks that a skip
+    /// table points into so they can still be binary searched, instead of fixed-width entries
+    #[serde(default)]
+    pub packed_vectors: bool,
+    /// Phrase records that are byte-identical to an earlier one are stored as a reference to it
+    /// rather than as another copy
+    #[serde(default)]
+    pub shared_values: bool,
+}
+
+const HIGH_PRECISION_BIT: u32 = 1;
+const WIDE_IDS_BIT: u32 = 1 << 1;
+const WIDE_COORDS_BIT: u32 = 1 << 2;
+const PACKED_VECTORS_BIT: u32 = 1 << 3;
+const SHARED_VALUES_BIT: u32 = 1 << 4;
+const KNOWN_FEATURE_BITS: u32 =
+    HIGH_PRECISION_BIT | WIDE_IDS_BIT | WIDE_COORDS_BIT | PACKED_VECTORS_BIT | SHARED_VALUES_BIT;
+
+impl FormatFeatures {
+    fn to_bits(self) -> u32 {
+        let bit = |set: bool, bit: u32| if set { bit } else { 0 };
+        bit(self.high_precision, HIGH_PRECISION_BIT)
+            | bit(self.wide_ids, WIDE_IDS_BIT)
+            | bit(self.wide_coords, WIDE_COORDS_BIT)
+            | bit(self.packed_vectors, PACKED_VECTORS_BIT)
+            | bit(self.shared_values, SHARED_VALUES_BIT)
+    }
+
+    fn from_bits(bits: u32) -> Result<Self, Error> {
+        if bits & !KNOWN_FEATURE_BITS != 0 {
+            return Err(format_err!("store uses unknown format features {:#x}", bits));
+        }
+        Ok(FormatFeatures {
+            high_precision: bits & HIGH_PRECISION_BIT != 0,
+            wide_ids: bits & WIDE_IDS_BIT != 0,
+            wide_coords: bits & WIDE_COORDS_BIT != 0,
+            packed_vectors: bits & PACKED_VECTORS_BIT != 0,
+            shared_values: bits & SHARED_VALUES_BIT != 0,
+        })
+    }
+
+    /// The deepest zoom whose tile coordinates these features can store: 16 bits per coordinate
+    /// normally, and 32 with wide coords.
+    pub fn max_zoom(&self) -> u16 {
+        if self.wide_coords {
+            32
+        } else {
+            16
+        }
+    }
+
+    /// Everything either set of features uses.
+    pub fn union(&self, other: &FormatFeatures) -> FormatFeatures {
+        FormatFeatures {
+            high_precision: self.high_precision || other.high_precision,
+            wide_ids: self.wide_ids || other.wide_ids,
+            wide_coords: self.wide_coords || other.wide_coords,
+            packed_vectors: self.packed_vectors || other.packed_vectors,
+            shared_values: self.shared_values || other.shared_values,
+        }
+    }
+}
+
+/// Reads the format version a store was written with, without checking whether it's supported.
+pub fn read_format_version(storage: &dyn GridStorage) -> Result<u32, Error> {
+    match storage.get(FORMAT_VERSION_KEY)? {
+        Some(value) => {
+            let encoded: [u8; 4] = value
+                .as_ref()
+                .get(..4)
+                .and_then(|version| version.try_into().ok())
+                .ok_or_else(|| format_err!("malformed format version record"))?;
+            Ok(u32::from_le_bytes(encoded))
+        }
+        None => Ok(UNVERSIONED_FORMAT_VERSION),
+    }
+}
+
+/// Reads the optional features a store was written with. Only call this once the version is
+/// known to be supported.
+pub fn read_format_features(storage: &dyn GridStorage) -> Result<FormatFeatures, Error> {
+    let value = match storage.get(FORMAT_VERSION_KEY)? {
+        Some(value) => value,
+        None => return Ok(FormatFeatures::default()),
+    };
+    match value.as_ref().len() {
+        4 => Ok(FormatFeatures::default()),
+        8 => FormatFeatures::from_bits(u32::from_le_bytes(value.as_ref()[4..].try_into().unwrap())),
+        _ => Err(format_err!("malformed format version record")),
+    }
+}
+
+pub fn write_format_version<W: GridStorageWriter>(
+    writer: &mut W,
+    features: &FormatFeatures,
+) -> Result<(), Error> {
+    if *features == FormatFeatures::default() {
+        return writer.put(FORMAT_VERSION_KEY, &BASE_FORMAT_VERSION.to_le_bytes());
+    }
+    let mut encoded = Vec::with_capacity(8);
+    encoded.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    encoded.extend_from_slice(&features.to_bits().to_le_bytes());
+    writer.put(FORMAT_VERSION_KEY, &encoded)
+}
+
+/// The options a store is queried with. These used to be passed in on every open; they're now
+/// also recorded in the store itself at build time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridStoreOptions {
+    pub zoom: u16,
+    pub type_id: u16,
+    pub coalesce_radius: f64,
+    pub bboxes: Vec<[u32; 4]>,
+    pub max_score: f64,
+}
+
+impl Default for GridStoreOptions {
+    fn default() -> Self {
+        GridStoreOptions {
+            zoom: 6,
+            type_id: 0,
+            coalesce_radius: 0.0,
+            bboxes: vec![[0, 0, 63, 63]],
+            max_score: 0.0,
+        }
+    }
+}
+
+/// Fields to replace in a store's recorded options when opening it with `GridStore::open`;
+/// anything left as `None` keeps its recorded value.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GridStoreOptionOverrides {
+    pub zoom: Option<u16>,
+    pub type_id: Option<u16>,
+    pub coalesce_radius: Option<f64>,
+    pub bboxes: Option<Vec<[u32; 4]>>,
+    pub max_score: Option<f64>,
+}
+
+impl GridStoreOptionOverrides {
+    pub fn apply_to(&self, options: GridStoreOptions) -> GridStoreOptions {
+        GridStoreOptions {
+            zoom: self.zoom.unwrap_or(options.zoom),
+            type_id: self.type_id.unwrap_or(options.type_id),
+            coalesce_radius: self.coalesce_radius.unwrap_or(options.coalesce_radius),
+            bboxes: self.bboxes.clone().unwrap_or(options.bboxes),
+            max_score: self.max_score.unwrap_or(options.max_score),
+        }
+    }
+}
+
+/// Everything a store records about how it was built.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridStoreMetadata {
+    pub options: GridStoreOptions,
+    /// Seconds since the Unix epoch at which the builder finished.
+    pub build_timestamp: u64,
+    /// Free-form description of the data the store was built from.
+    pub source: String,
+}
+
+impl GridStoreMetadata {
+    /// Makes a metadata record stamped with the current time.
+    pub fn new(options: GridStoreOptions, source: &str) -> Self {
+        let build_timestamp =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        GridStoreMetadata { options, build_timestamp, source: source.to_owned() }
+    }
+
+    /// Reads the metadata record out of a store; stores built before metadata existed don't have
+    /// one.
+    pub fn read_from(storage: &dyn GridStorage) -> Result<Option<Self>, Error> {
+        match storage.get(METADATA_KEY)? {
+            Some(value) => Ok(Some(serde_json::from_slice(value.as_ref())?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn write_to<W: GridStorageWriter>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.put(METADATA_KEY, &serde_json::to_vec(self)?)
+    }
+}
+
+#[test]
+fn overrides_test() {
+    let built = GridStoreOptions { zoom: 14, type_id: 3, ..GridStoreOptions::default() };
+
+    let unchanged = GridStoreOptionOverrides::default().apply_to(built.clone());
+    assert_eq!(unchanged, built, "empty overrides keep every recorded option");
+
+    let overrides = GridStoreOptionOverrides {
+        coalesce_radius: Some(200.),
+        max_score: Some(1.),
+        ..GridStoreOptionOverrides::default()
+    };
+    let changed = overrides.apply_to(built);
+    assert_eq!(changed.zoom, 14);
+    assert_eq!(changed.type_id, 3);
+    assert_eq!(changed.coalesce_radius, 200.);
+    assert_eq!(changed.max_score, 1.);
+}
@@ -1,17 +1,38 @@
 mod builder;
 mod coalesce;
 mod common;
+mod feature_index;
 mod gridstore_format;
+mod merge;
+mod metadata;
+mod patch;
+mod shared_values;
 mod spatial;
+mod spill;
 mod stackable;
+mod stats;
+mod storage;
 mod store;
+mod tile_index;
+mod verify;
 
 pub use builder::*;
 pub use coalesce::{coalesce, collapse_phrasematches, stack_and_coalesce, tree_coalesce};
 pub use common::*;
+pub use gridstore_format::DecodeError;
+pub use merge::MergeError;
+pub use metadata::{
+    FormatFeatures, GridStoreMetadata, GridStoreOptionOverrides, GridStoreOptions,
+    BASE_FORMAT_VERSION, FORMAT_VERSION, MIN_FORMAT_VERSION,
+};
+pub use patch::{Changeset, ChangesetFeature, ChangesetPhrase, PatchReport};
 pub use spatial::global_bbox_for_zoom;
 pub use stackable::stackable;
+pub use stats::{GridStoreStats, Histogram, LargeRecord, RecordTypeStats};
+pub use storage::*;
 pub use store::*;
+pub use tile_index::{features_covering, CoveringFeatures};
+pub use verify::{VerifyIssue, VerifyReport};
 
 #[cfg(test)]
 mod tests {
@@ -20,6 +41,18 @@ mod tests {
     use once_cell::sync::Lazy;
     use std::collections::BTreeMap;
 
+    /// Finishes a builder into memory and opens it at the zoom and type_id given to its
+    /// `set_metadata`, or at zoom 6 and type_id 0 if it has no metadata
+    fn memory_store(builder: GridStoreBuilder) -> GridStore {
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+        let (zoom, type_id) = match GridStoreMetadata::read_from(&storage).unwrap() {
+            Some(metadata) => (metadata.options.zoom, metadata.options.type_id),
+            None => (6, 0),
+        };
+        GridStore::from_storage(storage, zoom, type_id, 0., vec![], 0.).unwrap()
+    }
+
     #[test]
     fn combined_test() {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
@@ -37,7 +70,7 @@ mod tests {
         builder.finish().unwrap();
 
         let reader = GridStore::new(directory.path()).unwrap();
-        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
 
         entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
         assert_eq!(
@@ -83,8 +116,12 @@ mod tests {
         let reader = GridStore::new(directory.path()).unwrap();
 
         for id in 0..=2 {
-            let entries: Vec<_> =
-                reader.get(&GridKey { phrase_id: id, lang_set: 1 }).unwrap().unwrap().collect();
+            let entries: Vec<_> = reader
+                .get(&GridKey { phrase_id: id, lang_set: 1 })
+                .unwrap()
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
             assert_eq!(id, entries[0].id);
         }
     }
@@ -106,7 +143,7 @@ mod tests {
         builder.finish().unwrap();
 
         let reader = GridStore::new(directory.path()).unwrap();
-        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
 
         entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
         assert_eq!(
@@ -132,7 +169,7 @@ mod tests {
         builder.finish().unwrap();
 
         let reader = GridStore::new(directory.path()).unwrap();
-        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
 
         // Results come back morton order. Maybe we should implement a custom partial_cmp
         assert_eq!(record[0], entries[1], "expected first result");
@@ -156,7 +193,7 @@ mod tests {
         builder.finish().unwrap();
 
         let reader = GridStore::new(directory.path()).unwrap();
-        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect();
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
 
         entries.sort_by(|a, b| b.partial_cmp(a).unwrap());
         assert_eq!(
@@ -165,6 +202,1039 @@ mod tests {
         );
     }
 
+    #[test]
+    fn in_memory_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut rocksdb_builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let mut memory_builder = GridStoreBuilder::new_in_memory();
+
+        for phrase_id in 0..4 {
+            let key = GridKey { phrase_id, lang_set: 1 };
+            let entries = vec![
+                GridEntry { id: phrase_id, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+                GridEntry { id: 9, x: 2, y: 3, relev: 0.8, score: 4, source_phrase_hash: 1 },
+            ];
+            rocksdb_builder.insert(&key, entries.clone()).expect("Unable to insert record");
+            memory_builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        rocksdb_builder.load_bin_boundaries(vec![0, 2, 4]).unwrap();
+        memory_builder.load_bin_boundaries(vec![0, 2, 4]).unwrap();
+
+        rocksdb_builder.finish().unwrap();
+        let mut storage = MemoryStorage::new();
+        memory_builder.finish_to(&mut storage).unwrap();
+
+        let rocksdb_reader = GridStore::new(directory.path()).unwrap();
+        let memory_reader =
+            GridStore::from_storage(storage, 6, 0, 0.0, vec![[0, 0, 63, 63]], 0.0).unwrap();
+        assert_eq!(memory_reader.bin_boundaries, rocksdb_reader.bin_boundaries);
+
+        let rocksdb_records: Vec<_> = rocksdb_reader.iter().map(|r| r.unwrap()).collect();
+        let memory_records: Vec<_> = memory_reader.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(memory_records.len(), 4);
+        assert_eq!(memory_records, rocksdb_records, "both backends hold the same records");
+
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 2, end: 4 }, lang_set: 1 };
+        let rocksdb_matches: Vec<_> = rocksdb_reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let memory_matches: Vec<_> = memory_reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(memory_matches.len(), 3, "prefix bin merges the shared grid");
+        assert_eq!(memory_matches, rocksdb_matches, "both backends match the same grids");
+    }
+
+    #[test]
+    fn single_file_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let rocksdb_path = directory.path().join("rocksdb");
+        let single_file_path = directory.path().join("store.gridstore");
+        let mut rocksdb_builder = GridStoreBuilder::new(&rocksdb_path).unwrap();
+        let mut single_file_builder = GridStoreBuilder::new(&single_file_path).unwrap();
+        single_file_builder.set_storage_format(StorageFormat::SingleFile);
+
+        for phrase_id in 0..6 {
+            for lang_set in &[1, 3, std::u128::MAX] {
+                let key = GridKey { phrase_id, lang_set: *lang_set };
+                let entries = vec![GridEntry {
+                    id: phrase_id,
+                    x: phrase_id as u32,
+                    y: 2,
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                }];
+                rocksdb_builder.insert(&key, entries.clone()).expect("Unable to insert record");
+                single_file_builder.insert(&key, entries).expect("Unable to insert record");
+            }
+        }
+        rocksdb_builder.load_bin_boundaries(vec![0, 3, 6]).unwrap();
+        single_file_builder.load_bin_boundaries(vec![0, 3, 6]).unwrap();
+        rocksdb_builder.finish().unwrap();
+        single_file_builder.finish().unwrap();
+        assert!(single_file_path.is_file(), "single-file stores are one file");
+
+        let rocksdb_reader = GridStore::new(&rocksdb_path).unwrap();
+        let single_file_reader = GridStore::new(&single_file_path).unwrap();
+        assert_eq!(single_file_reader.bin_boundaries, rocksdb_reader.bin_boundaries);
+
+        let rocksdb_records: Vec<_> = rocksdb_reader.iter().map(|r| r.unwrap()).collect();
+        let single_file_records: Vec<_> = single_file_reader.iter().map(|r| r.unwrap()).collect();
+        assert_eq!(single_file_records.len(), 18);
+        assert_eq!(single_file_records, rocksdb_records);
+
+        for match_phrase in vec![
+            MatchPhrase::Exact(4),
+            MatchPhrase::Range { start: 3, end: 6 },
+            MatchPhrase::Range { start: 1, end: 5 },
+        ] {
+            let search_key = MatchKey { match_phrase, lang_set: 2 };
+            let rocksdb_matches: Vec<_> = rocksdb_reader
+                .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let single_file_matches: Vec<_> = single_file_reader
+                .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            assert!(!single_file_matches.is_empty());
+            assert_eq!(single_file_matches, rocksdb_matches);
+        }
+    }
+
+    #[test]
+    fn metadata_test() {
+        let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let mut builder = GridStoreBuilder::new(directory.path()).unwrap();
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let entries =
+            vec![GridEntry { id: 1, x: 1000, y: 1000, relev: 1., score: 1, source_phrase_hash: 0 }];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        let options = GridStoreOptions {
+            zoom: 14,
+            type_id: 2,
+            coalesce_radius: 200.,
+            bboxes: global_bbox_for_zoom(14),
+            max_score: 1.,
+        };
+        builder.set_metadata(options.clone(), "addresses.geojson");
+        builder.finish().unwrap();
+
+        let reader = GridStore::new(directory.path()).unwrap();
+        assert_eq!((reader.zoom, reader.type_id), (14, 2), "options come from the metadata");
+        assert_eq!(reader.coalesce_radius, 200.);
+        let metadata = reader.metadata.clone().unwrap();
+        assert_eq!(metadata.options, options);
+        assert_eq!(metadata.source, "addresses.geojson");
+        assert!(metadata.build_timestamp > 0);
+
+        let overrides = GridStoreOptionOverrides {
+            coalesce_radius: Some(50.),
+            ..GridStoreOptionOverrides::default()
+        };
+        let reader = GridStore::open(directory.path(), &overrides).unwrap();
+        assert_eq!(reader.coalesce_radius, 50., "overridden option");
+        assert_eq!(reader.max_score, 1., "recorded option");
+        drop(reader);
+
+        let wrong_zoom =
+            GridStore::new_with_options(directory.path(), 6, 2, 200., vec![[0, 0, 63, 63]], 1.);
+        match wrong_zoom.unwrap_err().downcast::<StoreError>() {
+            Ok(StoreError::ZoomMismatch { built: 14, requested: 6 }) => (),
+            other => panic!("expected a zoom mismatch, got {:?}", other),
+        }
+        let overrides =
+            GridStoreOptionOverrides { zoom: Some(12), ..GridStoreOptionOverrides::default() };
+        assert!(GridStore::open(directory.path(), &overrides).is_err());
+
+        // stores without metadata open with whatever they're given
+        let mut storage = MemoryStorage::new();
+        GridStoreBuilder::new_in_memory().finish_to(&mut storage).unwrap();
+        let reader = GridStore::from_storage(storage, 12, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(reader.zoom, 12);
+        assert!(reader.metadata.is_none());
+    }
+
+    #[test]
+    fn format_version_test() {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let entries =
+            vec![GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        builder.insert(&key, entries.clone()).expect("Unable to insert record");
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+
+        // without any optional features, stores are written as the version older readers know
+        let reader = GridStore::from_storage(storage.clone(), 6, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(reader.format_version, BASE_FORMAT_VERSION);
+        assert_eq!(storage.get(b"~FORMAT").unwrap().unwrap().as_ref(), &1u32.to_le_bytes());
+
+        // stores from before versioning have no record, and are read as version 1
+        let mut unversioned = MemoryStorage::new();
+        for (key, value) in storage.iter_from(&[]).map(Result::unwrap) {
+            if &key[..] != b"~FORMAT" {
+                unversioned.put(&key, value.as_ref()).unwrap();
+            }
+        }
+        let reader = GridStore::from_storage(unversioned, 6, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(reader.format_version, 1);
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(record, entries);
+
+        let mut from_the_future = storage.clone();
+        from_the_future.put(b"~FORMAT", &(FORMAT_VERSION + 1).to_le_bytes()).unwrap();
+        let result = GridStore::from_storage(from_the_future, 6, 0, 0., vec![], 0.);
+        match result.unwrap_err().downcast::<StoreError>() {
+            Ok(StoreError::UnsupportedFormatVersion { found, .. }) => {
+                assert_eq!(found, FORMAT_VERSION + 1)
+            }
+            other => panic!("expected an unsupported version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn high_precision_test() {
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 0.55, score: 200, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.9, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 3, y: 1, relev: 0.55, score: 201, source_phrase_hash: 0 },
+        ];
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder
+            .set_format_features(FormatFeatures {
+                high_precision: true,
+                ..FormatFeatures::default()
+            })
+            .unwrap();
+        builder.insert(&key, entries).unwrap();
+        assert!(
+            builder.set_format_features(FormatFeatures::default()).is_err(),
+            "features are fixed once there's data"
+        );
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+
+        let reader = GridStore::from_storage(storage.clone(), 6, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(
+            reader.format_features,
+            FormatFeatures { high_precision: true, ..FormatFeatures::default() }
+        );
+        assert_eq!(reader.format_version, FORMAT_VERSION);
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        let quantized = |relev: f64| (relev * 255.).round() / 255.;
+        assert_eq!(
+            record.iter().map(|entry| (entry.id, entry.relev, entry.score)).collect::<Vec<_>>(),
+            vec![(2, quantized(0.9), 3), (3, quantized(0.55), 201), (1, quantized(0.55), 200)],
+            "sorted by relev, then score"
+        );
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "high-precision store verifies: {:?}", report.issues);
+
+        // a plain store merged in takes on the high-precision encoding
+        let mut plain = GridStoreBuilder::new_in_memory();
+        let plain_key = GridKey { phrase_id: 2, lang_set: 1 };
+        plain
+            .insert(
+                &plain_key,
+                vec![GridEntry { id: 4, x: 4, y: 4, relev: 0.8, score: 7, source_phrase_hash: 0 }],
+            )
+            .unwrap();
+        let plain = memory_store(plain);
+        assert_eq!(plain.format_features, FormatFeatures::default());
+
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.merge_stores(&[&plain, &reader]).unwrap();
+        let merged = memory_store(builder);
+        assert!(merged.format_features.high_precision);
+        let record: Vec<_> =
+            merged.get(&plain_key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!((record[0].relev, record[0].score), (quantized(0.8), 7));
+        assert!(merged.verify().unwrap().is_ok());
+
+        // old readers see a version they don't know, and unknown feature bits are refused
+        let mut unknown_bits = storage.clone();
+        let mut format = FORMAT_VERSION.to_le_bytes().to_vec();
+        format.extend_from_slice(&(1u32 << 31).to_le_bytes());
+        unknown_bits.put(b"~FORMAT", &format).unwrap();
+        assert!(GridStore::from_storage(unknown_bits, 6, 0, 0., vec![], 0.).is_err());
+    }
+
+    #[test]
+    fn wide_ids_test() {
+        let big_id = u32::MAX;
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let entries = vec![
+            GridEntry { id: big_id, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0xab },
+            GridEntry { id: 1 << 24, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 7, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 2 },
+        ];
+
+        let mut narrow = GridStoreBuilder::new_in_memory();
+        assert!(narrow.insert(&key, entries.clone()).is_err(), "ids need more than 24 bits");
+
+        let spill_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let wide_ids = FormatFeatures { wide_ids: true, ..FormatFeatures::default() };
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.set_format_features(wide_ids).unwrap();
+        builder.set_spill_dir(spill_dir.path());
+        builder.set_memory_budget(1);
+        builder.set_feature_index(true);
+        builder.set_tile_index(true);
+        builder.insert(&key, entries.clone()).unwrap();
+        builder.load_bin_boundaries(vec![0]).unwrap();
+
+        let reader = memory_store(builder);
+        assert_eq!(reader.format_features, wide_ids);
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(record, entries, "ids and hashes survive spilling, encoding and decoding");
+
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 0, end: 2 }, lang_set: 1 };
+        let matched: Vec<_> = reader
+            .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+            .unwrap()
+            .map(|entry| entry.unwrap().grid_entry.id)
+            .collect();
+        assert_eq!(matched, vec![big_id, 1 << 24, 7], "read through the prefix bin");
+        assert_eq!(reader.phrases_for_feature(big_id).unwrap(), vec![key.clone()]);
+        assert_eq!(reader.features_at(1, 1).unwrap(), vec![1 << 24, big_id]);
+        assert_eq!(reader.stats().unwrap().ids_per_phrase.total, 3);
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "wide-id store verifies: {:?}", report.issues);
+
+        // wide ids can be renumbered past 24 bits, and a narrow store merged in takes them on
+        let mut renumbered = GridStoreBuilder::new_in_memory();
+        renumbered.set_format_features(wide_ids).unwrap();
+        let small =
+            vec![GridEntry { id: 1, x: 3, y: 3, relev: 1., score: 1, source_phrase_hash: 0 }];
+        renumbered.insert(&GridKey { phrase_id: 2, lang_set: 1 }, small.clone()).unwrap();
+        renumbered.renumber_features(&[0, 1 << 30]).unwrap();
+        let renumbered = memory_store(renumbered);
+        let ids: Vec<_> =
+            renumbered.iter().flat_map(|record| record.unwrap().1).map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![1 << 30]);
+
+        let mut narrow = GridStoreBuilder::new_in_memory();
+        narrow.insert(&GridKey { phrase_id: 3, lang_set: 1 }, small).unwrap();
+        let narrow = memory_store(narrow);
+        let mut merged = GridStoreBuilder::new_in_memory();
+        merged.merge_stores(&[&narrow, &renumbered]).unwrap();
+        let merged = memory_store(merged);
+        assert!(merged.format_features.wide_ids);
+        let ids: Vec<_> =
+            merged.iter().flat_map(|record| record.unwrap().1).map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![1 << 30, 1]);
+    }
+
+    #[test]
+    fn wide_coords_test() {
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let far = (200_000, 150_000);
+        let entries = vec![
+            GridEntry { id: 1, x: far.0, y: far.1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 3, y: 5, relev: 1., score: 1, source_phrase_hash: 0 },
+        ];
+        let options = GridStoreOptions { zoom: 18, ..GridStoreOptions::default() };
+
+        let mut narrow = GridStoreBuilder::new_in_memory();
+        assert!(narrow.insert(&key, entries.clone()).is_err(), "coords need more than 16 bits");
+        narrow.set_metadata(options.clone(), "z18.geojson");
+        let small = vec![entries[1].clone()];
+        narrow.insert(&key, small.clone()).unwrap();
+        match narrow.finish_to(&mut MemoryStorage::new()).unwrap_err().downcast::<BuildError>() {
+            Ok(BuildError::UnsupportedZoom { zoom: 18, max_supported: 16 }) => (),
+            other => panic!("expected an unsupported zoom error, got {:?}", other),
+        }
+        let mut narrow = GridStoreBuilder::new_in_memory();
+        narrow.insert(&key, small).unwrap();
+        let mut storage = MemoryStorage::new();
+        narrow.finish_to(&mut storage).unwrap();
+        match GridStore::from_storage(storage, 18, 0, 0., vec![], 0.)
+            .unwrap_err()
+            .downcast::<StoreError>()
+        {
+            Ok(StoreError::UnsupportedZoom { zoom: 18, max_supported: 16 }) => (),
+            other => panic!("expected an unsupported zoom error, got {:?}", other),
+        }
+
+        let spill_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+        let wide_coords = FormatFeatures { wide_coords: true, ..FormatFeatures::default() };
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.set_format_features(wide_coords).unwrap();
+        builder.set_metadata(options, "z18.geojson");
+        builder.set_spill_dir(spill_dir.path());
+        builder.set_memory_budget(1);
+        builder.set_tile_index(true);
+        assert!(
+            builder.insert(&key, vec![GridEntry { x: 1 << 18, ..entries[1].clone() }]).is_err(),
+            "still has to be inside zoom 18"
+        );
+        builder.insert(&key, entries.clone()).unwrap();
+        builder
+            .compact_append(&GridKey { phrase_id: 2, lang_set: 1 }, 0.8, 2, 3, 0, &[far])
+            .unwrap();
+        builder.load_bin_boundaries(vec![0]).unwrap();
+
+        let reader = memory_store(builder);
+        assert_eq!(reader.format_features, wide_coords);
+        let record: Vec<_> = reader.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(record, entries, "coords survive spilling, encoding and decoding");
+
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 0, end: 3 }, lang_set: 1 };
+        let matching = |match_opts: &MatchOpts| -> Vec<(u32, u32, u32)> {
+            reader
+                .streaming_get_matching(&search_key, match_opts, std::usize::MAX)
+                .unwrap()
+                .map(|entry| entry.unwrap().grid_entry)
+                .map(|grid| (grid.id, grid.x, grid.y))
+                .collect()
+        };
+        let bbox = MatchOpts {
+            bbox: Some([far.0 - 10, far.1 - 10, far.0 + 10, far.1 + 10]),
+            zoom: 18,
+            ..MatchOpts::default()
+        };
+        assert_eq!(matching(&bbox), vec![(1, far.0, far.1), (3, far.0, far.1)]);
+        let proximity =
+            MatchOpts { proximity: Some([far.0, far.1 + 1]), zoom: 18, ..MatchOpts::default() };
+        assert_eq!(matching(&proximity)[0], (1, far.0, far.1), "nearest first");
+        assert_eq!(matching(&proximity).len(), 3);
+        // a zoom 16 bbox covering the far tile is scaled to zoom 18
+        let z16 = MatchOpts {
+            bbox: Some([far.0 >> 2, far.1 >> 2, far.0 >> 2, far.1 >> 2]),
+            zoom: 16,
+            ..MatchOpts::default()
+        };
+        assert_eq!(matching(&z16.adjust_to_zoom(18)).len(), 2);
+
+        assert_eq!(reader.features_at(far.0, far.1).unwrap(), vec![1, 3]);
+        assert_eq!(reader.features_at(3, 5).unwrap(), vec![2]);
+        let covering = features_covering(&[&reader], 16, far.0 >> 2, far.1 >> 2).unwrap();
+        assert_eq!(covering[0].ids, vec![1, 3], "found from the parent tile");
+        assert_eq!(reader.stats().unwrap().extent, Some([3, 5, far.0, far.1]));
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "wide-coord store verifies: {:?}", report.issues);
+    }
+
+    #[test]
+    fn packed_vectors_test() {
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        // a 10x10 block of coords, each with a couple of features, spread over two relevs
+        let mut entries = Vec::new();
+        for x in 0..10 {
+            for y in 0..10 {
+                let id = x * 10 + y + 1;
+                let relev = if x < 5 { 1. } else { 0.8 };
+                for &id in &[id, id + 1000] {
+                    entries.push(GridEntry { id, x, y, relev, score: 1, source_phrase_hash: 0 });
+                }
+            }
+        }
+        let build = |features: FormatFeatures| {
+            let spill_dir: tempfile::TempDir = tempfile::tempdir().unwrap();
+            let mut builder = GridStoreBuilder::new_in_memory();
+            builder.set_format_features(features).unwrap();
+            builder.set_spill_dir(spill_dir.path());
+            builder.set_memory_budget(1);
+            builder.set_tile_index(true);
+            builder.insert(&key, entries.clone()).unwrap();
+            builder.insert(&GridKey { phrase_id: 2, lang_set: 1 }, entries[..2].to_vec()).unwrap();
+            builder.load_bin_boundaries(vec![0]).unwrap();
+            memory_store(builder)
+        };
+        let packed_vectors = FormatFeatures { packed_vectors: true, ..FormatFeatures::default() };
+        let plain = build(FormatFeatures::default());
+        let reader = build(packed_vectors);
+        assert_eq!(reader.format_features, packed_vectors);
+
+        let get = |store: &GridStore| -> Vec<GridEntry> {
+            store.get(&key).unwrap().unwrap().collect::<Result<_, _>>().unwrap()
+        };
+        assert_eq!(get(&reader), get(&plain), "packed records decode to the same entries");
+        assert_eq!(get(&reader).len(), 200);
+
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 0, end: 3 }, lang_set: 1 };
+        let matching = |store: &GridStore, match_opts: &MatchOpts| -> Vec<(u32, u32, u32)> {
+            store
+                .streaming_get_matching(&search_key, match_opts, std::usize::MAX)
+                .unwrap()
+                .map(|entry| entry.unwrap().grid_entry)
+                .map(|grid| (grid.id, grid.x, grid.y))
+                .collect()
+        };
+        let bbox = MatchOpts { bbox: Some([2, 3, 7, 4]), zoom: 6, ..MatchOpts::default() };
+        assert_eq!(matching(&reader, &bbox).len(), 24);
+        assert_eq!(matching(&reader, &bbox), matching(&plain, &bbox));
+        let proximity = MatchOpts { proximity: Some([6, 6]), zoom: 6, ..MatchOpts::default() };
+        assert_eq!(matching(&reader, &proximity), matching(&plain, &proximity));
+        let both = MatchOpts { bbox: Some([2, 3, 7, 4]), ..proximity };
+        assert_eq!(matching(&reader, &both), matching(&plain, &both));
+
+        assert_eq!(reader.features_at(3, 4).unwrap(), vec![35, 1035]);
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "packed store verifies: {:?}", report.issues);
+        let (stats, plain_stats) = (reader.stats().unwrap(), plain.stats().unwrap());
+        assert_eq!(stats.ids_per_phrase.total, plain_stats.ids_per_phrase.total);
+        assert_eq!(stats.extent, Some([0, 0, 9, 9]));
+        assert!(stats.total_bytes < plain_stats.total_bytes, "packing makes the store smaller");
+
+        // merging a plain store into a packed one repacks its records
+        let other = GridKey { phrase_id: 3, lang_set: 1 };
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder
+            .insert(
+                &other,
+                vec![GridEntry {
+                    id: 5000,
+                    x: 1,
+                    y: 1,
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                }],
+            )
+            .unwrap();
+        builder.load_bin_boundaries(vec![0]).unwrap();
+        let unpacked = memory_store(builder);
+        let mut merged = GridStoreBuilder::new_in_memory();
+        merged.merge_stores(&[&unpacked, &reader]).unwrap();
+        let merged = memory_store(merged);
+        assert!(merged.format_features.packed_vectors);
+        assert_eq!(get(&merged), get(&plain));
+        assert_eq!(merged.get(&other).unwrap().unwrap().count(), 1);
+        assert!(merged.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn shared_values_test() {
+        let key = |phrase_id, lang_set| GridKey { phrase_id, lang_set };
+        let entries: Vec<GridEntry> = (1..20)
+            .map(|id| GridEntry { id, x: id, y: 3, relev: 1., score: 1, source_phrase_hash: 0 })
+            .collect();
+        let tiny =
+            vec![GridEntry { id: 40, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 }];
+        // phrases 1 and 4 and phrase 2 in another language are synonyms; 5 and 6 are too small
+        // to be worth sharing
+        let records = vec![
+            (key(1, 1), entries.clone()),
+            (key(2, 2), entries.clone()),
+            (key(3, 1), entries[1..].to_vec()),
+            (key(4, 1), entries.clone()),
+            (key(5, 1), tiny.clone()),
+            (key(6, 1), tiny.clone()),
+        ];
+        let build = |features: FormatFeatures, records: &[(GridKey, Vec<GridEntry>)]| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            builder.set_format_features(features).unwrap();
+            for (key, entries) in records {
+                builder.insert(key, entries.clone()).unwrap();
+            }
+            builder.load_bin_boundaries(vec![0, 3]).unwrap();
+            builder
+        };
+        let shared_values = FormatFeatures { shared_values: true, ..FormatFeatures::default() };
+        let mut plain = MemoryStorage::new();
+        let plain_report =
+            build(FormatFeatures::default(), &records).finish_to(&mut plain).unwrap();
+        let plain = GridStore::from_storage(plain, 6, 0, 0., vec![], 0.).unwrap();
+        let mut storage = MemoryStorage::new();
+        let report = build(shared_values, &records).finish_to(&mut storage).unwrap();
+        assert_eq!(plain_report.shared_values, 0);
+        assert_eq!(report.shared_values, 2, "phrases 2 and 4 point to phrase 1");
+        assert!(report.bytes_written < plain_report.bytes_written);
+        for record in &report.largest_records {
+            let mut db_key = Vec::new();
+            record.key.write_to(record.marker, &mut db_key).unwrap();
+            let written = storage.get(&db_key).unwrap().unwrap().as_ref().len();
+            assert_eq!(record.bytes, written, "{:?} is reported as written", record.key);
+        }
+        let reader = GridStore::from_storage(storage.clone(), 6, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(reader.format_features, shared_values);
+
+        let get = |store: &GridStore, key: &GridKey| -> Vec<GridEntry> {
+            store.get(key).unwrap().unwrap().collect::<Result<_, _>>().unwrap()
+        };
+        for (key, _) in &records {
+            assert_eq!(get(&reader, key), get(&plain, key), "{:?} reads back the same", key);
+        }
+        let matching = |store: &GridStore, match_key: &MatchKey| -> Vec<u32> {
+            store
+                .streaming_get_matching(match_key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .map(|entry| entry.unwrap().grid_entry.id)
+                .collect()
+        };
+        for match_phrase in &[MatchPhrase::Exact(4), MatchPhrase::Range { start: 0, end: 7 }] {
+            let match_key = MatchKey { match_phrase: match_phrase.clone(), lang_set: 2 };
+            assert_eq!(matching(&reader, &match_key), matching(&plain, &match_key));
+        }
+        let all = |store: &GridStore| -> Vec<(GridKey, Vec<GridEntry>)> {
+            store.iter().collect::<Result<_, _>>().unwrap()
+        };
+        assert_eq!(all(&reader), all(&plain));
+
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "shared store verifies: {:?}", report.issues);
+        assert_eq!(report.phrase_records, 6);
+        let (stats, plain_stats) = (reader.stats().unwrap(), plain.stats().unwrap());
+        assert_eq!(stats.shared_values, 2);
+        assert_eq!(stats.ids_per_phrase, plain_stats.ids_per_phrase, "shared records count twice");
+        assert!(stats.total_bytes < plain_stats.total_bytes);
+
+        // a reference to a record that isn't there
+        let mut db_key = Vec::new();
+        key(4, 1).write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+        let mut broken = storage.clone();
+        broken.put(&db_key, &shared_values::encode_reference(&key(9, 1)).unwrap()).unwrap();
+        let broken = GridStore::from_storage(broken, 6, 0, 0., vec![], 0.).unwrap();
+        match broken.get(&key(4, 1)).err().map(|e| e.downcast::<StoreError>()) {
+            Some(Ok(StoreError::BrokenReference { .. })) => (),
+            other => panic!("expected a broken reference error, got {:?}", other.map(|_| ())),
+        }
+        let issues = broken.verify().unwrap().issues;
+        let broken_reference =
+            VerifyIssue::BrokenReference { marker: TypeMarker::SinglePhrase, key: key(4, 1) };
+        assert!(issues.contains(&broken_reference), "{:?}", issues);
+
+        // phrases sharing a record that's patched keep what it used to hold
+        let changeset = Changeset {
+            remove: vec![],
+            add: vec![ChangesetFeature {
+                id: 99,
+                phrases: vec![ChangesetPhrase {
+                    key: key(1, 1),
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                    coords: vec![(7, 7)],
+                }],
+            }],
+        };
+        let mut patched = MemoryStorage::new();
+        reader.patch_to(&changeset, &mut patched).unwrap();
+        let patched = GridStore::from_storage(patched, 6, 0, 0., vec![], 0.).unwrap();
+        assert_eq!(get(&patched, &key(1, 1)).len(), 20);
+        let original = get(&reader, &key(1, 1));
+        assert_eq!(get(&patched, &key(2, 2)), original);
+        assert_eq!(get(&patched, &key(4, 1)), original);
+        assert!(patched.verify().unwrap().is_ok());
+
+        // merged stores share values if either of their inputs did
+        let other_entries: Vec<GridEntry> =
+            entries.iter().map(|entry| GridEntry { id: entry.id + 100, ..entry.clone() }).collect();
+        let other = memory_store(build(
+            FormatFeatures::default(),
+            &[(key(7, 1), other_entries.clone()), (key(8, 1), other_entries)],
+        ));
+        let mut merged = GridStoreBuilder::new_in_memory();
+        merged.merge_stores(&[&reader, &other]).unwrap();
+        let mut storage = MemoryStorage::new();
+        let report = merged.finish_to(&mut storage).unwrap();
+        assert_eq!(report.shared_values, 3, "phrase 8 points to phrase 7 too");
+        let merged = GridStore::from_storage(storage, 6, 0, 0., vec![], 0.).unwrap();
+        assert!(merged.format_features.shared_values);
+        assert_eq!(get(&merged, &key(4, 1)), original);
+        assert_eq!(get(&merged, &key(8, 1)), get(&other, &key(7, 1)));
+        assert!(merged.verify().unwrap().is_ok());
+    }
+
+    #[test]
+    fn malformed_record_test() {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let entries = vec![
+            GridEntry { id: 1, x: 1, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+            GridEntry { id: 2, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+            GridEntry { id: 3, x: 2, y: 1, relev: 0.8, score: 3, source_phrase_hash: 0 },
+        ];
+        builder.insert(&key, entries).expect("Unable to insert record");
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+
+        let mut db_key = Vec::new();
+        key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+        let value = storage.get(&db_key).unwrap().unwrap().as_ref().to_vec();
+        let with_value = |value: &[u8]| {
+            let mut corrupt = storage.clone();
+            corrupt.put(&db_key, value).unwrap();
+            GridStore::from_storage(corrupt, 6, 0, 0., vec![], 0.).unwrap()
+        };
+        let search_key = MatchKey { match_phrase: MatchPhrase::Exact(1), lang_set: 1 };
+
+        let reader = with_value(&[0xff, 0xff, 0xff, 0xff]);
+        assert!(reader.get(&key).is_err(), "root pointing past the end of the record");
+        assert!(reader.streaming_get_matching(&search_key, &MatchOpts::default(), 10).is_err());
+
+        // an id list claiming far more ids than the record holds is only found when it's read
+        let mut long_ids = value.clone();
+        long_ids[0] = 0x7f;
+        let reader = with_value(&long_ids);
+        assert!(reader.get(&key).unwrap().unwrap().any(|entry| entry.is_err()));
+        assert!(reader.iter().any(|record| record.is_err()));
+
+        // no truncation should panic, whether it's caught up front or partway through
+        for len in 0..value.len() {
+            let reader = with_value(&value[..len]);
+            if let Ok(Some(record)) = reader.get(&key) {
+                record.for_each(drop);
+            }
+            let matches = reader.streaming_get_matching(&search_key, &MatchOpts::default(), 10);
+            if let Ok(matches) = matches {
+                matches.for_each(drop);
+            }
+        }
+    }
+
+    #[test]
+    fn verify_test() {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        for phrase_id in 0..6 {
+            for lang_set in &[1, 2] {
+                let key = GridKey { phrase_id, lang_set: *lang_set };
+                let entries = vec![
+                    GridEntry {
+                        id: phrase_id,
+                        x: 1,
+                        y: 1,
+                        relev: 1.,
+                        score: 1,
+                        source_phrase_hash: 0,
+                    },
+                    GridEntry { id: 9, x: 2, y: 3, relev: 0.8, score: 4, source_phrase_hash: 1 },
+                ];
+                builder.insert(&key, entries).expect("Unable to insert record");
+            }
+        }
+        builder.load_bin_boundaries(vec![0, 3]).unwrap();
+        let mut storage = MemoryStorage::new();
+        builder.finish_to(&mut storage).unwrap();
+
+        let reader = GridStore::from_storage(storage.clone(), 6, 0, 0., vec![], 0.).unwrap();
+        let report = reader.verify().unwrap();
+        assert!(report.is_ok(), "freshly-built store verifies: {:?}", report.issues);
+        assert_eq!(report.phrase_records, 12);
+        assert_eq!(report.prefix_bin_records, 4);
+
+        let db_key = |marker: TypeMarker, phrase_id: u32, lang_set: u128| {
+            let mut db_key = Vec::new();
+            GridKey { phrase_id, lang_set }.write_to(marker, &mut db_key).unwrap();
+            db_key
+        };
+        let verify = |storage: MemoryStorage| {
+            GridStore::from_storage(storage, 6, 0, 0., vec![], 0.).unwrap().verify().unwrap().issues
+        };
+
+        // a bin holding only one of its phrases, and a bin that's gone entirely
+        let mut broken_bins = storage.clone();
+        let phrase_value = storage.get(&db_key(TypeMarker::SinglePhrase, 4, 1)).unwrap().unwrap();
+        broken_bins.put(&db_key(TypeMarker::PrefixBin, 3, 1), phrase_value.as_ref()).unwrap();
+        let mut without_bin = MemoryStorage::new();
+        for (key, value) in broken_bins.iter_from(&[]).map(Result::unwrap) {
+            if key.as_ref() != &db_key(TypeMarker::PrefixBin, 0, 2)[..] {
+                without_bin.put(&key, value.as_ref()).unwrap();
+            }
+        }
+        assert_eq!(
+            verify(without_bin),
+            vec![
+                VerifyIssue::MissingPrefixBin { key: GridKey { phrase_id: 0, lang_set: 2 } },
+                VerifyIssue::PrefixBinMismatch { key: GridKey { phrase_id: 3, lang_set: 1 } },
+            ]
+        );
+
+        // a phrase record with its coords in ascending order, and duplicate ids
+        let mut writer = gridstore_format::Writer::new();
+        let ids = writer.write_fixed_vec(&[1u32 << 8, 1u32 << 8]);
+        let coords = writer.write_uniform_vec(&[
+            gridstore_format::Coord { coord: 3, ids },
+            gridstore_format::Coord { coord: 7, ids },
+        ]);
+        let rses =
+            writer.write_var_vec(&[gridstore_format::RelevScore { relev_score: 49, coords }]);
+        writer.write_fixed_scalar(gridstore_format::PhraseRecord { relev_scores: rses });
+        let mut unsorted = storage.clone();
+        unsorted.put(&db_key(TypeMarker::SinglePhrase, 1, 1), &writer.finish()).unwrap();
+        let issues = verify(unsorted);
+        let key = GridKey { phrase_id: 1, lang_set: 1 };
+        let marker = TypeMarker::SinglePhrase;
+        assert!(issues.contains(&VerifyIssue::CoordsOutOfOrder {
+            marker,
+            key: key.clone(),
+            relev_score: 49
+        }));
+        assert!(issues.contains(&VerifyIssue::IdsOutOfOrder {
+            marker,
+            key,
+            relev_score: 49,
+            coord: 3
+        }));
+        assert!(issues.contains(&VerifyIssue::PrefixBinMismatch {
+            key: GridKey { phrase_id: 0, lang_set: 1 }
+        }));
+
+        let mut truncated = storage.clone();
+        truncated.put(&db_key(TypeMarker::SinglePhrase, 5, 2), &[1, 2]).unwrap();
+        assert!(verify(truncated).iter().any(|issue| match issue {
+            VerifyIssue::Undecodable { .. } => true,
+            _ => false,
+        }));
+
+        // phrases below the first boundary go into the first bin
+        let mut builder = GridStoreBuilder::new_in_memory();
+        for phrase_id in 0..8 {
+            let key = GridKey { phrase_id, lang_set: 1 };
+            let entries = vec![GridEntry {
+                id: phrase_id,
+                x: phrase_id,
+                y: 1,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        builder.load_bin_boundaries(vec![3, 6]).unwrap();
+        let report = memory_store(builder).verify().unwrap();
+        assert!(report.is_ok(), "phrases before the first boundary verify: {:?}", report.issues);
+        assert_eq!(report.prefix_bin_records, 2);
+    }
+
+    #[test]
+    fn stats_test() {
+        let mut builder = GridStoreBuilder::new_in_memory();
+        for phrase_id in 0..4 {
+            let key = GridKey { phrase_id, lang_set: 1 };
+            let mut entries = vec![GridEntry {
+                id: phrase_id,
+                x: 1,
+                y: 5,
+                relev: 1.,
+                score: 1,
+                source_phrase_hash: 0,
+            }];
+            // phrase 3 is much bigger than the others
+            if phrase_id == 3 {
+                entries.extend((0..20).map(|id| GridEntry {
+                    id,
+                    x: 10 + id as u32,
+                    y: 2,
+                    relev: 0.8,
+                    score: 4,
+                    source_phrase_hash: 1,
+                }));
+            }
+            builder.insert(&key, entries).expect("Unable to insert record");
+        }
+        builder.load_bin_boundaries(vec![0, 2]).unwrap();
+        let reader = memory_store(builder);
+
+        let stats = reader.stats().unwrap();
+        assert_eq!(stats.records_by_type[&TypeMarker::SinglePhrase].records, 4);
+        assert_eq!(stats.records_by_type[&TypeMarker::PrefixBin].records, 2);
+        assert_eq!(stats.special_records.records, 2, "~BOUNDS and ~FORMAT");
+        let record_bytes: u64 = stats.records_by_type.values().map(|s| s.bytes).sum();
+        assert_eq!(stats.total_bytes, record_bytes + stats.special_records.bytes);
+
+        let relev_score_1_1 = pack_relev_score(1., 1, false);
+        let relev_score_08_4 = pack_relev_score(0.8, 4, false);
+        assert_eq!(stats.entries_by_relev_score[&relev_score_1_1], 4);
+        assert_eq!(stats.entries_by_relev_score[&relev_score_08_4], 20);
+
+        assert_eq!(stats.coords_per_phrase.count, 4);
+        assert_eq!(stats.coords_per_phrase.max, 21);
+        assert_eq!(stats.coords_per_phrase.min, 1);
+        assert_eq!(stats.ids_per_phrase.total, 24);
+        assert_eq!(stats.ids_per_phrase.mean(), 6.);
+        // three phrases with one id, one with 21
+        assert_eq!(stats.ids_per_phrase.buckets, vec![0, 3, 0, 0, 0, 1]);
+
+        assert_eq!(stats.largest_records.len(), 6);
+        assert_eq!(stats.largest_records[0].marker, TypeMarker::PrefixBin);
+        assert_eq!(stats.largest_records[0].key, GridKey { phrase_id: 2, lang_set: 1 });
+        assert_eq!(stats.largest_records[1].key, GridKey { phrase_id: 3, lang_set: 1 });
+        assert!(stats.largest_records[0].bytes > stats.largest_records[1].bytes);
+
+        assert_eq!(stats.extent, Some([1, 2, 29, 5]));
+    }
+
+    #[test]
+    fn overlay_test() {
+        let entry =
+            |id, x, relev, score| GridEntry { id, x, y: x, relev, score, source_phrase_hash: 0 };
+        let build = |records: Vec<(u32, Vec<GridEntry>)>, tombstones: &[u32]| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            for (phrase_id, entries) in records {
+                builder.insert(&GridKey { phrase_id, lang_set: 1 }, entries).unwrap();
+            }
+            builder.add_tombstones(tombstones);
+            memory_store(builder)
+        };
+
+        let base = build(vec![(1, vec![entry(1, 1, 1., 1), entry(2, 2, 1., 3)])], &[]);
+        // moves feature 2 and adds a new feature 3, repeating feature 1 unchanged
+        let delta = build(
+            vec![(1, vec![entry(1, 1, 1., 1), entry(2, 5, 0.8, 3)]), (2, vec![entry(3, 3, 1., 1)])],
+            &[2, 2],
+        );
+        assert_eq!(delta.tombstones, [2].iter().cloned().collect());
+        assert!(base.tombstones.is_empty());
+
+        let mut zoom_14 = GridStoreBuilder::new_in_memory();
+        zoom_14.add_tombstones(&[3]);
+        zoom_14.set_metadata(GridStoreOptions { zoom: 14, ..GridStoreOptions::default() }, "");
+        assert!(
+            build(vec![], &[]).with_overlay(memory_store(zoom_14)).is_err(),
+            "overlays must be at the base's zoom"
+        );
+
+        let store = base.with_overlay(delta).unwrap();
+        let phrase_1 = GridKey { phrase_id: 1, lang_set: 1 };
+        let phrase_2 = GridKey { phrase_id: 2, lang_set: 1 };
+        let merged: Vec<_> =
+            store.get(&phrase_1).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(
+            merged,
+            vec![entry(1, 1, 1., 1), entry(2, 5, 0.8, 3)],
+            "tombstoned base entry is replaced, and the repeated one appears once"
+        );
+        let added: Vec<_> =
+            store.get(&phrase_2).unwrap().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(added, vec![entry(3, 3, 1., 1)]);
+
+        // a second overlay deleting the feature the first one added
+        let store = store.with_overlay(build(vec![], &[3])).unwrap();
+        assert_eq!(store.get(&phrase_2).unwrap().unwrap().count(), 0);
+        assert!(store.get(&GridKey { phrase_id: 3, lang_set: 1 }).unwrap().is_none());
+
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 1, end: 3 }, lang_set: 1 };
+        let matched: Vec<_> = store
+            .streaming_get_matching(&search_key, &MatchOpts::default(), 10)
+            .unwrap()
+            .map(|entry| entry.map(|entry| (entry.grid_entry.id, entry.grid_entry.relev)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(matched, vec![(1, 1.), (2, 0.8)]);
+
+        assert_eq!(store.keys().count(), 1, "keys only covers the base store");
+    }
+
+    #[test]
+    fn feature_index_test() {
+        let entry =
+            |id, relev| GridEntry { id, x: 1, y: 1, relev, score: 1, source_phrase_hash: 0 };
+        let build = |records: Vec<(GridKey, Vec<GridEntry>)>, tombstones: &[u32], indexed| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            for (key, entries) in records {
+                builder.insert(&key, entries).unwrap();
+            }
+            builder.load_bin_boundaries(vec![0, 2]).unwrap();
+            builder.add_tombstones(tombstones);
+            builder.set_feature_index(indexed);
+            memory_store(builder)
+        };
+
+        let key_1 = GridKey { phrase_id: 1, lang_set: 1 };
+        let key_2 = GridKey { phrase_id: 2, lang_set: 2 };
+        let key_3 = GridKey { phrase_id: 3, lang_set: 1 };
+        let records = vec![
+            (key_1.clone(), vec![entry(1, 1.), entry(2, 1.)]),
+            // feature 2 shows up under two relevs, but the phrase is only listed once
+            (key_2.clone(), vec![entry(2, 1.), entry(2, 0.8)]),
+            (key_3.clone(), vec![entry(3, 1.)]),
+        ];
+        let store = build(records.clone(), &[], true);
+        assert_eq!(store.phrases_for_feature(2).unwrap(), vec![key_1.clone(), key_2.clone()]);
+        assert_eq!(store.phrases_for_feature(3).unwrap(), vec![key_3.clone()]);
+        assert_eq!(store.phrases_for_feature(4).unwrap(), vec![], "unknown feature");
+
+        let report = store.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.feature_index_records, 3);
+        assert_eq!(report.phrase_records, 3, "feature index records aren't phrases");
+        assert_eq!(store.keys().count(), 3);
+
+        let unindexed = build(records, &[], false);
+        assert!(unindexed.phrases_for_feature(2).is_err(), "store has no feature index");
+
+        // an overlay that deletes feature 2 and re-adds it under a different phrase, and an empty
+        // one that still has an index
+        let delta = build(vec![(key_3.clone(), vec![entry(2, 1.)])], &[2], true);
+        let store = store.with_overlay(delta).unwrap().with_overlay(build(vec![], &[], true));
+        let store = store.unwrap();
+        assert_eq!(store.phrases_for_feature(2).unwrap(), vec![key_3.clone()]);
+        assert_eq!(store.phrases_for_feature(1).unwrap(), vec![key_1]);
+        let store = store.with_overlay(build(vec![], &[], false)).unwrap();
+        assert!(store.phrases_for_feature(1).is_err(), "an overlay has no feature index");
+    }
+
+    #[test]
+    fn tile_index_test() {
+        let entry = |id, x, y| GridEntry { id, x, y, relev: 1., score: 1, source_phrase_hash: 0 };
+        let build = |entries: Vec<GridEntry>, options: &GridStoreOptions, indexed| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            builder.insert(&GridKey { phrase_id: 1, lang_set: 1 }, entries).unwrap();
+            builder.set_tile_index(indexed);
+            builder.set_metadata(options.clone(), "");
+            builder
+        };
+        let places_options =
+            GridStoreOptions { zoom: 6, type_id: 1, ..GridStoreOptions::default() };
+
+        let places =
+            build(vec![entry(1, 1, 1), entry(1, 2, 2), entry(2, 1, 1)], &places_options, true);
+        let places = memory_store(places);
+        assert_eq!(places.features_at(1, 1).unwrap(), vec![1, 2]);
+        assert_eq!(places.features_at(2, 2).unwrap(), vec![1]);
+        assert!(places.features_at(3, 3).unwrap().is_empty());
+
+        let report = places.verify().unwrap();
+        assert!(report.is_ok(), "{:?}", report.issues);
+        assert_eq!(report.tile_index_records, 2);
+        assert_eq!(report.phrase_records, 1);
+
+        // zoom 6 tile 1/1 covers zoom 14 tiles 256/256 through 511/511
+        let addresses_options = GridStoreOptions { zoom: 14, ..GridStoreOptions::default() };
+        let addresses =
+            build(vec![entry(5, 300, 300), entry(6, 1000, 1000)], &addresses_options, true);
+        let addresses = memory_store(addresses);
+        let stores = vec![places, addresses];
+        assert_eq!(
+            features_covering(&stores, 6, 1, 1).unwrap(),
+            vec![
+                CoveringFeatures { idx: 1, type_id: 0, ids: vec![5] },
+                CoveringFeatures { idx: 0, type_id: 1, ids: vec![1, 2] },
+            ],
+            "ordered by type_id, searching the higher-zoom store across all the child tiles"
+        );
+        assert_eq!(
+            features_covering(&stores, 14, 1000, 1000).unwrap(),
+            vec![CoveringFeatures { idx: 1, type_id: 0, ids: vec![6] }],
+            "the lower-zoom store is searched at the parent tile, where it has nothing"
+        );
+
+        let unindexed = memory_store(build(vec![entry(1, 1, 1)], &places_options, false));
+        assert!(unindexed.features_at(1, 1).is_err(), "store has no tile index");
+
+        let places = stores.into_iter().next().unwrap();
+        let mut delta = build(vec![entry(3, 1, 1)], &places_options, true);
+        delta.add_tombstones(&[2]);
+        let patched = places.with_overlay(memory_store(delta)).unwrap();
+        assert_eq!(patched.features_at(1, 1).unwrap(), vec![1, 3]);
+    }
+
     #[test]
     fn matching_test() {
         let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
@@ -182,10 +1252,10 @@ mod tests {
             for _j in 0..2 {
                 #[cfg_attr(rustfmt, rustfmt::skip)]
                 let entries = vec![
-                    GridEntry { id: i, x: (2 * i) as u16, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
-                    GridEntry { id: i + 1, x: ((2 * i) + 1) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
-                    GridEntry { id: i + 2, x: ((2 * i) + 2) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
-                    GridEntry { id: i + 3, x: ((2 * i) + 1) as u16, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                    GridEntry { id: i, x: (2 * i) as u32, y: 1, relev: 1., score: 1, source_phrase_hash: 0 },
+                    GridEntry { id: i + 1, x: ((2 * i) + 1) as u32, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                    GridEntry { id: i + 2, x: ((2 * i) + 2) as u32, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
+                    GridEntry { id: i + 3, x: ((2 * i) + 1) as u32, y: 1, relev: 1., score: 7, source_phrase_hash: 0 },
                 ];
                 i += 4;
 
@@ -210,7 +1280,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -231,7 +1302,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -256,7 +1328,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -281,7 +1354,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -306,7 +1380,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -331,7 +1406,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         assert_eq!(records, []);
 
         let search_key =
@@ -339,7 +1415,8 @@ mod tests {
         let records: Vec<_> = reader
             .streaming_get_matching(&search_key, &MatchOpts::default(), MAX_CONTEXTS)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         assert_eq!(records, []);
 
         let search_key =
@@ -351,7 +1428,8 @@ mod tests {
                 MAX_CONTEXTS,
             )
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -374,7 +1452,8 @@ mod tests {
                 MAX_CONTEXTS,
             )
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         assert_eq!(records.len(), 0, "no matching recods in bbox");
 
         // Search where neither z-order curve or actual x,y overlap with bbox.
@@ -391,7 +1470,8 @@ mod tests {
                 MAX_CONTEXTS,
             )
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         assert_eq!(records.len(), 0, "no matching recods in bbox");
 
         let search_key =
@@ -403,7 +1483,8 @@ mod tests {
                 MAX_CONTEXTS,
             )
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -436,7 +1517,8 @@ mod tests {
                 MAX_CONTEXTS,
             )
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         #[cfg_attr(rustfmt, rustfmt::skip)]
         assert_eq!(
             records,
@@ -490,7 +1572,7 @@ mod tests {
             let key = GridKey { phrase_id: i, lang_set: 1 };
             let entries = vec![GridEntry {
                 id: i,
-                x: i as u16,
+                x: i as u32,
                 y: 1,
                 relev: 1.,
                 score: 1,
@@ -578,11 +1660,13 @@ mod tests {
         let mut records_with_boundaries: Vec<_> = reader_with_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         let mut records_without_boundaries: Vec<_> = reader_without_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
 
         records_with_boundaries.sort_by(|a, b| a.partial_cmp(b).unwrap());
         records_without_boundaries.sort_by(|a, b| a.partial_cmp(b).unwrap());
@@ -593,7 +1677,7 @@ mod tests {
                 grid_entry: GridEntry {
                     relev: 1.0,
                     score: 1,
-                    x: i as u16,
+                    x: i as u32,
                     y: 1,
                     id: i,
                     source_phrase_hash: 0,
@@ -621,11 +1705,13 @@ mod tests {
         let mut records_with_boundaries: Vec<_> = reader_with_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
         let mut records_without_boundaries: Vec<_> = reader_without_boundaries
             .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
             .unwrap()
-            .collect();
+            .collect::<Result<_, _>>()
+            .unwrap();
 
         records_with_boundaries.sort_by(|a, b| a.partial_cmp(b).unwrap());
         records_without_boundaries.sort_by(|a, b| a.partial_cmp(b).unwrap());
@@ -636,7 +1722,7 @@ mod tests {
                 grid_entry: GridEntry {
                     relev: 1.0,
                     score: 1,
-                    x: i as u16,
+                    x: i as u32,
                     y: 1,
                     id: i,
                     source_phrase_hash: 0,
@@ -695,4 +1781,278 @@ mod tests {
         // and so should the starts_with_bc ones
         assert_eq!(results[2], results[3]);
     }
+
+    #[test]
+    fn merge_test() {
+        let entry = |id, x| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: 0 };
+        let key = |phrase_id| GridKey { phrase_id, lang_set: 1 };
+        let build = |records: Vec<(u32, Vec<GridEntry>)>, bounds: Vec<u32>| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            for (phrase_id, entries) in records {
+                builder.insert(&key(phrase_id), entries).unwrap();
+            }
+            builder.load_bin_boundaries(bounds).unwrap();
+            builder.set_feature_index(true);
+            memory_store(builder)
+        };
+
+        let west = build(vec![(1, vec![entry(1, 1)]), (2, vec![entry(2, 2)])], vec![0, 2]);
+        let east = build(vec![(1, vec![entry(3, 3)]), (3, vec![entry(4, 4)])], vec![0, 2]);
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.merge_stores(&[&west, &east]).unwrap();
+        let merged = memory_store(builder);
+
+        let ids = |store: &GridStore, key: &GridKey| -> Vec<u32> {
+            store.get(key).unwrap().unwrap().map(|entry| entry.unwrap().id).collect()
+        };
+        assert_eq!(ids(&merged, &key(1)), vec![3, 1], "entries for a shared key are unioned");
+        assert_eq!(ids(&merged, &key(2)), vec![2]);
+        assert_eq!(ids(&merged, &key(3)), vec![4]);
+        assert_eq!(merged.bin_boundaries, west.bin_boundaries);
+        assert!(merged.verify().unwrap().is_ok(), "prefix bins are rebuilt for the merged records");
+        assert_eq!(merged.phrases_for_feature(4).unwrap(), vec![key(3)]);
+
+        // stores with different bins are merged with the bins of both, and settings the builder
+        // already has are kept
+        let rebinned = build(vec![(3, vec![entry(5, 5)])], vec![0, 3]);
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.set_tile_index(true);
+        builder.set_metadata(GridStoreOptions { zoom: 6, ..GridStoreOptions::default() }, "mine");
+        builder.merge_stores(&[&west, &rebinned]).unwrap();
+        let rebinned_merge = memory_store(builder);
+        assert_eq!(rebinned_merge.bin_boundaries, [0, 2, 3].iter().cloned().collect());
+        assert!(rebinned_merge.verify().unwrap().is_ok());
+        assert_eq!(rebinned_merge.features_at(5, 5).unwrap(), vec![5]);
+        assert_eq!(rebinned_merge.metadata.unwrap().source, "mine");
+
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.set_auto_bins(1 << 20);
+        builder.merge_stores(&[&west, &rebinned]).unwrap();
+        let auto_merge = memory_store(builder);
+        assert_eq!(auto_merge.bin_boundaries, [0].iter().cloned().collect(), "bins are recomputed");
+        let overlapping = build(vec![(4, vec![entry(2, 6)])], vec![0, 2]);
+        assert!(
+            GridStoreBuilder::new_in_memory().merge_stores(&[&west, &overlapping]).is_err(),
+            "the same feature id in two stores is a conflict"
+        );
+        let empty: &[GridStore] = &[];
+        assert!(GridStoreBuilder::new_in_memory().merge_stores(empty).is_err());
+    }
+
+    #[test]
+    fn patch_test() {
+        let entry =
+            |id, x, relev| GridEntry { id, x, y: x, relev, score: 1, source_phrase_hash: 0 };
+        let key = |phrase_id, lang_set| GridKey { phrase_id, lang_set };
+        let build = |records: Vec<(GridKey, Vec<GridEntry>)>, indexed| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            for (key, entries) in records {
+                builder.append(&key, entries).unwrap();
+            }
+            builder.load_bin_boundaries(vec![0, 2]).unwrap();
+            builder.set_feature_index(indexed);
+            builder.set_tile_index(indexed);
+            builder
+        };
+        let contents = |storage: &MemoryStorage| -> Vec<(Vec<u8>, Vec<u8>)> {
+            storage
+                .iter_from(&[])
+                .map(|item| item.unwrap())
+                .map(|(key, value)| (key.to_vec(), value.as_ref().to_vec()))
+                .collect()
+        };
+
+        let before = vec![
+            (key(0, 1), vec![entry(1, 1, 1.), entry(2, 2, 1.)]),
+            (key(1, 1), vec![entry(1, 1, 0.8)]),
+            (key(1, 256), vec![entry(3, 3, 1.)]),
+            (key(2, 1), vec![entry(4, 4, 1.)]),
+            (key(3, 1), vec![entry(5, 5, 1.)]),
+        ];
+        // removes feature 1, moves feature 2, adds feature 6 and gives feature 4 another phrase
+        let changeset = Changeset {
+            remove: vec![1, 2],
+            add: vec![
+                ChangesetFeature {
+                    id: 2,
+                    phrases: vec![ChangesetPhrase {
+                        key: key(0, 1),
+                        relev: 1.,
+                        score: 1,
+                        source_phrase_hash: 0,
+                        coords: vec![(7, 7)],
+                    }],
+                },
+                ChangesetFeature {
+                    id: 6,
+                    phrases: vec![ChangesetPhrase {
+                        key: key(4, 2),
+                        relev: 0.6,
+                        score: 1,
+                        source_phrase_hash: 0,
+                        coords: vec![(6, 6), (8, 8)],
+                    }],
+                },
+                ChangesetFeature {
+                    id: 4,
+                    phrases: vec![ChangesetPhrase {
+                        key: key(1, 1),
+                        relev: 1.,
+                        score: 1,
+                        source_phrase_hash: 0,
+                        coords: vec![(4, 4)],
+                    }],
+                },
+            ],
+        };
+        let after = vec![
+            (key(0, 1), vec![entry(2, 7, 1.)]),
+            (key(1, 1), vec![entry(4, 4, 1.)]),
+            (key(1, 256), vec![entry(3, 3, 1.)]),
+            (key(2, 1), vec![entry(4, 4, 1.)]),
+            (key(3, 1), vec![entry(5, 5, 1.)]),
+            (key(4, 2), vec![entry(6, 6, 0.6), entry(6, 8, 0.6)]),
+        ];
+
+        for indexed in &[true, false] {
+            let store = memory_store(build(before.clone(), *indexed));
+            let mut patched = MemoryStorage::new();
+            let report = store.patch_to(&changeset, &mut patched).unwrap();
+            let mut rebuilt = MemoryStorage::new();
+            build(after.clone(), *indexed).finish_to(&mut rebuilt).unwrap();
+            assert_eq!(
+                contents(&patched),
+                contents(&rebuilt),
+                "patching matches a rebuild (indexed: {})",
+                indexed
+            );
+            assert!(report.copied_records > 0, "untouched records are copied");
+            assert!(report.rewritten_records > 0);
+        }
+
+        // additions are held to the zoom the store records
+        let mut builder = GridStoreBuilder::new_in_memory();
+        builder.insert(&key(0, 1), vec![entry(1, 1, 1.)]).unwrap();
+        builder.set_metadata(GridStoreOptions { zoom: 6, ..GridStoreOptions::default() }, "");
+        let store = memory_store(builder);
+        let adding = |x| Changeset {
+            remove: vec![],
+            add: vec![ChangesetFeature {
+                id: 2,
+                phrases: vec![ChangesetPhrase {
+                    key: key(0, 1),
+                    relev: 1.,
+                    score: 1,
+                    source_phrase_hash: 0,
+                    coords: vec![(x, 0)],
+                }],
+            }],
+        };
+        assert!(store.patch_to(&adding(63), &mut MemoryStorage::new()).is_ok());
+        assert!(store.patch_to(&adding(64), &mut MemoryStorage::new()).is_err(), "past zoom 6");
+        let mut bad_relev = adding(1);
+        bad_relev.add[0].phrases[0].relev = 0.7;
+        assert!(store.patch_to(&bad_relev, &mut MemoryStorage::new()).is_err());
+        let mut bad_id = adding(1);
+        bad_id.add[0].id = 1 << 24;
+        assert!(store.patch_to(&bad_id, &mut MemoryStorage::new()).is_err(), "no wide ids");
+    }
+
+    #[test]
+    fn auto_bins_test() {
+        let entry = |id, x| GridEntry { id, x, y: x, relev: 1., score: 1, source_phrase_hash: 0 };
+        let build = |loaded: Option<Vec<u32>>| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            // each phrase has two coords with an id apiece, or about 24 bytes encoded
+            for phrase_id in 0..6 {
+                let key = GridKey { phrase_id, lang_set: 1 };
+                builder.insert(&key, vec![entry(phrase_id, 1), entry(phrase_id + 10, 2)]).unwrap();
+            }
+            builder.set_auto_bins(50);
+            if let Some(boundaries) = loaded {
+                builder.load_bin_boundaries(boundaries).unwrap();
+            }
+            memory_store(builder)
+        };
+
+        let store = build(None);
+        assert_eq!(store.bin_boundaries, [0, 2, 4].iter().cloned().collect());
+        assert!(store.verify().unwrap().is_ok());
+        let search_key =
+            MatchKey { match_phrase: MatchPhrase::Range { start: 2, end: 4 }, lang_set: 1 };
+        let mut ids: Vec<_> = store
+            .streaming_get_matching(&search_key, &MatchOpts::default(), 10)
+            .unwrap()
+            .map(|entry| entry.unwrap().grid_entry.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![2, 3, 12, 13], "lookups on the chosen bins find their phrases");
+
+        let store = build(Some(vec![0, 3]));
+        assert_eq!(store.bin_boundaries, [0, 3].iter().cloned().collect(), "loaded bins win");
+    }
+
+    #[test]
+    fn composed_range_test() {
+        let build = |boundaries: Option<Vec<u32>>| {
+            let mut builder = GridStoreBuilder::new_in_memory();
+            for phrase_id in 0..9 {
+                let key = GridKey { phrase_id, lang_set: 1 };
+                let entries = (0..2)
+                    .map(|i| GridEntry {
+                        id: phrase_id * 10 + i,
+                        x: phrase_id as u32,
+                        y: i as u32,
+                        relev: 1.,
+                        score: (phrase_id % 7) as u8,
+                        source_phrase_hash: 0,
+                    })
+                    .collect();
+                builder.insert(&key, entries).unwrap();
+            }
+            if let Some(boundaries) = boundaries {
+                builder.load_bin_boundaries(boundaries).unwrap();
+            }
+            memory_store(builder)
+        };
+        let binned = build(Some(vec![0, 2, 4, 6]));
+        let unbinned = build(None);
+
+        use TypeMarker::{PrefixBin, SinglePhrase};
+        assert_eq!(
+            binned.range_segments(1, 7),
+            vec![(SinglePhrase, 1, 2), (PrefixBin, 2, 5), (SinglePhrase, 6, 7)]
+        );
+        assert_eq!(binned.range_segments(0, 4), vec![(PrefixBin, 0, 3)]);
+        assert_eq!(binned.range_segments(2, 5), vec![(PrefixBin, 2, 3), (SinglePhrase, 4, 5)]);
+        assert_eq!(binned.range_segments(3, 5), vec![(SinglePhrase, 3, 5)], "no whole bin inside");
+        assert_eq!(
+            binned.range_segments(6, 9),
+            vec![(SinglePhrase, 6, 9)],
+            "the last bin runs on past the end"
+        );
+
+        let matching = |store: &GridStore, start, end| {
+            let search_key =
+                MatchKey { match_phrase: MatchPhrase::Range { start, end }, lang_set: 1 };
+            let mut ids: Vec<_> = store
+                .streaming_get_matching(&search_key, &MatchOpts::default(), std::usize::MAX)
+                .unwrap()
+                .map(|entry| entry.unwrap().grid_entry.id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        for start in 0..9 {
+            for end in (start + 1)..=9 {
+                assert_eq!(
+                    matching(&binned, start, end),
+                    matching(&unbinned, start, end),
+                    "range {}..{}",
+                    start,
+                    end
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,317 @@
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashSet};
+use std::path::Path;
+
+use failure::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::gridstore::builder::{
+    check_coord, check_values, extend_entries, get_encoded_value, merge_entries, BuilderEntry,
+};
+use crate::gridstore::common::*;
+use crate::gridstore::feature_index::{encode_phrase_list, feature_index_key, FEATURE_INDEX_KEY};
+use crate::gridstore::metadata::FormatFeatures;
+use crate::gridstore::shared_values::reference_target;
+use crate::gridstore::storage::{
+    GridStorageWriter, MmapStorageWriter, RocksDBBulkWriter, StorageFormat,
+};
+use crate::gridstore::store::{decode_builder_entry, GridStore};
+use crate::gridstore::tile_index::{
+    decode_id_list, encode_id_list, index_tile, tile_index_key, TileIndex, TILE_INDEX_KEY,
+};
+
+/// One of the phrases a feature in a changeset is indexed under, with the same details
+/// `GridStoreBuilder::compact_append` takes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangesetPhrase {
+    pub key: GridKey,
+    pub relev: f64,
+    pub score: u8,
+    #[serde(default)]
+    pub source_phrase_hash: u8,
+    pub coords: Vec<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChangesetFeature {
+    pub id: u32,
+    pub phrases: Vec<ChangesetPhrase>,
+}
+
+/// Changes to make to a finished store with `GridStore::patch`. Removals happen first, so a
+/// feature that's both removed and added is replaced outright.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Changeset {
+    /// Ids of features to take out of every phrase they're in
+    #[serde(default)]
+    pub remove: Vec<u32>,
+    #[serde(default)]
+    pub add: Vec<ChangesetFeature>,
+}
+
+/// How much of the store a patch had to touch
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PatchReport {
+    /// Records written with new contents, including ones that didn't exist before
+    pub rewritten_records: usize,
+    /// Records left out because the changeset emptied them
+    pub removed_records: usize,
+    /// Records copied across untouched
+    pub copied_records: usize,
+}
+
+/// The bin a phrase falls into, grouped the same way `GridStoreBuilder::finish` groups them.
+fn bin_for(bin_boundaries: &[u32], phrase_id: u32) -> Option<u32> {
+    let first = *bin_boundaries.first()?;
+    Some(
+        bin_boundaries
+            .iter()
+            .cloned()
+            .take_while(|bound| *bound <= phrase_id)
+            .last()
+            .unwrap_or(first),
+    )
+}
+
+/// Takes every id in `removed` out of an entry, noting the tiles they were found at.
+fn remove_ids(entry: &mut BuilderEntry, removed: &HashSet<u32>, tiles: &mut BTreeSet<u64>) {
+    for coords in entry.values_mut() {
+        for (coord, ids) in coords.iter_mut() {
+            let before = ids.len();
+            ids.retain(|id_comp| !removed.contains(&((*id_comp >> 8) as u32)));
+            if ids.len() != before {
+                tiles.insert(*coord);
+            }
+        }
+        coords.retain(|_, ids| !ids.is_empty());
+    }
+    entry.retain(|_, coords| !coords.is_empty());
+}
+
+fn read_entry(store: &GridStore, db_key: &[u8]) -> Result<BuilderEntry, Error> {
+    match store.storage.get(db_key)? {
+        Some(value) => decode_builder_entry(store.resolve(value)?, &store.format_features),
+        None => Ok(BuilderEntry::new()),
+    }
+}
+
+/// What to write for a record: its new encoding, or nothing if it's been emptied
+type Change = Option<Vec<u8>>;
+
+fn encode_change(entry: BuilderEntry, features: &FormatFeatures) -> Result<Change, Error> {
+    Ok(if entry.is_empty() { None } else { Some(get_encoded_value(entry, features)?) })
+}
+
+impl GridStore {
+    /// Applies a changeset to this store (not including any overlays), writing the result out as
+    /// a new store at `path`. See `patch_to`.
+    pub fn patch<P: AsRef<Path>>(
+        &self,
+        changeset: &Changeset,
+        path: P,
+        storage_format: StorageFormat,
+    ) -> Result<PatchReport, Error> {
+        match storage_format {
+            StorageFormat::RocksDB => {
+                let sst_options = RocksDBBulkWriter::sst_options();
+                let mut writer = RocksDBBulkWriter::create(path, &sst_options)?;
+                let report = self.patch_to(changeset, &mut writer)?;
+                writer.finish()?;
+                Ok(report)
+            }
+            StorageFormat::SingleFile => {
+                let mut writer = MmapStorageWriter::create(path)?;
+                let report = self.patch_to(changeset, &mut writer)?;
+                writer.finish()?;
+                Ok(report)
+            }
+        }
+    }
+
+    /// Applies a changeset to this store, writing the result to an arbitrary storage backend.
+    /// Only the phrase and prefix bin records the changeset touches are decoded and re-encoded,
+    /// along with the feature and tile index records for the features involved if the store has
+    /// those indexes; everything else is copied across byte for byte. The phrases a removed
+    /// feature is in are found with the feature index if there is one, and with a scan of every
+    /// phrase record otherwise. Added phrases are checked as `GridStoreBuilder` checks its entries
+    /// outside lossy mode, so a value the store can't hold is an error.
+    pub fn patch_to<W: GridStorageWriter>(
+        &self,
+        changeset: &Changeset,
+        writer: &mut W,
+    ) -> Result<PatchReport, Error> {
+        let removed: HashSet<u32> = changeset.remove.iter().cloned().collect();
+        let has_feature_index = self.storage.get(FEATURE_INDEX_KEY)?.is_some();
+        let has_tile_index = self.storage.get(TILE_INDEX_KEY)?.is_some();
+
+        let mut added: BTreeMap<GridKey, BuilderEntry> = BTreeMap::new();
+        let mut added_tiles = TileIndex::new();
+        let zoom = self.metadata.as_ref().map(|metadata| metadata.options.zoom);
+        for feature in &changeset.add {
+            for phrase in &feature.phrases {
+                // values are held to the store's format and zoom, as the builder holds them
+                // outside lossy mode
+                check_values(phrase.relev, phrase.score, feature.id, &self.format_features, None)?;
+                for &(x, y) in &phrase.coords {
+                    check_coord(x, y, zoom, &self.format_features, None)?;
+                }
+                let values = phrase
+                    .coords
+                    .iter()
+                    .map(|&(x, y)| GridEntry {
+                        id: feature.id,
+                        x,
+                        y,
+                        relev: phrase.relev,
+                        score: phrase.score,
+                        source_phrase_hash: phrase.source_phrase_hash,
+                    })
+                    .collect();
+                extend_entries(
+                    added.entry(phrase.key.clone()).or_default(),
+                    values,
+                    self.format_features.high_precision,
+                );
+            }
+        }
+        for entry in added.values() {
+            for (tile, id_comps) in entry.values().flatten() {
+                index_tile(&mut added_tiles, *tile, id_comps.iter().cloned());
+            }
+        }
+
+        let mut affected: BTreeSet<GridKey> = added.keys().cloned().collect();
+        if has_feature_index {
+            for id in &removed {
+                affected.extend(self.layer_phrases_for_feature(*id)?);
+            }
+        } else if !removed.is_empty() {
+            for record in self.builder_entries() {
+                let (key, entry) = record?;
+                let mut id_comps = entry.values().flat_map(|coords| coords.values()).flatten();
+                if id_comps.any(|id_comp| removed.contains(&((id_comp >> 8) as u32))) {
+                    affected.insert(key);
+                }
+            }
+        }
+
+        let mut bin_boundaries: Vec<u32> = self.bin_boundaries.iter().cloned().collect();
+        bin_boundaries.sort();
+        let mut changes: BTreeMap<Vec<u8>, Change> = BTreeMap::new();
+        let mut bin_additions: BTreeMap<GridKey, BuilderEntry> = BTreeMap::new();
+        let mut touched_tiles = BTreeSet::new();
+        for key in affected {
+            let mut db_key = Vec::with_capacity(MAX_KEY_LENGTH);
+            key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
+            let mut entry = read_entry(self, &db_key)?;
+            remove_ids(&mut entry, &removed, &mut touched_tiles);
+
+            let addition = added.remove(&key).unwrap_or_default();
+            if let Some(group_id) = bin_for(&bin_boundaries, key.phrase_id) {
+                let bin_key = GridKey { phrase_id: group_id, lang_set: key.lang_set };
+                merge_entries(addition.clone(), bin_additions.entry(bin_key).or_default());
+            }
+            merge_entries(addition, &mut entry);
+            changes.insert(db_key, encode_change(entry, &self.format_features)?);
+        }
+
+        // prefix bins are unions of the phrase records in them, so they can be patched the same way
+        for (bin_key, addition) in bin_additions {
+            let mut db_key = Vec::with_capacity(MAX_KEY_LENGTH);
+            bin_key.write_to(TypeMarker::PrefixBin, &mut db_key)?;
+            let mut entry = read_entry(self, &db_key)?;
+            remove_ids(&mut entry, &removed, &mut BTreeSet::new());
+            merge_entries(addition, &mut entry);
+            changes.insert(db_key, encode_change(entry, &self.format_features)?);
+        }
+
+        if has_feature_index {
+            let mut feature_phrases: BTreeMap<u32, BTreeSet<GridKey>> = BTreeMap::new();
+            for id in &removed {
+                feature_phrases.insert(*id, BTreeSet::new());
+            }
+            for feature in &changeset.add {
+                let keys = match feature_phrases.entry(feature.id) {
+                    btree_map::Entry::Occupied(entry) => entry.into_mut(),
+                    btree_map::Entry::Vacant(entry) => {
+                        let existing = self.layer_phrases_for_feature(feature.id)?;
+                        entry.insert(existing.into_iter().collect())
+                    }
+                };
+                keys.extend(feature.phrases.iter().map(|phrase| phrase.key.clone()));
+            }
+            for (id, keys) in feature_phrases {
+                let mut db_key = Vec::with_capacity(5);
+                feature_index_key(id, &mut db_key);
+                let keys: Vec<GridKey> = keys.into_iter().collect();
+                let change = if keys.is_empty() { None } else { Some(encode_phrase_list(&keys)) };
+                changes.insert(db_key, change);
+            }
+        }
+
+        if has_tile_index {
+            touched_tiles.extend(added_tiles.keys().cloned());
+            for tile in touched_tiles {
+                let mut db_key = Vec::with_capacity(5);
+                tile_index_key(tile, self.format_features.wide_coords, &mut db_key);
+                let mut ids = match self.storage.get(&db_key)? {
+                    Some(value) => decode_id_list(value.as_ref())?,
+                    None => Vec::new(),
+                };
+                ids.retain(|id| !removed.contains(id));
+                ids.extend(added_tiles.remove(&tile).unwrap_or_default());
+                ids.sort();
+                ids.dedup();
+                let change = if ids.is_empty() { None } else { Some(encode_id_list(&ids)) };
+                changes.insert(db_key, change);
+            }
+        }
+
+        // a record that other phrases share is only stored once, so if it changes they each need
+        // their own copy of what it used to be
+        let changed_keys: HashSet<Vec<u8>> = if self.format_features.shared_values {
+            changes.keys().cloned().collect()
+        } else {
+            HashSet::new()
+        };
+
+        // splice the changes in among the untouched records, keeping everything in key order
+        let mut report = PatchReport::default();
+        let mut changes = changes.into_iter().peekable();
+        for item in self.storage.iter_from(&[]) {
+            let (key, value) = item?;
+            let mut replaced = false;
+            while changes
+                .peek()
+                .filter(|(changed_key, _)| changed_key.as_slice() <= &key[..])
+                .is_some()
+            {
+                let (changed_key, change) = changes.next().unwrap();
+                let existed = changed_key.as_slice() == &key[..];
+                replaced |= existed;
+                match change {
+                    Some(value) => {
+                        writer.put(&changed_key, &value)?;
+                        report.rewritten_records += 1;
+                    }
+                    None if existed => report.removed_records += 1,
+                    None => {}
+                }
+            }
+            if !replaced {
+                let value = match reference_target(value.as_ref()) {
+                    Some(target) if changed_keys.contains(target) => self.resolve(value)?,
+                    _ => value,
+                };
+                writer.put(&key, value.as_ref())?;
+                report.copied_records += 1;
+            }
+        }
+        for (changed_key, change) in changes {
+            if let Some(value) = change {
+                writer.put(&changed_key, &value)?;
+                report.rewritten_records += 1;
+            }
+        }
+        Ok(report)
+    }
+}
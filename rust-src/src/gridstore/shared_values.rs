@@ -0,0 +1,90 @@
+use std::collections::hash_map::{Entry, RandomState};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+
+use failure::Error;
+
+use crate::gridstore::common::*;
+
+/// Stands in for a record's root pointer at the end of a reference. A real root always points
+/// somewhere inside the record, so it can never be all ones.
+const REFERENCE_ROOT: [u8; 4] = [0xff; 4];
+
+/// The most a reference can take; records no bigger than this aren't worth sharing.
+pub const MAX_REFERENCE_LENGTH: usize = MAX_KEY_LENGTH + REFERENCE_ROOT.len();
+
+/// Encodes a reference to the phrase record stored under `key`: its database key, followed by
+/// `REFERENCE_ROOT`.
+pub fn encode_reference(key: &GridKey) -> Result<Vec<u8>, Error> {
+    let mut reference = Vec::with_capacity(MAX_REFERENCE_LENGTH);
+    key.write_to(TypeMarker::SinglePhrase, &mut reference)?;
+    reference.extend_from_slice(&REFERENCE_ROOT);
+    Ok(reference)
+}
+
+/// The database key a stored value points to, if it's a reference rather than a record.
+pub fn reference_target(value: &[u8]) -> Option<&[u8]> {
+    match value.len().checked_sub(REFERENCE_ROOT.len()) {
+        Some(split) if split > 0 && value[split..] == REFERENCE_ROOT => Some(&value[..split]),
+        _ => None,
+    }
+}
+
+/// Spots phrase records that encode to the same bytes as one already written during a build.
+/// Every record is looked up by a hash of its contents, so this holds a hash and a key for each
+/// one until the build is done.
+pub struct SharedValues {
+    hashers: [RandomState; 2],
+    seen: HashMap<u128, GridKey>,
+}
+
+impl SharedValues {
+    pub fn new() -> Self {
+        SharedValues { hashers: [RandomState::new(), RandomState::new()], seen: HashMap::new() }
+    }
+
+    /// Hashes an encoded record with two independently keyed hashers, so that two different
+    /// records are vanishingly unlikely to be mistaken for each other. The hashes are never
+    /// stored, so they only have to agree within a single build.
+    pub fn content_hash(&self, value: &[u8]) -> u128 {
+        let hash = |hasher: &RandomState| {
+            let mut hasher = hasher.build_hasher();
+            hasher.write(value);
+            hasher.finish()
+        };
+        (u128::from(hash(&self.hashers[0])) << 64) | u128::from(hash(&self.hashers[1]))
+    }
+
+    /// The key of the first record seen with this content hash. If there isn't one yet, the record
+    /// under `key` becomes the one later copies point to.
+    pub fn find_or_insert(&mut self, hash: u128, key: &GridKey) -> Option<&GridKey> {
+        match self.seen.entry(hash) {
+            Entry::Occupied(entry) => Some(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                entry.insert(key.clone());
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn reference_test() {
+    let key = GridKey { phrase_id: 7, lang_set: 1 };
+    let reference = encode_reference(&key).unwrap();
+    let mut db_key = Vec::new();
+    key.write_to(TypeMarker::SinglePhrase, &mut db_key).unwrap();
+    assert_eq!(reference_target(&reference), Some(&db_key[..]));
+
+    assert_eq!(reference_target(&[1, 0, 0, 0, 0]), None, "a record whose root points at 1");
+    assert_eq!(reference_target(&REFERENCE_ROOT), None, "a reference needs a key");
+    assert_eq!(reference_target(&[]), None);
+
+    let mut shared = SharedValues::new();
+    let hash = shared.content_hash(b"record");
+    assert_eq!(hash, shared.content_hash(b"record"));
+    assert_ne!(hash, shared.content_hash(b"recore"));
+    assert_eq!(shared.find_or_insert(hash, &key), None);
+    let other = GridKey { phrase_id: 8, lang_set: 1 };
+    assert_eq!(shared.find_or_insert(hash, &other), Some(&key), "the first record is kept");
+}
@@ -1,17 +1,50 @@
-use crate::gridstore::gridstore_format::{Coord, UniformVec};
+use crate::gridstore::gridstore_format::{Coord, CoordVec};
 use itertools::Itertools;
-use morton::{deinterleave_morton, interleave_morton};
 
 #[cfg(test)]
-use crate::gridstore::common::relev_float_to_int;
+use crate::gridstore::common::pack_relev_score;
 #[cfg(test)]
 use crate::gridstore::gridstore_format;
 
+/// Spreads the bits of a coordinate out to every other bit of a u64
+#[inline]
+fn spread_bits(v: u32) -> u64 {
+    let mut v = u64::from(v);
+    v = (v | (v << 16)) & 0x0000_ffff_0000_ffff;
+    v = (v | (v << 8)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v << 2)) & 0x3333_3333_3333_3333;
+    (v | (v << 1)) & 0x5555_5555_5555_5555
+}
+
+#[inline]
+fn compact_bits(v: u64) -> u32 {
+    let mut v = v & 0x5555_5555_5555_5555;
+    v = (v | (v >> 1)) & 0x3333_3333_3333_3333;
+    v = (v | (v >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    v = (v | (v >> 4)) & 0x00ff_00ff_00ff_00ff;
+    v = (v | (v >> 8)) & 0x0000_ffff_0000_ffff;
+    ((v | (v >> 16)) & 0x0000_0000_ffff_ffff) as u32
+}
+
+/// Morton-codes a tile, with x in the even bits and y in the odd ones. Coordinates below 2^16 get
+/// the same code `morton::interleave_morton` gives them, so codes from zoom 16 and under fit in a
+/// u32.
+#[inline]
+pub fn interleave_morton(x: u32, y: u32) -> u64 {
+    spread_bits(x) | (spread_bits(y) << 1)
+}
+
+#[inline]
+pub fn deinterleave_morton(z: u64) -> (u32, u32) {
+    (compact_bits(z), compact_bits(z >> 1))
+}
+
 /// Generate a tuple of the (min, max) range of the Coord Vector that overlaps with the bounding box
 ///
 /// Returns (Some(min,max)) if the Coord Vector morton order range overlaps with the bounding box,
 /// [`None`] if the Coord Vector morton order range does not overlaps with the bounding box
-pub fn bbox_range<'a>(coords: UniformVec<'a, Coord>, bbox: [u16; 4]) -> Option<(u32, u32)> {
+pub fn bbox_range<B: AsRef<[u8]>>(coords: &CoordVec<B>, bbox: [u32; 4]) -> Option<(u32, u32)> {
     let min = interleave_morton(bbox[0], bbox[1]);
     let max = interleave_morton(bbox[2], bbox[3]);
     debug_assert!(min <= max, "Invalid bounding box");
@@ -31,11 +64,11 @@ pub fn bbox_range<'a>(coords: UniformVec<'a, Coord>, bbox: [u16; 4]) -> Option<(
     }
     debug_assert!(range_start >= range_end, "Expected descending sort");
 
-    let start = match coord_binary_search(&coords, max, 0) {
+    let start = match coord_binary_search(coords, max, 0) {
         Ok(v) => v,
         Err(_) => return None,
     };
-    let mut end = match coord_binary_search(&coords, min, start) {
+    let mut end = match coord_binary_search(coords, min, start) {
         Ok(v) => v,
         Err(_) => return None,
     };
@@ -52,16 +85,16 @@ pub fn bbox_range<'a>(coords: UniformVec<'a, Coord>, bbox: [u16; 4]) -> Option<(
 /// Returns [`Some(Iterator<>`] if the Coord Vector morton order range overlaps with the bounding box,
 /// [`None`] otherwise. May return an Iterator that yields no results if the morton order overlaps
 /// but the actual elements are not in the bounding box.
-pub fn bbox_filter<'a>(
-    coords: UniformVec<'a, Coord>,
-    bbox: [u16; 4],
-) -> Option<impl Iterator<Item = Coord> + 'a> {
+pub fn bbox_filter<B: AsRef<[u8]>>(
+    coords: CoordVec<B>,
+    bbox: [u32; 4],
+) -> Option<impl Iterator<Item = Coord>> {
     let len = coords.len();
     if len == 0 {
         return None;
     }
 
-    let range = bbox_range(coords, bbox)?;
+    let range = bbox_range(&coords, bbox)?;
     Some((range.0..=range.1).filter_map(move |idx| {
         let grid = coords.get(idx as usize);
         let (x, y) = deinterleave_morton(grid.coord);
@@ -76,28 +109,26 @@ pub fn bbox_filter<'a>(
 ///
 /// Returns [`Some(Iterator<>`] which is a Coord Vector morton order range ordered by the z-order distance from the proximity point
 /// [`None`] if the Coord Vector is empty
-pub fn proximity<'a>(
-    coords: UniformVec<'a, Coord>,
-    proximity: [u16; 2],
-) -> Option<impl Iterator<Item = Coord> + 'a> {
-    let prox_pt = interleave_morton(proximity[0], proximity[1]) as i64;
+pub fn proximity<B: AsRef<[u8]> + Clone>(
+    coords: CoordVec<B>,
+    proximity: [u32; 2],
+) -> Option<impl Iterator<Item = Coord>> {
+    let prox_pt = interleave_morton(proximity[0], proximity[1]);
     let len = coords.len() as u32;
     if len == 0 {
         return None;
     }
 
-    let prox_mid = match coord_binary_search(&coords, prox_pt as u32, 0) {
+    let prox_mid = match coord_binary_search(&coords, prox_pt, 0) {
         Ok(v) => v,
         Err(_) => return None,
     };
 
-    let getter = move |i| coords.get(i as usize);
-    let head = (0..prox_mid).rev().map(getter);
-    let tail = (prox_mid..len).map(getter);
+    let head_coords = coords.clone();
+    let head = (0..prox_mid).rev().map(move |i| head_coords.get(i as usize));
+    let tail = (prox_mid..len).map(move |i| coords.get(i as usize));
     let coord_sets = head.into_iter().merge_by(tail.into_iter(), move |a, b| {
-        let morton_distance_1 = (a.coord as i64 - prox_pt) as i64;
-        let morton_distance_2 = (b.coord as i64 - prox_pt) as i64;
-        morton_distance_1.abs() < morton_distance_2.abs()
+        morton_distance(a.coord, prox_pt) < morton_distance(b.coord, prox_pt)
     });
 
     Some(coord_sets)
@@ -107,18 +138,18 @@ pub fn proximity<'a>(
 ///
 /// Returns [`Some(Iterator<>`] which is a Coord Vector morton order range that overlaps with a bounding box and is ordered by the z-order distance from the proximity point
 /// [`None`] if the bounding box does not overlap with the morton order range
-pub fn bbox_proximity_filter<'a>(
-    coords: UniformVec<'a, Coord>,
-    bbox: [u16; 4],
-    proximity: [u16; 2],
-) -> Option<impl Iterator<Item = Coord> + 'a> {
-    let range = bbox_range(coords, bbox)?;
-    let prox_pt = interleave_morton(proximity[0], proximity[1]) as i64;
+pub fn bbox_proximity_filter<B: AsRef<[u8]> + Clone>(
+    coords: CoordVec<B>,
+    bbox: [u32; 4],
+    proximity: [u32; 2],
+) -> Option<impl Iterator<Item = Coord>> {
+    let range = bbox_range(&coords, bbox)?;
+    let prox_pt = interleave_morton(proximity[0], proximity[1]);
     if coords.len() == 0 {
         return None;
     }
 
-    let prox_mid = match coord_binary_search(&coords, prox_pt as u32, 0) {
+    let prox_mid = match coord_binary_search(&coords, prox_pt, 0) {
         Ok(v) => v,
         Err(_) => return None,
     };
@@ -133,16 +164,20 @@ pub fn bbox_proximity_filter<'a>(
         };
     };
 
-    let head = (range.0..prox_mid).rev().filter_map(filtered_get);
+    let head = (range.0..prox_mid).rev().filter_map(filtered_get.clone());
     let tail = (prox_mid..=range.1).filter_map(filtered_get);
     let coord_sets = head.into_iter().merge_by(tail.into_iter(), move |a, b| {
-        let morton_distance_1 = (a.coord as i64 - prox_pt) as i64;
-        let morton_distance_2 = (b.coord as i64 - prox_pt) as i64;
-        morton_distance_1.abs() < morton_distance_2.abs()
+        morton_distance(a.coord, prox_pt) < morton_distance(b.coord, prox_pt)
     });
 
     Some(coord_sets)
 }
+/// How far apart two morton codes are along the curve
+#[inline]
+fn morton_distance(a: u64, b: u64) -> u64 {
+    a.max(b) - a.min(b)
+}
+
 /// Binary search this FlatBuffers Coord Vector
 ///
 /// Derived from binary_search_by in core/slice/mod.rs except this expects descending order.
@@ -151,11 +186,11 @@ pub fn bbox_proximity_filter<'a>(
 /// index of the matching element. If the value is less than the first element and greater than the last,
 /// [`Result::Ok'] is returned containing either 0 or the length of the Vector. A ['Results:Err'] is
 /// returned if the offset is greater to the vector length.
-fn coord_binary_search<'a>(
-    coords: &UniformVec<'a, Coord>,
-    val: u32,
+fn coord_binary_search<B: AsRef<[u8]>>(
+    coords: &CoordVec<B>,
+    val: u64,
     offset: u32,
-) -> Result<u32, &'a str> {
+) -> Result<u32, &'static str> {
     let len = coords.len() as u32;
 
     if offset >= len {
@@ -188,13 +223,15 @@ fn coord_binary_search<'a>(
 }
 
 #[cfg(test)]
-fn encoded_val_generator<T: Iterator<Item = u32>>(val: T) -> Vec<u8> {
+fn encoded_val_generator<T: Iterator<Item = u64>>(
+    val: T,
+    features: &crate::gridstore::metadata::FormatFeatures,
+) -> Vec<u8> {
     let mut builder = gridstore_format::Writer::new();
 
-    let relev_score = (relev_float_to_int(1.0) << 4) | 1;
+    let relev_score = pack_relev_score(1.0, 1, false);
 
-    let ids: Vec<u32> = vec![];
-    let encoded_ids = builder.write_fixed_vec(&ids);
+    let encoded_ids = builder.write_id_list(&[], features);
 
     let mut coords: Vec<_> = Vec::new();
 
@@ -202,7 +239,7 @@ fn encoded_val_generator<T: Iterator<Item = u32>>(val: T) -> Vec<u8> {
         let coord = gridstore_format::Coord { coord: i, ids: encoded_ids.clone() };
         coords.push(coord);
     }
-    let encoded_coords = builder.write_uniform_vec(&coords);
+    let encoded_coords = builder.write_coord_vec(&coords, features);
     let encoded_rs = gridstore_format::RelevScore { relev_score, coords: encoded_coords };
 
     let encoded_rses = builder.write_var_vec(&vec![encoded_rs]);
@@ -216,247 +253,264 @@ fn encoded_val_generator<T: Iterator<Item = u32>>(val: T) -> Vec<u8> {
 #[cfg(test)]
 fn get_coords_from_reader<'a>(
     reader: &'a gridstore_format::Reader<&'a [u8]>,
-) -> gridstore_format::UniformVec<'a, gridstore_format::Coord> {
-    let record = gridstore_format::read_phrase_record_from(reader);
+    features: &crate::gridstore::metadata::FormatFeatures,
+) -> CoordVec<&'a [u8]> {
+    let record = gridstore_format::read_phrase_record_from(reader).unwrap();
 
-    let rs_obj = reader.read_var_vec(record.relev_scores).into_iter().next().unwrap();
+    let rs_obj = reader.read_var_vec(record.relev_scores).unwrap().into_iter().next().unwrap();
 
-    reader.read_uniform_vec(rs_obj.coords)
+    reader.read_coord_vec(rs_obj.coords, features).unwrap()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::gridstore::metadata::FormatFeatures;
+
+    fn layouts() -> [FormatFeatures; 2] {
+        [
+            FormatFeatures::default(),
+            FormatFeatures { packed_vectors: true, ..FormatFeatures::default() },
+        ]
+    }
 
     #[test]
     fn filter_bbox() {
-        let empty: Vec<u32> = vec![];
-        let buffer = encoded_val_generator(empty.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        assert_eq!(bbox_filter(coords, [0, 0, 0, 0]).is_none(), true);
-
-        let buffer = encoded_val_generator((0..4).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [0, 0, 1, 1]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 4);
-
-        let buffer = encoded_val_generator((2..4).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [0, 0, 1, 1]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 2, "starts before bbox and ends between the result set");
-
-        let buffer = encoded_val_generator((2..4).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [1, 1, 3, 1]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 1, "starts in the bbox and ends after the result set");
-
-        let buffer = encoded_val_generator((1..4).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [0, 1, 1, 1]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 2, "starts in the bbox and ends in the bbox");
-
-        let buffer = encoded_val_generator((5..7).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        assert_eq!(
-            bbox_filter(coords, [0, 0, 0, 1]).is_none(),
-            true,
-            "bbox ends before the range of coordinates"
-        );
-        assert_eq!(
-            bbox_filter(coords, [4, 0, 4, 1]).is_none(),
-            true,
-            "bbox starts after the range of coordinates"
-        );
-
-        let sparse: Vec<u32> = vec![24, 7];
-        let buffer = encoded_val_generator(sparse.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 2, "sparse result set that spans z-order jumps");
-
-        let buffer = encoded_val_generator((7..24).rev());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 3, "continuous result set that spans z-order jumps");
-
-        let sparse: Vec<u32> = vec![8];
-        let buffer = encoded_val_generator(sparse.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
-        assert_eq!(result.len(), 0, "result is on the z-order curve but not in the bbox");
+        for features in layouts().iter() {
+            let empty: Vec<u64> = vec![];
+            let buffer = encoded_val_generator(empty.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            assert_eq!(bbox_filter(coords, [0, 0, 0, 0]).is_none(), true);
+
+            let buffer = encoded_val_generator((0..4).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [0, 0, 1, 1]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 4);
+
+            let buffer = encoded_val_generator((2..4).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [0, 0, 1, 1]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 2, "starts before bbox and ends between the result set");
+
+            let buffer = encoded_val_generator((2..4).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [1, 1, 3, 1]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 1, "starts in the bbox and ends after the result set");
+
+            let buffer = encoded_val_generator((1..4).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [0, 1, 1, 1]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 2, "starts in the bbox and ends in the bbox");
+
+            let buffer = encoded_val_generator((5..7).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            assert_eq!(
+                bbox_filter(coords, [0, 0, 0, 1]).is_none(),
+                true,
+                "bbox ends before the range of coordinates"
+            );
+            assert_eq!(
+                bbox_filter(coords, [4, 0, 4, 1]).is_none(),
+                true,
+                "bbox starts after the range of coordinates"
+            );
+
+            let sparse: Vec<u64> = vec![24, 7];
+            let buffer = encoded_val_generator(sparse.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 2, "sparse result set that spans z-order jumps");
+
+            let buffer = encoded_val_generator((7..24).rev(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 3, "continuous result set that spans z-order jumps");
+
+            let sparse: Vec<u64> = vec![8];
+            let buffer = encoded_val_generator(sparse.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_filter(coords, [3, 1, 4, 2]).unwrap().collect::<Vec<Coord>>();
+            assert_eq!(result.len(), 0, "result is on the z-order curve but not in the bbox");
+        }
     }
 
     #[test]
     fn proximity_search() {
-        let buffer = encoded_val_generator((1..10).rev()); // [9,8,7,6,5,4,3,2,1]
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-
-        let result = proximity(coords, [3, 0]).unwrap().map(|x| x.coord).collect::<Vec<u32>>();
-        assert_eq!(
-            vec![5, 4, 6, 3, 7, 2, 8, 1, 9],
-            result,
-            "proximity point is in the middle of the result set - 5"
-        );
-
-        let result = proximity(coords, [0, 3]).unwrap().map(|x| x.coord).collect::<Vec<u32>>();
-        assert_eq!(
-            vec![9, 8, 7, 6, 5, 4, 3, 2, 1],
-            result,
-            "proximity point is greater than the result set - 10"
-        );
-
-        let result = proximity(coords, [1, 0]).unwrap().map(|x| x.coord).collect::<Vec<u32>>();
-        assert_eq!(
-            vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
-            result,
-            "proximity point is lesser than the result set - 1"
-        );
-
-        let empty: Vec<u32> = vec![];
-        let buffer = encoded_val_generator(empty.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        assert_eq!(proximity(coords, [3, 0]).is_none(), true);
-
-        let sparse: Vec<u32> = vec![24, 21, 13, 8, 7, 6, 1]; // 1 and 13 are at the same distance from 7
-        let buffer = encoded_val_generator(sparse.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = proximity(coords, [3, 1]).unwrap().map(|x| x.coord).collect::<Vec<u32>>();
-        assert_eq!(
-            vec![7, 6, 8, 1, 13, 21, 24],
-            result,
-            "sparse result set sorted by z-order in the middle of the result set"
-        );
+        for features in layouts().iter() {
+            let buffer = encoded_val_generator((1..10).rev(), features); // [9,8,7,6,5,4,3,2,1]
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+
+            let result = proximity(coords, [3, 0]).unwrap().map(|x| x.coord).collect::<Vec<u64>>();
+            assert_eq!(
+                vec![5, 4, 6, 3, 7, 2, 8, 1, 9],
+                result,
+                "proximity point is in the middle of the result set - 5"
+            );
+
+            let result = proximity(coords, [0, 3]).unwrap().map(|x| x.coord).collect::<Vec<u64>>();
+            assert_eq!(
+                vec![9, 8, 7, 6, 5, 4, 3, 2, 1],
+                result,
+                "proximity point is greater than the result set - 10"
+            );
+
+            let result = proximity(coords, [1, 0]).unwrap().map(|x| x.coord).collect::<Vec<u64>>();
+            assert_eq!(
+                vec![1, 2, 3, 4, 5, 6, 7, 8, 9],
+                result,
+                "proximity point is lesser than the result set - 1"
+            );
+
+            let empty: Vec<u64> = vec![];
+            let buffer = encoded_val_generator(empty.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            assert_eq!(proximity(coords, [3, 0]).is_none(), true);
+
+            let sparse: Vec<u64> = vec![24, 21, 13, 8, 7, 6, 1]; // 1 and 13 are at the same distance from 7
+            let buffer = encoded_val_generator(sparse.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = proximity(coords, [3, 1]).unwrap().map(|x| x.coord).collect::<Vec<u64>>();
+            assert_eq!(
+                vec![7, 6, 8, 1, 13, 21, 24],
+                result,
+                "sparse result set sorted by z-order in the middle of the result set"
+            );
+        }
     }
 
     #[test]
     fn bbox_proximity_search() {
-        let buffer = encoded_val_generator((1..10).rev()); // [9,8,7,6,5,4,3,2,1]
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        // bbox is from 1-7; proximity is 4
-        let result = bbox_proximity_filter(coords, [1, 0, 3, 1], [2, 0])
-            .unwrap()
-            .map(|x| x.coord)
-            .collect::<Vec<u32>>();
-        assert_eq!(
-            vec![4, 3, 5, 6, 1, 7],
-            result,
-            "bbox within the range of coordinates; proximity point within the result set"
-        );
-
-        assert_eq!(
-            bbox_proximity_filter(coords, [6, 4, 7, 5], [2, 0]).is_none(),
-            true,
-            "bbox outside list of coordinates; proximity within the result set"
-        );
-
-        let result = bbox_proximity_filter(coords, [1, 0, 3, 1], [0, 0])
-            .unwrap()
-            .map(|x| x.coord)
-            .collect::<Vec<u32>>();
-        assert_eq!(
-            vec![1, 3, 4, 5, 6, 7],
-            result,
-            "bbox within the range of coordinates; proximity point outside the result set"
-        );
-
-        let buffer = encoded_val_generator((2..5).rev()); // [4,3,2]
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        let result = bbox_proximity_filter(coords, [1, 1, 3, 1], [0, 0]) // bbox is 3-7; proximity is 0
-            .unwrap()
-            .map(|x| x.coord)
-            .collect::<Vec<u32>>();
-        assert_eq!(
+        for features in layouts().iter() {
+            let buffer = encoded_val_generator((1..10).rev(), features); // [9,8,7,6,5,4,3,2,1]
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            // bbox is from 1-7; proximity is 4
+            let result = bbox_proximity_filter(coords, [1, 0, 3, 1], [2, 0])
+                .unwrap()
+                .map(|x| x.coord)
+                .collect::<Vec<u64>>();
+            assert_eq!(
+                vec![4, 3, 5, 6, 1, 7],
+                result,
+                "bbox within the range of coordinates; proximity point within the result set"
+            );
+
+            assert_eq!(
+                bbox_proximity_filter(coords, [6, 4, 7, 5], [2, 0]).is_none(),
+                true,
+                "bbox outside list of coordinates; proximity within the result set"
+            );
+
+            let result = bbox_proximity_filter(coords, [1, 0, 3, 1], [0, 0])
+                .unwrap()
+                .map(|x| x.coord)
+                .collect::<Vec<u64>>();
+            assert_eq!(
+                vec![1, 3, 4, 5, 6, 7],
+                result,
+                "bbox within the range of coordinates; proximity point outside the result set"
+            );
+
+            let buffer = encoded_val_generator((2..5).rev(), features); // [4,3,2]
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            let result = bbox_proximity_filter(coords, [1, 1, 3, 1], [0, 0]) // bbox is 3-7; proximity is 0
+                .unwrap()
+                .map(|x| x.coord)
+                .collect::<Vec<u64>>();
+            assert_eq!(
             vec![3],
             result,
             "bbox starts in between the list of coordinates and ends after; proximity point outside the result set"
         );
 
-        let sparse: Vec<u32> = vec![24, 23, 13, 8, 7, 6, 1];
-        let buffer = encoded_val_generator(sparse.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        // bbox is 7-23; proximity is 7
-        let result = bbox_proximity_filter(coords, [3, 1, 7, 1], [3, 1])
-            .unwrap()
-            .map(|x| x.coord)
-            .collect::<Vec<u32>>();
-        assert_eq!(
-            vec![7, 23],
-            result,
-            "bbox within sparse result set; proximity within result set"
-        );
+            let sparse: Vec<u64> = vec![24, 23, 13, 8, 7, 6, 1];
+            let buffer = encoded_val_generator(sparse.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            // bbox is 7-23; proximity is 7
+            let result = bbox_proximity_filter(coords, [3, 1, 7, 1], [3, 1])
+                .unwrap()
+                .map(|x| x.coord)
+                .collect::<Vec<u64>>();
+            assert_eq!(
+                vec![7, 23],
+                result,
+                "bbox within sparse result set; proximity within result set"
+            );
+        }
     }
 
     #[test]
     fn binary_search() {
-        // Empty Coord list
-        let empty: Vec<u32> = vec![];
-        let buffer = encoded_val_generator(empty.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-        assert_eq!(coord_binary_search(&coords, 0, 0), Err("Offset greater than Vector"));
-        assert_eq!(coord_binary_search(&coords, 1, 0), Err("Offset greater than Vector"));
-
-        // Single Coord list
-        let single: Vec<u32> = vec![0];
-        let buffer = encoded_val_generator(single.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-
-        assert_eq!(coord_binary_search(&coords, 0, 0), Ok(0));
-        assert_eq!(coord_binary_search(&coords, 1, 0), Ok(0));
-
-        // Continuous Coord list
-        let buffer = encoded_val_generator((4..8).rev()); // [7,6,5,4]
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-
-        assert_eq!(coord_binary_search(&coords, 0, 0), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 4, 0), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 4, 1), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 5, 0), Ok(2));
-        assert_eq!(coord_binary_search(&coords, 6, 0), Ok(1));
-        assert_eq!(coord_binary_search(&coords, 7, 0), Ok(0));
-        assert_eq!(coord_binary_search(&coords, 7, 3), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 7, 4), Err("Offset greater than Vector"));
-        assert_eq!(coord_binary_search(&coords, 8, 0), Ok(0));
-
-        // Sparse Coord list
-        let sparse: Vec<u32> = vec![7, 4, 2, 1];
-        let buffer = encoded_val_generator(sparse.into_iter());
-        let reader = gridstore_format::Reader::new(buffer.as_slice());
-        let coords = get_coords_from_reader(&reader);
-
-        assert_eq!(coord_binary_search(&coords, 0, 0), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 1, 0), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 1, 1), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 2, 0), Ok(2));
-        assert_eq!(coord_binary_search(&coords, 3, 0), Ok(2));
-        assert_eq!(coord_binary_search(&coords, 4, 0), Ok(1));
-        assert_eq!(coord_binary_search(&coords, 5, 0), Ok(1));
-        assert_eq!(coord_binary_search(&coords, 7, 0), Ok(0));
-        assert_eq!(coord_binary_search(&coords, 7, 3), Ok(3));
-        assert_eq!(coord_binary_search(&coords, 7, 4), Err("Offset greater than Vector"));
-        assert_eq!(coord_binary_search(&coords, 8, 0), Ok(0));
+        for features in layouts().iter() {
+            // Empty Coord list
+            let empty: Vec<u64> = vec![];
+            let buffer = encoded_val_generator(empty.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+            assert_eq!(coord_binary_search(&coords, 0, 0), Err("Offset greater than Vector"));
+            assert_eq!(coord_binary_search(&coords, 1, 0), Err("Offset greater than Vector"));
+
+            // Single Coord list
+            let single: Vec<u64> = vec![0];
+            let buffer = encoded_val_generator(single.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+
+            assert_eq!(coord_binary_search(&coords, 0, 0), Ok(0));
+            assert_eq!(coord_binary_search(&coords, 1, 0), Ok(0));
+
+            // Continuous Coord list
+            let buffer = encoded_val_generator((4..8).rev(), features); // [7,6,5,4]
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+
+            assert_eq!(coord_binary_search(&coords, 0, 0), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 4, 0), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 4, 1), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 5, 0), Ok(2));
+            assert_eq!(coord_binary_search(&coords, 6, 0), Ok(1));
+            assert_eq!(coord_binary_search(&coords, 7, 0), Ok(0));
+            assert_eq!(coord_binary_search(&coords, 7, 3), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 7, 4), Err("Offset greater than Vector"));
+            assert_eq!(coord_binary_search(&coords, 8, 0), Ok(0));
+
+            // Sparse Coord list
+            let sparse: Vec<u64> = vec![7, 4, 2, 1];
+            let buffer = encoded_val_generator(sparse.into_iter(), features);
+            let reader = gridstore_format::Reader::new(buffer.as_slice());
+            let coords = get_coords_from_reader(&reader, features);
+
+            assert_eq!(coord_binary_search(&coords, 0, 0), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 1, 0), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 1, 1), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 2, 0), Ok(2));
+            assert_eq!(coord_binary_search(&coords, 3, 0), Ok(2));
+            assert_eq!(coord_binary_search(&coords, 4, 0), Ok(1));
+            assert_eq!(coord_binary_search(&coords, 5, 0), Ok(1));
+            assert_eq!(coord_binary_search(&coords, 7, 0), Ok(0));
+            assert_eq!(coord_binary_search(&coords, 7, 3), Ok(3));
+            assert_eq!(coord_binary_search(&coords, 7, 4), Err("Offset greater than Vector"));
+            assert_eq!(coord_binary_search(&coords, 8, 0), Ok(0));
+        }
     }
 }
 
 /// Calculates the tile distance between a proximity x and y and a grid x and y
-pub fn tile_dist(proximity_x: u16, proximity_y: u16, grid_x: u16, grid_y: u16) -> f64 {
+pub fn tile_dist(proximity_x: u32, proximity_y: u32, grid_x: u32, grid_y: u32) -> f64 {
     let dx = (proximity_x as f64) - (grid_x as f64);
     let dy = (proximity_y as f64) - (grid_y as f64);
     ((dx * dx) + (dy * dy)).sqrt()
@@ -532,6 +586,24 @@ fn tiles_per_mile_by_zoom_test() {
         0.031214753848498707,
         "Tiles per mile should work for down to zoom 6"
     );
+    assert_eq!(
+        tiles_per_mile_by_zoom(18),
+        4.05,
+        "Tiles per mile should keep scaling by 1.5 past zoom 16"
+    );
+}
+
+#[test]
+fn morton_test() {
+    for &(x, y) in &[(0u16, 0u16), (1, 0), (0, 1), (3, 5), (1234, 4321), (65535, 65535)] {
+        let z = interleave_morton(u32::from(x), u32::from(y));
+        assert_eq!(z, u64::from(morton::interleave_morton(x, y)), "matches the u16 encoding");
+        assert_eq!(deinterleave_morton(z), (u32::from(x), u32::from(y)));
+    }
+    let (x, y) = (0x0003_fffe, 0x0002_0001);
+    assert!(interleave_morton(x, y) > u64::from(u32::MAX));
+    assert_eq!(deinterleave_morton(interleave_morton(x, y)), (x, y));
+    assert_eq!(deinterleave_morton(u64::MAX), (u32::MAX, u32::MAX));
 }
 
 /// Convert proximity radius from miles into scaled number of tiles
@@ -599,42 +671,59 @@ pub fn scoredist(mut zoom: u16, mut distance: f64, mut score: u8, radius: f64) -
 }
 
 #[inline(always)]
-pub fn adjust_bbox_zoom(bbox: [u16; 4], source_z: u16, target_z: u16) -> [u16; 4] {
+pub fn adjust_bbox_zoom(bbox: [u32; 4], source_z: u16, target_z: u16) -> [u32; 4] {
+    // do this at u64 so that a difference of 32 zoom levels, possible with wide coords, doesn't
+    // overflow the shift, and saturate anything that still doesn't fit back in a u32
+    let saturate = |v: u64| v.min(u64::from(u32::MAX)) as u32;
     if target_z < source_z {
         let zoom_levels = source_z - target_z;
         // If this is a zoom out, divide each coordinate by 2^(number of zoom levels).
         // This is the same as shifting bits to the right by the number of zoom levels.
         [
-            bbox[0] >> zoom_levels,
-            bbox[1] >> zoom_levels,
-            bbox[2] >> zoom_levels,
-            bbox[3] >> zoom_levels,
+            saturate(u64::from(bbox[0]) >> zoom_levels),
+            saturate(u64::from(bbox[1]) >> zoom_levels),
+            saturate(u64::from(bbox[2]) >> zoom_levels),
+            saturate(u64::from(bbox[3]) >> zoom_levels),
         ]
     } else {
         // If this is a zoom in
-        let scale_multiplier = 1 << (target_z - source_z);
+        let scale_multiplier = 1u64 << (target_z - source_z);
 
         // Scale the top left (min x and y) tile coordinates by 2^(zoom diff).
         // Scale the bottom right (max x and y) tile coordinates by 2^(zoom diff),
         // and add the new number of tiles (-1) to get the outer edge of possible tiles.
-        // We subtract 1 from the scale_multiplier before adding to prevent an integer overflow
-        // given that we're using a 16bit integer
         [
-            bbox[0] * scale_multiplier,
-            bbox[1] * scale_multiplier,
-            bbox[2] * scale_multiplier + (scale_multiplier - 1),
-            bbox[3] * scale_multiplier + (scale_multiplier - 1),
+            saturate(u64::from(bbox[0]) * scale_multiplier),
+            saturate(u64::from(bbox[1]) * scale_multiplier),
+            saturate(u64::from(bbox[2]) * scale_multiplier + (scale_multiplier - 1)),
+            saturate(u64::from(bbox[3]) * scale_multiplier + (scale_multiplier - 1)),
         ]
     }
 }
 
-pub fn global_bbox_for_zoom(zoom: u16) -> Vec<[u16; 4]> {
-    // do this at u32 to avoid overflow at z16
-    let max: u32 = (1u32 << zoom) - 1;
-    let max = max as u16;
+pub fn global_bbox_for_zoom(zoom: u16) -> Vec<[u32; 4]> {
+    // do this at u64 to avoid overflow at z32
+    let max = ((1u64 << zoom) - 1) as u32;
     vec![[0, 0, max, max]]
 }
 
+#[test]
+fn adjust_bbox_zoom_test() {
+    assert_eq!(adjust_bbox_zoom([1, 2, 3, 4], 6, 6), [1, 2, 3, 4], "Same zoom is unchanged");
+    assert_eq!(adjust_bbox_zoom([1, 2, 3, 4], 6, 7), [2, 4, 7, 9], "Zoom in covers the children");
+    assert_eq!(adjust_bbox_zoom([2, 4, 7, 9], 7, 6), [1, 2, 3, 4], "Zoom out finds the parents");
+    assert_eq!(
+        adjust_bbox_zoom([0, 0, 0, 0], 0, 32),
+        [0, 0, u32::MAX, u32::MAX],
+        "Zooming in from z0 to z32 covers the whole z32 space without overflowing"
+    );
+    assert_eq!(
+        adjust_bbox_zoom([0, 0, u32::MAX, u32::MAX], 32, 0),
+        [0, 0, 0, 0],
+        "Zooming out from z32 to z0 lands on the single z0 tile"
+    );
+}
+
 #[test]
 fn scoredist_test() {
     assert_eq!(scoredist(14, 1., 0, 400.), 321.7508133738646, "scoredist for a feature 1 tile away from proximity point with score 0 and radius 400 should be 321.7508133738646");
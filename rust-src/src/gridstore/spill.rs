@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use failure::Error;
+
+use crate::gridstore::builder::{count_ids, merge_entries, BuilderEntry};
+use crate::gridstore::common::GridKey;
+
+/// One builder entry as it's written to a run.
+pub struct RunRecord {
+    pub key: GridKey,
+    /// Whether this entry was `insert`ed, and so replaces whatever earlier runs hold for its key
+    /// rather than adding to it
+    pub replaces: bool,
+    pub entry: BuilderEntry,
+}
+
+/// Writes records, which must already be sorted by key, to a new run file. Each record is the
+/// phrase id, lang set and replace flag, then a count of (relev/score, morton coord, id) triples
+/// and the triples themselves, all little-endian.
+pub fn write_run<I: IntoIterator<Item = RunRecord>>(path: &Path, records: I) -> Result<(), Error> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for record in records {
+        writer.write_u32::<LittleEndian>(record.key.phrase_id)?;
+        writer.write_u128::<LittleEndian>(record.key.lang_set)?;
+        writer.write_u8(record.replaces as u8)?;
+
+        writer.write_u32::<LittleEndian>(count_ids(&record.entry) as u32)?;
+        for (relev_score, coords) in record.entry.iter() {
+            for (coord, ids) in coords.iter() {
+                for id_comp in ids.iter() {
+                    writer.write_u32::<LittleEndian>(*relev_score)?;
+                    writer.write_u64::<LittleEndian>(*coord)?;
+                    writer.write_u64::<LittleEndian>(*id_comp)?;
+                }
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a run back in the order it was written.
+pub struct RunReader {
+    reader: BufReader<File>,
+}
+
+impl RunReader {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Ok(RunReader { reader: BufReader::new(File::open(path)?) })
+    }
+
+    fn read_record(&mut self) -> Result<Option<RunRecord>, Error> {
+        if self.reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        let phrase_id = self.reader.read_u32::<LittleEndian>()?;
+        let lang_set = self.reader.read_u128::<LittleEndian>()?;
+        let replaces = self.reader.read_u8()? != 0;
+
+        let mut entry = BuilderEntry::new();
+        for _ in 0..self.reader.read_u32::<LittleEndian>()? {
+            let relev_score = self.reader.read_u32::<LittleEndian>()?;
+            let coord = self.reader.read_u64::<LittleEndian>()?;
+            let id_comp = self.reader.read_u64::<LittleEndian>()?;
+            entry.entry(relev_score).or_default().entry(coord).or_default().push(id_comp);
+        }
+        Ok(Some(RunRecord { key: GridKey { phrase_id, lang_set }, replaces, entry }))
+    }
+}
+
+impl Iterator for RunReader {
+    type Item = Result<RunRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+pub type RecordSource = Box<dyn Iterator<Item = Result<RunRecord, Error>>>;
+
+/// A k-way merge of several sources of sorted records, combining the records each has for the
+/// same key in source order. Reading stops at the first error, which `finish` then returns.
+pub struct RunMerge {
+    sources: Vec<RecordSource>,
+    heads: Vec<Option<RunRecord>>,
+    queue: BinaryHeap<Reverse<(GridKey, usize)>>,
+    error: Option<Error>,
+}
+
+impl RunMerge {
+    /// Merges sources given oldest first, so that a later `insert` replaces earlier data.
+    pub fn new(sources: Vec<RecordSource>) -> Self {
+        let heads = sources.iter().map(|_| None).collect();
+        let mut merge = RunMerge { sources, heads, queue: BinaryHeap::new(), error: None };
+        for idx in 0..merge.sources.len() {
+            merge.advance(idx);
+        }
+        merge
+    }
+
+    fn advance(&mut self, idx: usize) {
+        match self.sources[idx].next() {
+            Some(Ok(record)) => {
+                self.queue.push(Reverse((record.key.clone(), idx)));
+                self.heads[idx] = Some(record);
+            }
+            Some(Err(e)) => {
+                self.error.get_or_insert(e);
+            }
+            None => {}
+        }
+    }
+
+    pub fn finish(self) -> Result<(), Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Iterator for RunMerge {
+    type Item = (GridKey, BuilderEntry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        let key = match self.queue.peek() {
+            Some(Reverse((key, _))) => key.clone(),
+            None => return None,
+        };
+
+        let mut merged = BuilderEntry::new();
+        // ties on the key come off the queue in source order
+        while self.queue.peek().filter(|Reverse((next_key, _))| *next_key == key).is_some() {
+            let Reverse((_, idx)) = self.queue.pop().unwrap();
+            let record = self.heads[idx].take().expect("queued source has a record");
+            if record.replaces {
+                merged = record.entry;
+            } else {
+                merge_entries(record.entry, &mut merged);
+            }
+            self.advance(idx);
+        }
+        if self.error.is_some() {
+            return None;
+        }
+        Some((key, merged))
+    }
+}
@@ -0,0 +1,178 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use failure::Error;
+use serde::Serialize;
+
+use crate::gridstore::common::*;
+use crate::gridstore::gridstore_format::{self, DecodeError};
+use crate::gridstore::metadata::FormatFeatures;
+use crate::gridstore::shared_values::reference_target;
+use crate::gridstore::spatial::deinterleave_morton;
+use crate::gridstore::store::GridStore;
+
+/// How many of the largest records `GridStore::stats` and `BuildReport` list.
+pub(crate) const LARGEST_RECORDS: usize = 10;
+
+/// A streaming summary of a set of counts, bucketed by powers of two: bucket 0 holds zeroes, and
+/// bucket `i` holds values in `[2^(i-1), 2^i)`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Histogram {
+    pub count: usize,
+    pub total: u64,
+    pub min: u64,
+    pub max: u64,
+    pub buckets: Vec<usize>,
+}
+
+impl Histogram {
+    pub fn add(&mut self, value: u64) {
+        if self.count == 0 || value < self.min {
+            self.min = value;
+        }
+        self.max = self.max.max(value);
+        self.count += 1;
+        self.total += value;
+
+        let bucket = (64 - value.leading_zeros()) as usize;
+        if self.buckets.len() <= bucket {
+            self.buckets.resize(bucket + 1, 0);
+        }
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.
+        } else {
+            self.total as f64 / self.count as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RecordTypeStats {
+    pub records: usize,
+    /// Combined size of the keys and values
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LargeRecord {
+    pub marker: TypeMarker,
+    /// For feature index records, `phrase_id` is the feature id, and for tile index records it's
+    /// the morton-coded tile. Stores with wide coords key tiles in eight bytes, so there it's the
+    /// tile's upper 32 bits, with the lower ones in `lang_set`.
+    pub key: GridKey,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GridStoreStats {
+    /// Record counts and sizes for each kind of key
+    pub records_by_type: BTreeMap<TypeMarker, RecordTypeStats>,
+    /// Records under the special `~` keys, such as `~BOUNDS`
+    pub special_records: RecordTypeStats,
+    pub total_bytes: u64,
+    /// Number of (coord, id) entries in each relev/score bucket, keyed by the packed relev/score
+    /// (see `pack_relev_score`), across single phrases
+    pub entries_by_relev_score: BTreeMap<u32, u64>,
+    pub coords_per_phrase: Histogram,
+    pub ids_per_phrase: Histogram,
+    /// The largest records of any kind, biggest first
+    pub largest_records: Vec<LargeRecord>,
+    /// `[min x, min y, max x, max y]` over every coord in every single phrase, if there are any
+    pub extent: Option<[u32; 4]>,
+    /// Phrase records stored as a reference to an identical record, in stores with shared values
+    pub shared_values: usize,
+}
+
+/// Per-record totals, for feeding into the store-wide stats.
+#[derive(Default)]
+struct RecordCounts {
+    coords: u64,
+    ids: u64,
+}
+
+fn count_record(
+    value: &[u8],
+    features: &FormatFeatures,
+    stats: &mut GridStoreStats,
+) -> Result<RecordCounts, DecodeError> {
+    let reader = gridstore_format::Reader::new(value);
+    let record = gridstore_format::read_phrase_record_from(&reader)?;
+
+    let mut counts = RecordCounts::default();
+    for rs in reader.read_var_vec(record.relev_scores)?.iter() {
+        let coords = reader.read_coord_vec(rs.coords, features)?;
+        let mut entries = 0;
+        for coord in coords.iter() {
+            entries += reader.read_id_list(coord.ids, features)?.len() as u64;
+
+            let (x, y) = deinterleave_morton(coord.coord);
+            stats.extent = Some(match stats.extent {
+                Some([min_x, min_y, max_x, max_y]) => {
+                    [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+                }
+                None => [x, y, x, y],
+            });
+        }
+        *stats.entries_by_relev_score.entry(rs.relev_score).or_insert(0) += entries;
+        counts.coords += coords.len() as u64;
+        counts.ids += entries;
+    }
+    Ok(counts)
+}
+
+impl GridStore {
+    /// Summarizes the contents of the whole store. This reads every record, so it takes about as
+    /// long as a full dump.
+    pub fn stats(&self) -> Result<GridStoreStats, Error> {
+        let mut stats = GridStoreStats::default();
+        let mut largest: BinaryHeap<Reverse<(usize, TypeMarker, Vec<u8>)>> = BinaryHeap::new();
+
+        for item in self.storage.iter_from(&[]) {
+            let (db_key, value) = item?;
+            let bytes = (db_key.len() + value.as_ref().len()) as u64;
+            stats.total_bytes += bytes;
+
+            let marker = match TypeMarker::from_key(&db_key) {
+                Some(marker) => marker,
+                None => {
+                    stats.special_records.records += 1;
+                    stats.special_records.bytes += bytes;
+                    continue;
+                }
+            };
+            let type_stats = stats.records_by_type.entry(marker).or_default();
+            type_stats.records += 1;
+            type_stats.bytes += bytes;
+
+            largest.push(Reverse((value.as_ref().len(), marker, db_key.to_vec())));
+            if largest.len() > LARGEST_RECORDS {
+                largest.pop();
+            }
+
+            if marker == TypeMarker::SinglePhrase {
+                // a shared record counts toward every phrase that points to it
+                if self.format_features.shared_values && reference_target(value.as_ref()).is_some()
+                {
+                    stats.shared_values += 1;
+                }
+                let record = self.resolve(value)?;
+                let counts = count_record(record.as_ref(), &self.format_features, &mut stats)?;
+                stats.coords_per_phrase.add(counts.coords);
+                stats.ids_per_phrase.add(counts.ids);
+            }
+        }
+
+        for Reverse((bytes, marker, db_key)) in largest.into_sorted_vec() {
+            stats.largest_records.push(LargeRecord {
+                marker,
+                key: GridKey::read_from(&db_key)?,
+                bytes,
+            });
+        }
+        Ok(stats)
+    }
+}
@@ -0,0 +1,535 @@
+use std::collections::{btree_map, BTreeMap};
+use std::ffi::OsString;
+use std::fmt::{self, Debug};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use failure::{Error, Fail};
+use memmap::Mmap;
+use rocksdb::{DBVector, Direction, IteratorMode, Options, SstFileWriter, DB};
+
+/// Which on-disk layout `GridStoreBuilder::finish` should produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageFormat {
+    /// A RocksDB directory
+    RocksDB,
+    /// A single immutable file holding a sorted key index and the value blobs, read through mmap
+    SingleFile,
+}
+
+/// A value read out of a storage backend. RocksDB lookups hand back the buffer RocksDB read the
+/// value into, and memory-mapped backends hand out views into the map, instead of copies.
+pub enum StorageValue {
+    Owned(Box<[u8]>),
+    RocksDB(DBVector),
+    Mapped { map: Arc<Mmap>, start: usize, end: usize },
+}
+
+impl AsRef<[u8]> for StorageValue {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            StorageValue::Owned(value) => value,
+            StorageValue::RocksDB(value) => value,
+            StorageValue::Mapped { map, start, end } => &map[*start..*end],
+        }
+    }
+}
+
+impl Debug for StorageValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("StorageValue").field(&self.as_ref()).finish()
+    }
+}
+
+impl From<&[u8]> for StorageValue {
+    fn from(value: &[u8]) -> Self {
+        StorageValue::Owned(value.into())
+    }
+}
+
+/// Key/value pairs coming out of a storage backend, in ascending key order, or an error for any
+/// pair that can't be read
+pub type StorageIter<'a> = Box<dyn Iterator<Item = Result<(Box<[u8]>, StorageValue), Error>> + 'a>;
+
+/// An ordered key/value backend that a `GridStore` reads its records from
+pub trait GridStorage: Debug + Send + Sync {
+    /// Returns the value stored under `key`, if there is one
+    fn get(&self, key: &[u8]) -> Result<Option<StorageValue>, Error>;
+
+    /// Iterates over every key/value pair whose key is greater than or equal to `start`, in
+    /// ascending byte order of the keys
+    fn iter_from<'a>(&'a self, start: &[u8]) -> StorageIter<'a>;
+}
+
+/// A destination for the records that `GridStoreBuilder` produces. The builder puts the records
+/// of each type marker in ascending key order, though special `~` keys can come at any point.
+pub trait GridStorageWriter {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error>;
+}
+
+/// Storage backed by a RocksDB directory
+#[derive(Debug)]
+pub struct RocksDBStorage {
+    db: DB,
+}
+
+impl RocksDBStorage {
+    /// Opens an existing RocksDB directory for reading
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let mut opts = Options::default();
+        opts.set_read_only(true);
+        opts.set_allow_mmap_reads(true);
+        let db = DB::open(&opts, path.as_ref())?;
+        Ok(RocksDBStorage { db })
+    }
+}
+
+impl GridStorage for RocksDBStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<StorageValue>, Error> {
+        Ok(self.db.get(key)?.map(StorageValue::RocksDB))
+    }
+
+    fn iter_from<'a>(&'a self, start: &[u8]) -> StorageIter<'a> {
+        Box::new(
+            self.db
+                .iterator(IteratorMode::From(start, Direction::Forward))
+                .map(|(key, value)| Ok((key, StorageValue::Owned(value)))),
+        )
+    }
+}
+
+/// Writes a new RocksDB directory by building sorted SST files, one per type marker, and ingesting
+/// them all once writing is done, which skips the memtable and the write-ahead log and leaves
+/// nothing to compact. Special `~` keys can come in any order, so they're held until `finish` and
+/// then written to an SST file of their own.
+pub struct RocksDBBulkWriter<'a> {
+    db: DB,
+    sst_options: &'a Options,
+    sst_dir: tempfile::TempDir,
+    sst_writers: BTreeMap<u8, (SstFileWriter<'a>, PathBuf)>,
+    special: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl<'a> RocksDBBulkWriter<'a> {
+    /// The options to build SST files with, which have to outlive the writer
+    pub fn sst_options() -> Options {
+        let mut opts = Options::default();
+        opts.prepare_for_bulk_load();
+        opts
+    }
+
+    /// Creates a RocksDB directory at `path`, which shouldn't already hold a database
+    pub fn create<P: AsRef<Path>>(path: P, sst_options: &'a Options) -> Result<Self, Error> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, path.as_ref())?;
+        // staging the files inside the database directory lets ingestion move them into place
+        // instead of copying
+        let sst_dir = tempfile::Builder::new().prefix("sst").tempdir_in(path.as_ref())?;
+        Ok(RocksDBBulkWriter {
+            db,
+            sst_options,
+            sst_dir,
+            sst_writers: BTreeMap::new(),
+            special: BTreeMap::new(),
+        })
+    }
+
+    /// Finishes the SST files and ingests them into the database.
+    pub fn finish(self) -> Result<(), Error> {
+        let mut paths = Vec::with_capacity(self.sst_writers.len() + 1);
+        for (_, (mut sst_writer, sst_path)) in self.sst_writers {
+            sst_writer.finish()?;
+            paths.push(sst_path);
+        }
+        if !self.special.is_empty() {
+            let sst_path = self.sst_dir.path().join("special.sst");
+            let mut sst_writer = SstFileWriter::create(self.sst_options);
+            sst_writer.open(&sst_path)?;
+            for (key, value) in self.special.iter() {
+                sst_writer.put(key, value)?;
+            }
+            sst_writer.finish()?;
+            paths.push(sst_path);
+        }
+        if !paths.is_empty() {
+            self.db.ingest_external_file(paths)?;
+        }
+        self.sst_dir.close()?;
+        Ok(())
+    }
+}
+
+impl<'a> GridStorageWriter for RocksDBBulkWriter<'a> {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let marker = match key.first() {
+            Some(b'~') | None => {
+                self.special.insert(key.to_vec(), value.to_vec());
+                return Ok(());
+            }
+            Some(marker) => *marker,
+        };
+        let (sst_writer, _) = match self.sst_writers.entry(marker) {
+            btree_map::Entry::Occupied(entry) => entry.into_mut(),
+            btree_map::Entry::Vacant(entry) => {
+                let sst_path = self.sst_dir.path().join(format!("{}.sst", marker));
+                let mut sst_writer = SstFileWriter::create(self.sst_options);
+                sst_writer.open(&sst_path)?;
+                entry.insert((sst_writer, sst_path))
+            }
+        };
+        sst_writer.put(key, value)?;
+        Ok(())
+    }
+}
+
+/// Storage held entirely in memory, mostly useful for tests and embedded tools that don't want
+/// to create a RocksDB directory
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    data: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage { data: BTreeMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl GridStorage for MemoryStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<StorageValue>, Error> {
+        Ok(self.data.get(key).map(|value| value.as_slice().into()))
+    }
+
+    fn iter_from<'a>(&'a self, start: &[u8]) -> StorageIter<'a> {
+        Box::new(
+            self.data
+                .range(start.to_vec()..)
+                .map(|(key, value)| Ok((key.as_slice().into(), value.as_slice().into()))),
+        )
+    }
+}
+
+impl GridStorageWriter for MemoryStorage {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.data.insert(key.to_vec(), value.to_vec());
+        Ok(())
+    }
+}
+
+// The single-file layout is, in order:
+// * an 8-byte magic string followed by a u32 layout version
+// * the value blobs, back to back
+// * one index entry per key, in ascending key order: u16 key length, the key bytes, then the u64
+//   offset and u32 length of its value
+// * a table of u64 index entry offsets, one per key, which is what lookups binary search over
+// * a footer holding the u64 offset of that table, the u64 key count, and the magic string again
+// All integers are little-endian.
+const SINGLE_FILE_MAGIC: &[u8; 8] = b"CARMGRID";
+const SINGLE_FILE_LAYOUT_VERSION: u32 = 1;
+const SINGLE_FILE_HEADER_LENGTH: usize = 12;
+const SINGLE_FILE_FOOTER_LENGTH: usize = 24;
+
+/// Storage backed by a single immutable file written by `MmapStorageWriter`, read through mmap
+#[derive(Debug)]
+pub struct MmapStorage {
+    map: Arc<Mmap>,
+    table_offset: usize,
+    key_count: usize,
+}
+
+struct IndexEntry<'a> {
+    key: &'a [u8],
+    value_start: usize,
+    value_end: usize,
+}
+
+impl MmapStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = File::open(path.as_ref())?;
+        // Safe so long as nobody modifies the file while it's mapped; the writer only ever
+        // replaces files wholesale via rename, so existing maps keep seeing the old contents.
+        let map = unsafe { Mmap::map(&file)? };
+
+        if map.len() < SINGLE_FILE_HEADER_LENGTH + SINGLE_FILE_FOOTER_LENGTH
+            || &map[..8] != SINGLE_FILE_MAGIC
+            || &map[(map.len() - 8)..] != SINGLE_FILE_MAGIC
+        {
+            return Err(StorageError::Malformed { reason: "missing magic string" }.into());
+        }
+        let layout_version = LittleEndian::read_u32(&map[8..12]);
+        if layout_version != SINGLE_FILE_LAYOUT_VERSION {
+            return Err(StorageError::Malformed { reason: "unknown layout version" }.into());
+        }
+
+        let footer = &map[(map.len() - SINGLE_FILE_FOOTER_LENGTH)..];
+        let table_offset = LittleEndian::read_u64(&footer[0..8]) as usize;
+        let key_count = LittleEndian::read_u64(&footer[8..16]) as usize;
+        let table_end = key_count
+            .checked_mul(8)
+            .and_then(|table_length| table_offset.checked_add(table_length))
+            .ok_or(StorageError::Malformed { reason: "index table out of bounds" })?;
+        if table_offset < SINGLE_FILE_HEADER_LENGTH
+            || table_end != map.len() - SINGLE_FILE_FOOTER_LENGTH
+        {
+            return Err(StorageError::Malformed { reason: "index table out of bounds" }.into());
+        }
+
+        // the index entries themselves are only checked as they're read, so that opening a file
+        // doesn't have to touch every page of its index
+        Ok(MmapStorage { map: Arc::new(map), table_offset, key_count })
+    }
+
+    fn entry(&self, i: usize) -> Result<IndexEntry<'_>, Error> {
+        let malformed = || StorageError::Malformed { reason: "index entry out of bounds" };
+        let entry_offset =
+            LittleEndian::read_u64(&self.map[(self.table_offset + i * 8)..]) as usize;
+        let entry = self.map.get(entry_offset..self.table_offset).ok_or_else(malformed)?;
+        let key_length = LittleEndian::read_u16(entry.get(..2).ok_or_else(malformed)?) as usize;
+        let key = entry.get(2..(2 + key_length)).ok_or_else(malformed)?;
+        let value_ref = entry.get((2 + key_length)..(14 + key_length)).ok_or_else(malformed)?;
+        let value_start = LittleEndian::read_u64(&value_ref[..8]) as usize;
+        let value_end = value_start
+            .checked_add(LittleEndian::read_u32(&value_ref[8..]) as usize)
+            .ok_or_else(malformed)?;
+        if value_start < SINGLE_FILE_HEADER_LENGTH || value_end > entry_offset {
+            return Err(malformed().into());
+        }
+        Ok(IndexEntry { key, value_start, value_end })
+    }
+
+    /// Returns the position of the first key greater than or equal to `key`
+    fn lower_bound(&self, key: &[u8]) -> Result<usize, Error> {
+        let (mut low, mut high) = (0, self.key_count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.entry(mid)?.key < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(low)
+    }
+
+    fn value(&self, entry: &IndexEntry) -> StorageValue {
+        StorageValue::Mapped {
+            map: self.map.clone(),
+            start: entry.value_start,
+            end: entry.value_end,
+        }
+    }
+}
+
+impl GridStorage for MmapStorage {
+    fn get(&self, key: &[u8]) -> Result<Option<StorageValue>, Error> {
+        let position = self.lower_bound(key)?;
+        if position < self.key_count {
+            let entry = self.entry(position)?;
+            if entry.key == key {
+                return Ok(Some(self.value(&entry)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn iter_from<'a>(&'a self, start: &[u8]) -> StorageIter<'a> {
+        let start = match self.lower_bound(start) {
+            Ok(start) => start,
+            Err(e) => return Box::new(std::iter::once(Err(e))),
+        };
+        Box::new((start..self.key_count).map(move |i| {
+            let entry = self.entry(i)?;
+            Ok((entry.key.into(), self.value(&entry)))
+        }))
+    }
+}
+
+/// Writes the single-file layout that `MmapStorage` reads. Values are streamed to a temporary
+/// file as they're put, and the key index is sorted and appended in `finish`, at which point the
+/// temporary file is moved into place. If the writer is dropped before that, or `finish` fails,
+/// the temporary file is removed.
+pub struct MmapStorageWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    file: BufWriter<File>,
+    position: u64,
+    index: Vec<(Vec<u8>, u64, u32)>,
+}
+
+impl MmapStorageWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let mut tmp_path: OsString = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        file.write_all(SINGLE_FILE_MAGIC)?;
+        file.write_u32::<LittleEndian>(SINGLE_FILE_LAYOUT_VERSION)?;
+        Ok(MmapStorageWriter {
+            path,
+            tmp_path,
+            file,
+            position: SINGLE_FILE_HEADER_LENGTH as u64,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn finish(mut self) -> Result<(), Error> {
+        // later puts of the same key win, as they would in RocksDB; the sort is stable, so the
+        // last of each run of equal keys is the most recent
+        self.index.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut index: Vec<(Vec<u8>, u64, u32)> = Vec::with_capacity(self.index.len());
+        for entry in self.index.drain(..) {
+            match index.last_mut() {
+                Some(last) if last.0 == entry.0 => *last = entry,
+                _ => index.push(entry),
+            }
+        }
+
+        let mut entry_offsets: Vec<u64> = Vec::with_capacity(index.len());
+        for (key, value_offset, value_length) in index.iter() {
+            entry_offsets.push(self.position);
+            self.file.write_u16::<LittleEndian>(key.len() as u16)?;
+            self.file.write_all(key)?;
+            self.file.write_u64::<LittleEndian>(*value_offset)?;
+            self.file.write_u32::<LittleEndian>(*value_length)?;
+            self.position += 14 + key.len() as u64;
+        }
+
+        let table_offset = self.position;
+        for entry_offset in entry_offsets.iter() {
+            self.file.write_u64::<LittleEndian>(*entry_offset)?;
+        }
+        self.file.write_u64::<LittleEndian>(table_offset)?;
+        self.file.write_u64::<LittleEndian>(entry_offsets.len() as u64)?;
+        self.file.write_all(SINGLE_FILE_MAGIC)?;
+
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        fs::rename(&self.tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for MmapStorageWriter {
+    fn drop(&mut self) {
+        // once `finish` has moved the file into place there's nothing left to remove
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+impl GridStorageWriter for MmapStorageWriter {
+    fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        if key.len() > u16::MAX as usize {
+            return Err(StorageError::KeyTooLarge { key_length: key.len() }.into());
+        }
+        if value.len() > u32::MAX as usize {
+            return Err(StorageError::ValueTooLarge { value_length: value.len() }.into());
+        }
+        self.file.write_all(value)?;
+        self.index.push((key.to_vec(), self.position, value.len() as u32));
+        self.position += value.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Fail)]
+enum StorageError {
+    #[fail(display = "malformed single-file store: {}", reason)]
+    Malformed { reason: &'static str },
+    #[fail(display = "key too large for single-file store (length {})", key_length)]
+    KeyTooLarge { key_length: usize },
+    #[fail(display = "value too large for single-file store (length {})", value_length)]
+    ValueTooLarge { value_length: usize },
+}
+
+#[cfg(test)]
+use tempfile;
+
+#[test]
+fn memory_storage_test() {
+    let mut storage = MemoryStorage::new();
+    storage.put(&[1, 0, 0, 0, 2], b"b").unwrap();
+    storage.put(&[0, 0, 0, 0, 1], b"a").unwrap();
+    storage.put(b"~BOUNDS", b"").unwrap();
+
+    assert_eq!(storage.len(), 3);
+    assert_eq!(storage.get(&[0, 0, 0, 0, 1]).unwrap().unwrap().as_ref(), b"a");
+    assert!(storage.get(&[0, 0, 0, 0, 2]).unwrap().is_none());
+
+    let keys: Vec<_> = storage.iter_from(&[1]).map(|item| item.unwrap().0.to_vec()).collect();
+    assert_eq!(keys, vec![vec![1, 0, 0, 0, 2], b"~BOUNDS".to_vec()], "iterates in key order");
+}
+
+#[test]
+fn mmap_storage_test() {
+    let directory: tempfile::TempDir = tempfile::tempdir().unwrap();
+    let path = directory.path().join("store.gridstore");
+
+    let mut writer = MmapStorageWriter::create(&path).unwrap();
+    writer.put(&[1, 0, 0, 0, 2], b"bin").unwrap();
+    writer.put(&[0, 0, 0, 0, 1], b"stale").unwrap();
+    writer.put(&[0, 0, 0, 0, 1], b"a").unwrap();
+    writer.put(&[0, 0, 0, 0, 3], b"").unwrap();
+    writer.put(b"~BOUNDS", &[0, 0, 0, 0]).unwrap();
+    writer.finish().unwrap();
+
+    let storage = MmapStorage::open(&path).unwrap();
+    assert_eq!(storage.get(&[0, 0, 0, 0, 1]).unwrap().unwrap().as_ref(), b"a", "last put wins");
+    assert_eq!(storage.get(&[0, 0, 0, 0, 3]).unwrap().unwrap().as_ref(), b"");
+    assert!(storage.get(&[0, 0, 0, 0, 2]).unwrap().is_none());
+    assert!(storage.get(&[0]).unwrap().is_none());
+    assert!(storage.get(b"~ZZZ").unwrap().is_none());
+
+    let pairs: Vec<_> = storage
+        .iter_from(&[0, 0, 0, 0, 2])
+        .map(Result::unwrap)
+        .map(|(key, value)| (key.to_vec(), value.as_ref().to_vec()))
+        .collect();
+    assert_eq!(
+        pairs,
+        vec![
+            (vec![0, 0, 0, 0, 3], vec![]),
+            (vec![1, 0, 0, 0, 2], b"bin".to_vec()),
+            (b"~BOUNDS".to_vec(), vec![0, 0, 0, 0]),
+        ],
+        "iterates in key order from the requested start"
+    );
+    assert_eq!(storage.iter_from(&[]).count(), 4);
+
+    let tmp_path = directory.path().join("store.gridstore.tmp");
+    assert!(!tmp_path.exists(), "the temporary file is moved into place");
+
+    // index entries are only checked once they're read
+    let mut contents = fs::read(&path).unwrap();
+    let footer_start = contents.len() - SINGLE_FILE_FOOTER_LENGTH;
+    let table_offset = LittleEndian::read_u64(&contents[footer_start..]) as usize;
+    LittleEndian::write_u64(&mut contents[table_offset..], u64::MAX);
+    fs::write(&path, &contents).unwrap();
+    let storage = MmapStorage::open(&path).unwrap();
+    assert!(storage.get(&[0, 0, 0, 0, 1]).is_err());
+    assert!(storage.iter_from(&[]).next().unwrap().is_err());
+
+    // truncating the file should be caught on open rather than on read
+    fs::write(&path, &contents[..(contents.len() - 1)]).unwrap();
+    assert!(MmapStorage::open(&path).is_err());
+
+    let mut writer = MmapStorageWriter::create(&path).unwrap();
+    assert!(writer.put(&vec![0; u16::MAX as usize + 1], b"").is_err());
+    assert!(tmp_path.exists());
+    drop(writer);
+    assert!(!tmp_path.exists(), "an unfinished writer cleans up after itself");
+}
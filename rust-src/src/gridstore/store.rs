@@ -2,123 +2,195 @@ use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::convert::TryInto;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use byteorder::{BigEndian, ReadBytesExt};
-use failure::Error;
-use itertools::Itertools;
+use failure::{Error, Fail};
+use itertools::{Either, Itertools};
 use min_max_heap::MinMaxHeap;
-use morton::deinterleave_morton;
 use ordered_float::OrderedFloat;
-use rocksdb::{Direction, IteratorMode, Options, DB};
 use serde::Serialize;
 
+use crate::gridstore::builder::BuilderEntry;
 use crate::gridstore::common::*;
-use crate::gridstore::gridstore_format;
-use crate::gridstore::spatial;
+use crate::gridstore::gridstore_format::{self, CoordVec, DecodeError, FixedVecOffset};
+use crate::gridstore::metadata::{
+    read_format_features, read_format_version, FormatFeatures, GridStoreMetadata,
+    GridStoreOptionOverrides, GridStoreOptions, FORMAT_VERSION, MIN_FORMAT_VERSION,
+};
+use crate::gridstore::shared_values::reference_target;
+use crate::gridstore::spatial::{self, deinterleave_morton, interleave_morton};
+use crate::gridstore::storage::{GridStorage, MmapStorage, RocksDBStorage, StorageValue};
 
 #[derive(Debug, Serialize)]
 pub struct GridStore {
     #[serde(skip_serializing)]
-    db: DB,
+    pub(crate) storage: Box<dyn GridStorage>,
     #[serde(skip_serializing)]
     pub bin_boundaries: HashSet<u32>,
+    /// The same boundaries in ascending order, for splitting up range lookups
+    #[serde(skip_serializing)]
+    sorted_bin_boundaries: Vec<u32>,
     pub path: PathBuf,
     // options:
     pub zoom: u16,
     pub type_id: u16,
     pub coalesce_radius: f64,
-    pub bboxes: Vec<[u16; 4]>,
+    pub bboxes: Vec<[u32; 4]>,
     pub max_score: f64,
+    /// What the builder recorded about this store, if it was built with metadata
+    pub metadata: Option<GridStoreMetadata>,
+    /// The on-disk format version the store was written with
+    pub format_version: u32,
+    /// The optional encodings the store's records use
+    pub format_features: FormatFeatures,
+    /// Ids of features this store deletes from any store it's layered over
+    #[serde(skip_serializing)]
+    pub tombstones: HashSet<u32>,
+    /// Delta stores layered on top of this one with `with_overlay`, bottom-most first
+    #[serde(skip_serializing)]
+    overlays: Vec<GridStore>,
 }
 
-#[inline]
-fn decode_value<T: AsRef<[u8]>>(value: T) -> impl Iterator<Item = GridEntry> {
-    let record_ref = {
-        let value_ref: &[u8] = value.as_ref();
-        // this is pretty sketch: we're opting out of compiler lifetime protection
-        // for this reference. This usage should be safe though, because we'll move the
-        // reference and the underlying owned object around together as a unit (the
-        // tuple below) so that when we pull the reference into the inner closures,
-        // we'll drag the owned object along, and won't drop it until the whole
-        // nest of closures is deleted
-        let static_ref: &'static [u8] = unsafe { std::mem::transmute(value_ref) };
-        (value, static_ref)
-    };
-    let reader = gridstore_format::Reader::new(record_ref.1);
-    let record = { gridstore_format::read_phrase_record_from(&reader) };
-
-    let iter = gridstore_format::read_var_vec_raw(record_ref.1, record.relev_scores)
+#[derive(Debug, Fail)]
+pub enum StoreError {
+    #[fail(display = "store was built at zoom {} but opened at zoom {}", built, requested)]
+    ZoomMismatch { built: u16, requested: u16 },
+    #[fail(
+        display = "store has format version {}, but only versions {} through {} are supported",
+        found, min_supported, max_supported
+    )]
+    UnsupportedFormatVersion { found: u32, min_supported: u32, max_supported: u32 },
+    #[fail(
+        display = "store supports zooms up to {}, but was opened at zoom {}",
+        max_supported, zoom
+    )]
+    UnsupportedZoom { zoom: u16, max_supported: u16 },
+    #[fail(display = "can't layer a zoom {} overlay onto a zoom {} store", overlay, base)]
+    OverlayZoomMismatch { base: u16, overlay: u16 },
+    #[fail(display = "store was built without a feature index")]
+    NoFeatureIndex,
+    #[fail(display = "store was built without a tile index")]
+    NoTileIndex,
+    #[fail(display = "shared value reference to {:?} doesn't lead to a record", target)]
+    BrokenReference { target: Vec<u8> },
+}
+
+fn open_storage(path: &Path) -> Result<Box<dyn GridStorage>, Error> {
+    // single-file stores are plain files, while RocksDB stores are directories
+    Ok(if path.is_file() {
+        Box::new(MmapStorage::open(path)?)
+    } else {
+        Box::new(RocksDBStorage::open_read_only(path)?)
+    })
+}
+
+/// A shared handle on a stored value, so that the lazy iterators decoding it can keep the bytes
+/// alive for as long as they need them.
+#[derive(Clone)]
+struct RecordBytes(Arc<StorageValue>);
+
+impl AsRef<[u8]> for RecordBytes {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+/// A record's coord vectors, each with the packed relev/score they're grouped under
+type CoordGroups = Vec<(u32, CoordVec<RecordBytes>)>;
+
+/// Decodes a record down to its relev/score groups, checking the root and every coord vector up
+/// front. The id lists the coords point to are checked as they're read.
+fn decode_groups(bytes: &RecordBytes, features: &FormatFeatures) -> Result<CoordGroups, Error> {
+    let reader = gridstore_format::Reader::new(bytes.clone());
+    let record = gridstore_format::read_phrase_record_from(&reader)?;
+    let relev_scores = gridstore_format::read_var_vec_raw(bytes.clone(), record.relev_scores)?;
+    let groups = relev_scores
         .into_iter()
-        .flat_map(move |rs_obj| {
-            // grab a reference to the outer object to make sure it doesn't get freed
-            let _ref = &record_ref;
-
-            let relev_score = rs_obj.relev_score;
-            let relev = relev_int_to_float(relev_score >> 4);
-            // mask for the least significant four bits
-            let score = relev_score & 15;
-
-            let nested_ref = record_ref.1;
-            gridstore_format::read_uniform_vec_raw(record_ref.1, rs_obj.coords)
-                .into_iter()
-                .flat_map(move |coords_obj| {
-                    let (x, y) = deinterleave_morton(coords_obj.coord);
+        .map(|rs_obj| {
+            let coords =
+                gridstore_format::read_coord_vec_raw(bytes.clone(), rs_obj.coords, features)?;
+            Ok((rs_obj.relev_score, coords))
+        })
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+    Ok(groups)
+}
 
-                    gridstore_format::read_fixed_vec_raw(nested_ref, coords_obj.ids)
-                        .into_iter()
-                        .map(move |id_comp| {
-                            let id = id_comp >> 8;
-                            let source_phrase_hash = (id_comp & 255) as u8;
-                            GridEntry { relev, score, x, y, id, source_phrase_hash }
-                        })
-                })
-        });
-    iter
+fn read_ids(
+    bytes: &RecordBytes,
+    offset: FixedVecOffset<u32>,
+    features: &FormatFeatures,
+) -> impl Iterator<Item = Result<u64, Error>> {
+    match gridstore_format::read_id_list_raw(bytes.clone(), offset, features) {
+        Ok(ids) => Either::Left(ids.into_iter().map(Ok)),
+        Err(e) => Either::Right(std::iter::once(Err(e.into()))),
+    }
 }
 
 #[inline]
-fn decode_matching_value<T: AsRef<[u8]>>(
-    value: T,
+fn decode_value(
+    value: StorageValue,
+    features: &FormatFeatures,
+) -> Result<impl Iterator<Item = Result<GridEntry, Error>>, Error> {
+    let features = *features;
+    let bytes = RecordBytes(Arc::new(value));
+    let groups = decode_groups(&bytes, &features)?;
+
+    let iter = groups.into_iter().flat_map(move |(relev_score, coords)| {
+        let (relev, score) = unpack_relev_score(relev_score);
+
+        let bytes = bytes.clone();
+        coords.into_iter().flat_map(move |coords_obj| {
+            let (x, y) = deinterleave_morton(coords_obj.coord);
+
+            read_ids(&bytes, coords_obj.ids, &features).map(move |id_comp| {
+                let id_comp = id_comp?;
+                let id = (id_comp >> 8) as u32;
+                let source_phrase_hash = (id_comp & 255) as u8;
+                Ok(GridEntry { relev, score, x, y, id, source_phrase_hash })
+            })
+        })
+    });
+    Ok(iter)
+}
+
+/// Decodes a whole record back into the form the builder keeps it in.
+pub(crate) fn decode_builder_entry(
+    value: StorageValue,
+    features: &FormatFeatures,
+) -> Result<BuilderEntry, Error> {
+    let bytes = RecordBytes(Arc::new(value));
+    let mut entry = BuilderEntry::new();
+    for (relev_score, coords) in decode_groups(&bytes, features)? {
+        let rs_entry = entry.entry(relev_score).or_default();
+        for coords_obj in coords.into_iter() {
+            let ids = read_ids(&bytes, coords_obj.ids, features).collect::<Result<_, _>>()?;
+            rs_entry.insert(coords_obj.coord, ids);
+        }
+    }
+    Ok(entry)
+}
+
+#[inline]
+fn decode_matching_value(
+    value: StorageValue,
     match_opts: &MatchOpts,
     matches_language: bool,
     coalesce_radius: f64,
-) -> impl Iterator<Item = MatchEntry> {
+    features: &FormatFeatures,
+) -> Result<impl Iterator<Item = Result<MatchEntry, Error>>, Error> {
     let match_opts = match_opts.clone();
+    let features = *features;
 
-    let record_ref = {
-        let value_ref: &[u8] = value.as_ref();
-        // this is pretty sketch: we're opting out of compiler lifetime protection
-        // for this reference. This usage should be safe though, because we'll move the
-        // reference and the underlying owned object around together as a unit (the
-        // tuple below) so that when we pull the reference into the inner closures,
-        // we'll drag the owned object along, and won't drop it until the whole
-        // nest of closures is deleted
-        let static_ref: &'static [u8] = unsafe { std::mem::transmute(value_ref) };
-        (value, static_ref)
-    };
-    let reader = gridstore_format::Reader::new(record_ref.1);
-    let record = { gridstore_format::read_phrase_record_from(&reader) };
-
-    let relevs = gridstore_format::read_var_vec_raw(record_ref.1, record.relev_scores)
-        .into_iter()
-        .map(|rs_obj| {
-            let relev_score = rs_obj.relev_score;
-            let relev = relev_int_to_float(relev_score >> 4);
-            // mask for the least significant four bits
-            let score = relev_score & 15;
-            (relev, score, rs_obj)
-        });
-
-    let iter = somewhat_eager_groupby(relevs.into_iter(), |(relev, _, _)| *relev)
-        .into_iter()
-        .flat_map(move |(relev, score_groups)| {
-            // grab a reference to the outer object to make sure it doesn't get freed
-            let _ref = &record_ref;
+    let bytes = RecordBytes(Arc::new(value));
+    let relevs = decode_groups(&bytes, &features)?.into_iter().map(|(relev_score, coords_vec)| {
+        let (relev, score) = unpack_relev_score(relev_score);
+        (relev, score, coords_vec)
+    });
 
+    let iter = somewhat_eager_groupby(relevs, |(relev, _, _)| *relev).flat_map(
+        move |(relev, score_groups)| {
             let match_opts = match_opts.clone();
-            let nested_ref = _ref.1;
-            let coords_per_score = score_groups.into_iter().map(move |(_, score, rs_obj)| {
-                let coords_vec = gridstore_format::read_uniform_vec_raw(nested_ref, rs_obj.coords);
+            let coords_per_score = score_groups.into_iter().map(move |(_, score, coords_vec)| {
                 let coords =
                     match &match_opts {
                         MatchOpts { bbox: None, proximity: None, .. } => {
@@ -181,15 +253,14 @@ fn decode_matching_value<T: AsRef<[u8]>>(
                 scoredist1.partial_cmp(scoredist2).unwrap() == Ordering::Greater
             });
 
-            let nested_ref = record_ref.1;
+            let bytes = bytes.clone();
             all_coords.flat_map(
                 move |(distance, within_radius, score, scoredist, x, y, coords_obj)| {
-                    let ids = gridstore_format::read_fixed_vec_raw(nested_ref, coords_obj.ids);
-
-                    ids.into_iter().map(move |id_comp| {
-                        let id = id_comp >> 8;
+                    read_ids(&bytes, coords_obj.ids, &features).map(move |id_comp| {
+                        let id_comp = id_comp?;
+                        let id = (id_comp >> 8) as u32;
                         let source_phrase_hash = (id_comp & 255) as u8;
-                        MatchEntry {
+                        Ok(MatchEntry {
                             grid_entry: GridEntry {
                                 relev: relev
                                     * (if matches_language || within_radius {
@@ -206,151 +277,408 @@ fn decode_matching_value<T: AsRef<[u8]>>(
                             matches_language,
                             distance,
                             scoredist,
-                        }
+                        })
                     })
                 },
             )
-        });
-    iter
+        },
+    );
+    Ok(iter)
+}
+
+/// Reads a list of little-endian u32s, ignoring any trailing partial value.
+fn decode_u32_list(encoded: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    encoded.chunks_exact(4).map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+}
+
+/// The tombstones of every layer above the one at the start of `layers`.
+fn tombstones_above<'a>(layers: &[&'a GridStore]) -> Vec<&'a HashSet<u32>> {
+    layers
+        .iter()
+        .skip(1)
+        .map(|layer| &layer.tombstones)
+        .filter(|tombstones| !tombstones.is_empty())
+        .collect()
+}
+
+/// Where an entry falls in the order records are written in: relev, score, then morton coord, then
+/// id and source phrase hash, all descending. Relevs compare in 255ths, which orders them the same
+/// whichever encoding they came from.
+fn grid_entry_rank(entry: &GridEntry) -> (u8, u8, u64, u64) {
+    (
+        relev_float_to_byte(entry.relev),
+        entry.score,
+        interleave_morton(entry.x, entry.y),
+        (u64::from(entry.id) << 8) | u64::from(entry.source_phrase_hash),
+    )
+}
+
+fn match_entry_sort_key(
+    entry: &MatchEntry,
+) -> (OrderedFloat<f64>, OrderedFloat<f64>, bool, u32, u32, u32) {
+    (
+        OrderedFloat(entry.grid_entry.relev),
+        OrderedFloat(entry.scoredist),
+        entry.matches_language,
+        entry.grid_entry.x,
+        entry.grid_entry.y,
+        entry.grid_entry.id,
+    )
+}
+
+/// Whether `a` should come out of a merge of several layers' results before `b`. Errors come out
+/// as soon as they're reached.
+fn merge_before<T, K: Ord, F: Fn(&T) -> K>(
+    a: &Result<T, Error>,
+    b: &Result<T, Error>,
+    key: F,
+) -> bool {
+    match (a, b) {
+        (Ok(a), Ok(b)) => key(a) > key(b),
+        (Err(_), _) => true,
+        (Ok(_), Err(_)) => false,
+    }
+}
+
+/// Adjacent results in a merge, handed back by `drop_repeat` when they differ
+type ResultPair<T> = (Result<T, Error>, Result<T, Error>);
+
+/// Collapses an entry that's repeated across layers, for `Itertools::coalesce`.
+fn drop_repeat<T: PartialEq>(
+    a: Result<T, Error>,
+    b: Result<T, Error>,
+) -> Result<Result<T, Error>, ResultPair<T>> {
+    match (a, b) {
+        (Ok(a), Ok(b)) if a == b => Ok(Ok(a)),
+        (a, b) => Err((a, b)),
+    }
 }
 
-struct QueueElement<T: Iterator<Item = MatchEntry>> {
+struct QueueElement<T: Iterator<Item = Result<MatchEntry, Error>>> {
     next_entry: MatchEntry,
     entry_iter: T,
 }
 
-impl<T: Iterator<Item = MatchEntry>> QueueElement<T> {
-    fn sort_key(&self) -> (OrderedFloat<f64>, OrderedFloat<f64>, bool, u16, u16, u32) {
-        (
-            OrderedFloat(self.next_entry.grid_entry.relev),
-            OrderedFloat(self.next_entry.scoredist),
-            self.next_entry.matches_language,
-            self.next_entry.grid_entry.x,
-            self.next_entry.grid_entry.y,
-            self.next_entry.grid_entry.id,
-        )
+impl<T: Iterator<Item = Result<MatchEntry, Error>>> QueueElement<T> {
+    fn sort_key(&self) -> (OrderedFloat<f64>, OrderedFloat<f64>, bool, u32, u32, u32) {
+        match_entry_sort_key(&self.next_entry)
     }
 }
 
-impl<T: Iterator<Item = MatchEntry>> Ord for QueueElement<T> {
+impl<T: Iterator<Item = Result<MatchEntry, Error>>> Ord for QueueElement<T> {
     fn cmp(&self, other: &Self) -> Ordering {
         self.sort_key().cmp(&other.sort_key())
     }
 }
 
-impl<T: Iterator<Item = MatchEntry>> PartialOrd for QueueElement<T> {
+impl<T: Iterator<Item = Result<MatchEntry, Error>>> PartialOrd for QueueElement<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl<T: Iterator<Item = MatchEntry>> PartialEq for QueueElement<T> {
+impl<T: Iterator<Item = Result<MatchEntry, Error>>> PartialEq for QueueElement<T> {
     fn eq(&self, other: &Self) -> bool {
         self.sort_key() == other.sort_key()
     }
 }
 
-impl<T: Iterator<Item = MatchEntry>> Eq for QueueElement<T> {}
+impl<T: Iterator<Item = Result<MatchEntry, Error>>> Eq for QueueElement<T> {}
 
 impl GridStore {
+    /// Opens a store using the options recorded in its metadata, or the defaults for stores built
+    /// without metadata.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        GridStore::new_with_options(path, 6, 0, 0.0, vec![[0, 0, 63, 63]], 0.0)
+        GridStore::open(path, &GridStoreOptionOverrides::default())
     }
 
     pub fn might_be_slow(&self) -> bool {
         return self.zoom >= 14;
     }
 
+    /// Opens a store with explicitly-provided options. If the store has metadata, the zoom has to
+    /// match the zoom it was built at.
     pub fn new_with_options<P: AsRef<Path>>(
         path: P,
         zoom: u16,
         type_id: u16,
         coalesce_radius: f64,
-        bboxes: Vec<[u16; 4]>,
+        bboxes: Vec<[u32; 4]>,
         max_score: f64,
     ) -> Result<Self, Error> {
         let path = path.as_ref().to_owned();
-        let mut opts = Options::default();
-        opts.set_read_only(true);
-        opts.set_allow_mmap_reads(true);
-        let db = DB::open(&opts, &path)?;
-
-        let bin_boundaries: HashSet<u32> = match db.get("~BOUNDS")? {
-            Some(entry) => {
-                let encoded_boundaries: &[u8] = entry.as_ref();
-                encoded_boundaries
-                    .chunks(4)
-                    .filter_map(|chunk| {
-                        if chunk.len() == 4 {
-                            Some(u32::from_le_bytes(chunk.try_into().unwrap()))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
+        let storage = open_storage(&path)?;
+        let options = GridStoreOptions { zoom, type_id, coalesce_radius, bboxes, max_score };
+        let mut store = GridStore::from_boxed_storage(storage, options)?;
+        store.path = path;
+        Ok(store)
+    }
+
+    /// Opens a store using the options recorded in its metadata (or the defaults, if it has
+    /// none), with any fields set in `overrides` taking precedence. Overriding the zoom of a store
+    /// that records one is an error, since the zoom is baked into its data.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        overrides: &GridStoreOptionOverrides,
+    ) -> Result<Self, Error> {
+        let path = path.as_ref().to_owned();
+        let storage = open_storage(&path)?;
+        let recorded_options = GridStoreMetadata::read_from(storage.as_ref())?
+            .map(|metadata| metadata.options)
+            .unwrap_or_default();
+        let mut store =
+            GridStore::from_boxed_storage(storage, overrides.apply_to(recorded_options))?;
+        store.path = path;
+        Ok(store)
+    }
+
+    /// Makes a GridStore that reads from an already-open storage backend rather than a RocksDB
+    /// directory on disk. The resulting store has an empty `path`.
+    pub fn from_storage<S: GridStorage + 'static>(
+        storage: S,
+        zoom: u16,
+        type_id: u16,
+        coalesce_radius: f64,
+        bboxes: Vec<[u32; 4]>,
+        max_score: f64,
+    ) -> Result<Self, Error> {
+        let options = GridStoreOptions { zoom, type_id, coalesce_radius, bboxes, max_score };
+        GridStore::from_boxed_storage(Box::new(storage), options)
+    }
+
+    fn from_boxed_storage(
+        storage: Box<dyn GridStorage>,
+        options: GridStoreOptions,
+    ) -> Result<Self, Error> {
+        // check the version first: nothing else in the store can be trusted to decode otherwise
+        let format_version = read_format_version(storage.as_ref())?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&format_version) {
+            return Err(StoreError::UnsupportedFormatVersion {
+                found: format_version,
+                min_supported: MIN_FORMAT_VERSION,
+                max_supported: FORMAT_VERSION,
             }
+            .into());
+        }
+        let format_features = read_format_features(storage.as_ref())?;
+        if options.zoom > format_features.max_zoom() {
+            return Err(StoreError::UnsupportedZoom {
+                zoom: options.zoom,
+                max_supported: format_features.max_zoom(),
+            }
+            .into());
+        }
+
+        let metadata = GridStoreMetadata::read_from(storage.as_ref())?;
+        if let Some(metadata) = &metadata {
+            if metadata.options.zoom != options.zoom {
+                return Err(StoreError::ZoomMismatch {
+                    built: metadata.options.zoom,
+                    requested: options.zoom,
+                }
+                .into());
+            }
+        }
+
+        let bin_boundaries: HashSet<u32> = match storage.get(b"~BOUNDS")? {
+            Some(entry) => decode_u32_list(entry.as_ref()).collect(),
+            None => HashSet::new(),
+        };
+        let mut sorted_bin_boundaries: Vec<u32> = bin_boundaries.iter().cloned().collect();
+        sorted_bin_boundaries.sort();
+        let tombstones: HashSet<u32> = match storage.get(TOMBSTONES_KEY)? {
+            Some(entry) => decode_u32_list(entry.as_ref()).collect(),
             None => HashSet::new(),
         };
 
         Ok(GridStore {
-            db,
-            path,
+            storage,
+            path: PathBuf::new(),
             bin_boundaries,
-            zoom,
-            type_id,
-            coalesce_radius,
-            bboxes,
-            max_score,
+            sorted_bin_boundaries,
+            zoom: options.zoom,
+            type_id: options.type_id,
+            coalesce_radius: options.coalesce_radius,
+            bboxes: options.bboxes,
+            max_score: options.max_score,
+            metadata,
+            format_version,
+            format_features,
+            tombstones,
+            overlays: Vec::new(),
         })
     }
 
+    /// Layers a delta store on top of this one. Lookups through the result see the entries of
+    /// both, except that entries in this store (and any overlays already added) for features the
+    /// delta tombstones are hidden. The query options of this store apply to the whole stack.
+    ///
+    /// `keys`, `iter`, `verify` and `stats` only look at this store, not its overlays.
+    pub fn with_overlay(mut self, mut overlay: GridStore) -> Result<Self, Error> {
+        if overlay.zoom != self.zoom {
+            return Err(
+                StoreError::OverlayZoomMismatch { base: self.zoom, overlay: overlay.zoom }.into()
+            );
+        }
+        let nested: Vec<GridStore> = overlay.overlays.drain(..).collect();
+        self.overlays.push(overlay);
+        self.overlays.extend(nested);
+        Ok(self)
+    }
+
+    /// This store and its overlays, bottom-most first.
+    pub(crate) fn layers(&self) -> Vec<&GridStore> {
+        std::iter::once(self).chain(self.overlays.iter()).collect()
+    }
+
+    /// Looks up the entries for a single phrase. A record that turns out to be malformed is an
+    /// error here if its structure is broken, or an error item partway through if one of its id
+    /// lists is.
     #[inline(never)]
-    pub fn get(&self, key: &GridKey) -> Result<Option<impl Iterator<Item = GridEntry>>, Error> {
+    pub fn get(
+        &self,
+        key: &GridKey,
+    ) -> Result<Option<impl Iterator<Item = Result<GridEntry, Error>> + '_>, Error> {
+        if self.overlays.is_empty() {
+            return Ok(self.layer_get(key)?.map(Either::Left));
+        }
+
+        let layers = self.layers();
+        let mut found = Vec::new();
+        for (i, layer) in layers.iter().enumerate() {
+            if let Some(entries) = layer.layer_get(key)? {
+                let hidden = tombstones_above(&layers[i..]);
+                found.push(entries.filter(move |entry| match entry {
+                    Ok(entry) => !hidden.iter().any(|tombstones| tombstones.contains(&entry.id)),
+                    Err(_) => true,
+                }));
+            }
+        }
+        if found.is_empty() {
+            return Ok(None);
+        }
+        let merged = found
+            .into_iter()
+            .kmerge_by(|a, b| merge_before(a, b, grid_entry_rank))
+            .coalesce(drop_repeat);
+        Ok(Some(Either::Right(merged)))
+    }
+
+    /// `get`, for this store alone.
+    fn layer_get(
+        &self,
+        key: &GridKey,
+    ) -> Result<Option<impl Iterator<Item = Result<GridEntry, Error>>>, Error> {
         let mut db_key: Vec<u8> = Vec::new();
         key.write_to(TypeMarker::SinglePhrase, &mut db_key)?;
 
-        Ok(match self.db.get(&db_key)? {
-            Some(value) => Some(decode_value(value)),
+        Ok(match self.storage.get(&db_key)? {
+            Some(value) => Some(decode_value(self.resolve(value)?, &self.format_features)?),
             None => None,
         })
     }
 
+    /// Swaps a shared value reference for the record it points to, in stores built with shared
+    /// values. References always point straight at a record, never at another reference.
+    pub(crate) fn resolve(&self, value: StorageValue) -> Result<StorageValue, Error> {
+        if !self.format_features.shared_values {
+            return Ok(value);
+        }
+        let target = match reference_target(value.as_ref()) {
+            Some(target) => target,
+            None => return Ok(value),
+        };
+        match self.storage.get(target)? {
+            Some(record) if reference_target(record.as_ref()).is_none() => Ok(record),
+            _ => Err(StoreError::BrokenReference { target: target.to_vec() }.into()),
+        }
+    }
+
+    /// Streams the best entries for every phrase matching `match_key`. With overlays, up to
+    /// `max_values` records are read from each layer before tombstoned features are dropped.
     pub fn streaming_get_matching(
         &self,
         match_key: &MatchKey,
         match_opts: &MatchOpts,
         max_values: usize,
-    ) -> Result<impl Iterator<Item = MatchEntry>, Error> {
-        let (fetch_start, fetch_end, fetch_type_marker) = match match_key.match_phrase {
-            MatchPhrase::Exact(id) => (id, id + 1, TypeMarker::SinglePhrase),
-            MatchPhrase::Range { start, end } => {
-                if self.bin_boundaries.contains(&start) && self.bin_boundaries.contains(&end) {
-                    (start, end, TypeMarker::PrefixBin)
-                } else {
-                    (start, end, TypeMarker::SinglePhrase)
+    ) -> Result<impl Iterator<Item = Result<MatchEntry, Error>> + '_, Error> {
+        if self.overlays.is_empty() {
+            return Ok(Either::Left(self.layer_get_matching(
+                match_key,
+                match_opts,
+                max_values,
+                self.coalesce_radius,
+            )?));
+        }
+
+        let layers = self.layers();
+        let mut streams = Vec::with_capacity(layers.len());
+        for (i, layer) in layers.iter().enumerate() {
+            let hidden = tombstones_above(&layers[i..]);
+            let stream = layer.layer_get_matching(
+                match_key,
+                match_opts,
+                max_values,
+                self.coalesce_radius,
+            )?;
+            streams.push(stream.filter(move |entry| match entry {
+                Ok(entry) => {
+                    !hidden.iter().any(|tombstones| tombstones.contains(&entry.grid_entry.id))
                 }
-            }
+                Err(_) => true,
+            }));
+        }
+        let merged = streams
+            .into_iter()
+            .kmerge_by(|a, b| merge_before(a, b, match_entry_sort_key))
+            .coalesce(drop_repeat);
+        Ok(Either::Right(merged))
+    }
+
+    /// `streaming_get_matching`, for this store alone. Overlays are searched with the coalesce
+    /// radius of the store they're layered onto.
+    fn layer_get_matching(
+        &self,
+        match_key: &MatchKey,
+        match_opts: &MatchOpts,
+        max_values: usize,
+        coalesce_radius: f64,
+    ) -> Result<impl Iterator<Item = Result<MatchEntry, Error>>, Error> {
+        let segments = match match_key.match_phrase {
+            MatchPhrase::Exact(id) => vec![(TypeMarker::SinglePhrase, id, id + 1)],
+            MatchPhrase::Range { start, end } => self.range_segments(start, end),
         };
 
         let match_opts = match_opts.clone();
 
-        let mut range_key = match_key.clone();
-        range_key.match_phrase = MatchPhrase::Range { start: fetch_start, end: fetch_end };
-        let mut db_key: Vec<u8> = Vec::new();
-        range_key.write_start_to(fetch_type_marker, &mut db_key)?;
-
-        let db_iter = self
-            .db
-            .iterator(IteratorMode::From(&db_key, Direction::Forward))
-            .take_while(|(k, _)| range_key.matches_key(fetch_type_marker, k).unwrap());
+        let mut db_iters = Vec::with_capacity(segments.len());
+        for (type_marker, start, end) in segments {
+            let mut range_key = match_key.clone();
+            range_key.match_phrase = MatchPhrase::Range { start, end };
+            let mut db_key: Vec<u8> = Vec::new();
+            range_key.write_start_to(type_marker, &mut db_key)?;
+
+            db_iters.push(self.storage.iter_from(&db_key).take_while(move |item| match item {
+                Ok((k, _)) => range_key.matches_key(type_marker, k).unwrap(),
+                Err(_) => true,
+            }));
+        }
+        let db_iter = db_iters.into_iter().flatten();
 
         let mut pri_queue = MinMaxHeap::<QueueElement<_>>::new();
 
-        for (key, value) in db_iter {
-            let matches_language = match_key.matches_language(&key).unwrap();
-            let mut entry_iter =
-                decode_matching_value(value, &match_opts, matches_language, self.coalesce_radius);
+        for item in db_iter {
+            let (key, value) = item?;
+            let matches_language = match_key.matches_language(&key)?;
+            let mut entry_iter = decode_matching_value(
+                self.resolve(value)?,
+                &match_opts,
+                matches_language,
+                coalesce_radius,
+                &self.format_features,
+            )?;
             if let Some(next_entry) = entry_iter.next() {
+                let next_entry = next_entry?;
                 let queue_element = QueueElement { next_entry, entry_iter };
                 if pri_queue.len() >= max_values {
                     let worst_entry = pri_queue.peek_min().unwrap();
@@ -367,12 +695,20 @@ impl GridStore {
 
         let iter = std::iter::from_fn(move || {
             if let Some(mut best_entry) = pri_queue.peek_max_mut() {
-                if let Some(mut next_entry) = best_entry.entry_iter.next() {
-                    std::mem::swap(&mut next_entry, &mut (best_entry.next_entry));
-                    Some(next_entry)
-                } else {
-                    let best_entry = best_entry.pop();
-                    Some(best_entry.next_entry)
+                match best_entry.entry_iter.next() {
+                    Some(Ok(mut next_entry)) => {
+                        std::mem::swap(&mut next_entry, &mut (best_entry.next_entry));
+                        Some(Ok(next_entry))
+                    }
+                    Some(Err(e)) => {
+                        // the rest of this record can't be trusted, so drop it
+                        best_entry.pop();
+                        Some(Err(e))
+                    }
+                    None => {
+                        let best_entry = best_entry.pop();
+                        Some(Ok(best_entry.next_entry))
+                    }
                 }
             } else {
                 None
@@ -381,47 +717,81 @@ impl GridStore {
         Ok(iter)
     }
 
-    pub fn keys<'i>(&'i self) -> impl Iterator<Item = Result<GridKey, Error>> + 'i {
-        let db_iter = self.db.iterator(IteratorMode::Start);
-        db_iter.take_while(|(key, _)| key[0] == 0).map(|(key, _)| {
-            let phrase_id = (&key[1..]).read_u32::<BigEndian>()?;
-
-            let key_lang_partial = &key[5..];
-            let lang_set: u128 = if key_lang_partial.len() == 0 {
-                // 0-length language array is the shorthand for "matches everything"
-                std::u128::MAX
-            } else {
-                let mut key_lang_full = [0u8; 16];
-                key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
+    /// Splits the phrase range `[start, end)` into the pieces to read for it: the run of prefix
+    /// bins lying wholly inside the range, as a range of `PrefixBin` keys, plus `SinglePhrase`
+    /// ranges for whatever's left over at either edge. Bin `b` holds the phrases from `b` up to
+    /// the next boundary, except that the first bin also holds everything below it and the last
+    /// one everything above it, so the last bin is never wholly inside a range.
+    pub(crate) fn range_segments(&self, start: u32, end: u32) -> Vec<(TypeMarker, u32, u32)> {
+        let bounds = &self.sorted_bin_boundaries;
+        // the first bin that starts at or after `start`
+        let mut first = match bounds.binary_search(&start) {
+            Ok(i) | Err(i) => i,
+        };
+        if first == 0 && start > 0 {
+            first = 1;
+        }
+        // one past the last bin that ends at or before `end`, which is the bin that starts there
+        let last_end = match bounds.binary_search(&end) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        if first >= last_end {
+            return vec![(TypeMarker::SinglePhrase, start, end)];
+        }
 
-                (&key_lang_full[..]).read_u128::<BigEndian>()?
-            };
+        let bins_start = if first == 0 { 0 } else { bounds[first] };
+        let bins_end = bounds[last_end];
+        let mut segments = Vec::with_capacity(3);
+        if start < bins_start {
+            segments.push((TypeMarker::SinglePhrase, start, bins_start));
+        }
+        segments.push((TypeMarker::PrefixBin, bounds[first], bounds[last_end - 1] + 1));
+        if bins_end < end {
+            segments.push((TypeMarker::SinglePhrase, bins_end, end));
+        }
+        segments
+    }
 
-            Ok(GridKey { phrase_id, lang_set })
-        })
+    pub fn keys<'i>(&'i self) -> impl Iterator<Item = Result<GridKey, Error>> + 'i {
+        let db_iter = self.storage.iter_from(&[]);
+        db_iter.take_while(is_single_phrase).map(|item| GridKey::read_from(&item?.0))
     }
 
     pub fn iter<'i>(
         &'i self,
     ) -> impl Iterator<Item = Result<(GridKey, Vec<GridEntry>), Error>> + 'i {
-        let db_iter = self.db.iterator(IteratorMode::Start);
-        db_iter.take_while(|(key, _)| key[0] == 0).map(|(key, value)| {
-            let phrase_id = (&key[1..]).read_u32::<BigEndian>()?;
-
-            let key_lang_partial = &key[5..];
-            let lang_set: u128 = if key_lang_partial.len() == 0 {
-                // 0-length language array is the shorthand for "matches everything"
-                std::u128::MAX
-            } else {
-                let mut key_lang_full = [0u8; 16];
-                key_lang_full[(16 - key_lang_partial.len())..].copy_from_slice(key_lang_partial);
-
-                (&key_lang_full[..]).read_u128::<BigEndian>()?
-            };
-
-            let entries: Vec<_> = decode_value(value).collect();
+        let features = self.format_features;
+        let db_iter = self.storage.iter_from(&[]);
+        db_iter.take_while(is_single_phrase).map(move |item| {
+            let (key, value) = item?;
+            let grid_key = GridKey::read_from(&key)?;
+            let entries =
+                decode_value(self.resolve(value)?, &features)?.collect::<Result<Vec<_>, _>>()?;
+            Ok((grid_key, entries))
+        })
+    }
 
-            Ok((GridKey { phrase_id, lang_set }, entries))
+    /// Like `iter`, but decodes each record back into the form the builder keeps it in, with the
+    /// relev/scores, morton coords and id/hash pairs just as they were stored.
+    pub(crate) fn builder_entries<'i>(
+        &'i self,
+    ) -> impl Iterator<Item = Result<(GridKey, BuilderEntry), Error>> + 'i {
+        let features = self.format_features;
+        let db_iter = self.storage.iter_from(&[]);
+        db_iter.take_while(is_single_phrase).map(move |item| {
+            let (key, value) = item?;
+            let entry = decode_builder_entry(self.resolve(value)?, &features)?;
+            Ok((GridKey::read_from(&key)?, entry))
         })
     }
 }
+
+/// Whether an item from a storage iterator is still among the single phrase records, which come
+/// first. Errors are let through so that whoever's iterating gets to see them.
+fn is_single_phrase(item: &Result<(Box<[u8]>, StorageValue), Error>) -> bool {
+    match item {
+        Ok((key, _)) => key[0] == TypeMarker::SinglePhrase as u8,
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,153 @@
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use failure::{format_err, Error};
+use serde::Serialize;
+
+use crate::gridstore::common::*;
+use crate::gridstore::spatial::interleave_morton;
+use crate::gridstore::store::{GridStore, StoreError};
+
+/// Present in every store built with a tile index, even one without any tiles in it.
+pub const TILE_INDEX_KEY: &[u8] = b"~TILE_INDEX";
+
+/// Secondary index from morton-coded tiles to the ids of the features covering them, built up
+/// alongside the phrase records
+pub type TileIndex = BTreeMap<u64, Vec<u32>>;
+
+/// Tiles are keyed big-endian so they sort in morton order: in four bytes, or in eight in stores
+/// with wide coords, whose morton codes can take more than 32 bits.
+pub fn tile_index_key(tile: u64, wide_coords: bool, db_key: &mut Vec<u8>) {
+    db_key.push(TypeMarker::TileIndex as u8);
+    if wide_coords {
+        db_key.extend_from_slice(&tile.to_be_bytes());
+    } else {
+        db_key.extend_from_slice(&(tile as u32).to_be_bytes());
+    }
+}
+
+/// Reads the morton-coded tile back out of a tile index key of either width.
+pub fn read_tile_index_key(db_key: &[u8]) -> Result<u64, Error> {
+    match db_key.get(1..) {
+        Some(tile) if tile.len() == 4 => {
+            Ok(u64::from(u32::from_be_bytes(tile.try_into().unwrap())))
+        }
+        Some(tile) if tile.len() == 8 => Ok(u64::from_be_bytes(tile.try_into().unwrap())),
+        _ => Err(format_err!("malformed tile index key of length {}", db_key.len())),
+    }
+}
+
+pub fn encode_id_list(ids: &[u32]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(ids.len() * 4);
+    for id in ids {
+        encoded.extend_from_slice(&id.to_le_bytes());
+    }
+    encoded
+}
+
+pub fn decode_id_list(encoded: &[u8]) -> Result<Vec<u32>, Error> {
+    let ids = encoded.chunks_exact(4);
+    if !ids.remainder().is_empty() {
+        return Err(format_err!("tile index record of length {} is malformed", encoded.len()));
+    }
+    Ok(ids.map(|id| u32::from_le_bytes(id.try_into().unwrap())).collect())
+}
+
+/// Adds the features at one tile to the index; each tile's list is sorted and deduped when it's
+/// written.
+pub fn index_tile<I: IntoIterator<Item = u64>>(index: &mut TileIndex, tile: u64, id_comps: I) {
+    let ids = id_comps.into_iter().map(|id_comp| (id_comp >> 8) as u32);
+    index.entry(tile).or_default().extend(ids);
+}
+
+/// The features from one store covering a tile, as returned by `features_covering`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CoveringFeatures {
+    /// The store's position in the list passed to `features_covering`
+    pub idx: usize,
+    pub type_id: u16,
+    pub ids: Vec<u32>,
+}
+
+/// Finds the features covering a tile at `zoom` in each of several stores, which can be at any
+/// zoom: lower-zoom stores are searched at the parent tile, and higher-zoom stores across all of
+/// its children. Stores with no covering features are left out, and the rest are ordered by
+/// `type_id`.
+pub fn features_covering<T: Borrow<GridStore>>(
+    stores: &[T],
+    zoom: u16,
+    x: u32,
+    y: u32,
+) -> Result<Vec<CoveringFeatures>, Error> {
+    let mut out = Vec::new();
+    for (idx, store) in stores.iter().enumerate() {
+        let store = store.borrow();
+        let ids = store.features_in_tile(zoom, x, y)?;
+        if !ids.is_empty() {
+            out.push(CoveringFeatures { idx, type_id: store.type_id, ids });
+        }
+    }
+    out.sort_by_key(|covering| covering.type_id);
+    Ok(out)
+}
+
+impl GridStore {
+    /// Lists the ids of the features covering a tile at the store's zoom. This needs the store to
+    /// have been built with `GridStoreBuilder::set_tile_index`; it's an error to ask a store (or
+    /// any of its overlays) without one.
+    pub fn features_at(&self, x: u32, y: u32) -> Result<Vec<u32>, Error> {
+        self.features_in_tile(self.zoom, x, y)
+    }
+
+    /// Like `features_at`, but for a tile at any zoom.
+    fn features_in_tile(&self, zoom: u16, x: u32, y: u32) -> Result<Vec<u32>, Error> {
+        // the tiles under a square at a higher zoom are one contiguous run of morton codes
+        let levels = zoom.max(self.zoom) - zoom.min(self.zoom);
+        if levels >= 32 {
+            return Err(format_err!("can't search zoom {} from zoom {}", self.zoom, zoom));
+        }
+        let (start, count) = if zoom >= self.zoom {
+            (interleave_morton(x >> levels, y >> levels), 1u64)
+        } else {
+            (interleave_morton(x << levels, y << levels), 1u64 << (2 * levels))
+        };
+
+        let layers = self.layers();
+        let mut ids = Vec::new();
+        for (i, layer) in layers.iter().enumerate() {
+            let layer_ids = layer.layer_features_in_range(start, count)?;
+            let hidden: Vec<_> = layers[i + 1..].iter().map(|above| &above.tombstones).collect();
+            ids.extend(
+                layer_ids.into_iter().filter(|id| !hidden.iter().any(|dead| dead.contains(id))),
+            );
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// The features in this store alone covering `count` tiles, starting from the morton-coded
+    /// tile `start`.
+    fn layer_features_in_range(&self, start: u64, count: u64) -> Result<Vec<u32>, Error> {
+        if self.storage.get(TILE_INDEX_KEY)?.is_none() {
+            return Err(StoreError::NoTileIndex.into());
+        }
+        let end = start + count;
+
+        let mut db_key = Vec::with_capacity(9);
+        tile_index_key(start, self.format_features.wide_coords, &mut db_key);
+        let mut ids = Vec::new();
+        for item in self.storage.iter_from(&db_key) {
+            let (key, value) = item?;
+            if TypeMarker::from_key(&key) != Some(TypeMarker::TileIndex) {
+                break;
+            }
+            if read_tile_index_key(&key)? >= end {
+                break;
+            }
+            ids.extend(decode_id_list(value.as_ref())?);
+        }
+        Ok(ids)
+    }
+}
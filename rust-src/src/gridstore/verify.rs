@@ -0,0 +1,354 @@
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryInto;
+
+use failure::{Error, Fail};
+
+use crate::gridstore::common::*;
+use crate::gridstore::feature_index::{decode_phrase_list, read_feature_index_key};
+use crate::gridstore::gridstore_format::{self, DecodeError};
+use crate::gridstore::metadata::FormatFeatures;
+use crate::gridstore::shared_values::reference_target;
+use crate::gridstore::store::GridStore;
+use crate::gridstore::tile_index::{decode_id_list, read_tile_index_key};
+
+/// Something wrong with a store that its readers would otherwise silently misbehave on.
+#[derive(Debug, Fail, Clone, PartialEq)]
+pub enum VerifyIssue {
+    #[fail(display = "malformed key {:?}", key)]
+    MalformedKey { key: Vec<u8> },
+    #[fail(display = "{:?} record {:?} can't be decoded: {}", marker, key, error)]
+    Undecodable { marker: TypeMarker, key: GridKey, error: DecodeError },
+    #[fail(
+        display = "{:?} record {:?} has relev/score {} that isn't valid in the store's encoding",
+        marker, key, relev_score
+    )]
+    InvalidRelev { marker: TypeMarker, key: GridKey, relev_score: u32 },
+    #[fail(display = "{:?} record {:?} has relev/scores out of order", marker, key)]
+    RelevScoresOutOfOrder { marker: TypeMarker, key: GridKey },
+    #[fail(
+        display = "{:?} record {:?} has coords out of descending morton order under relev/score {}",
+        marker, key, relev_score
+    )]
+    CoordsOutOfOrder { marker: TypeMarker, key: GridKey, relev_score: u32 },
+    #[fail(
+        display = "{:?} record {:?} has unsorted or duplicate ids at coord {} under relev/score {}",
+        marker, key, coord, relev_score
+    )]
+    IdsOutOfOrder { marker: TypeMarker, key: GridKey, relev_score: u32, coord: u64 },
+    #[fail(display = "~BOUNDS record is malformed")]
+    MalformedBounds,
+    #[fail(display = "~BOUNDS record isn't in ascending order")]
+    UnsortedBounds,
+    #[fail(display = "prefix bin {:?} doesn't match a boundary with member phrases", key)]
+    UnexpectedPrefixBin { key: GridKey },
+    #[fail(display = "prefix bin {:?} is missing", key)]
+    MissingPrefixBin { key: GridKey },
+    #[fail(display = "prefix bin {:?} isn't the union of its member phrases", key)]
+    PrefixBinMismatch { key: GridKey },
+    #[fail(display = "feature index record {:?} is malformed", key)]
+    MalformedFeatureIndex { key: Vec<u8> },
+    #[fail(display = "feature index lists phrase {:?} for feature {}, but it's missing", key, id)]
+    DanglingFeatureIndex { id: u32, key: GridKey },
+    #[fail(display = "tile index record {:?} is malformed", key)]
+    MalformedTileIndex { key: Vec<u8> },
+    #[fail(display = "tile index record for tile {} has unsorted or duplicate ids", tile)]
+    TileIndexOutOfOrder { tile: u64 },
+    #[fail(
+        display = "{:?} record {:?} points to a record that's missing or is itself a reference",
+        marker, key
+    )]
+    BrokenReference { marker: TypeMarker, key: GridKey },
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub phrase_records: usize,
+    pub prefix_bin_records: usize,
+    pub feature_index_records: usize,
+    pub tile_index_records: usize,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Every (relev/score, morton coord, id) triple in a record, for comparing prefix bins against
+/// their members
+type RecordContents = BTreeSet<(u32, u64, u64)>;
+
+/// Checks the ordering invariants of a single record, adding its contents to `contents` if given.
+/// Only decoding failures are returned as errors; everything else is added to `issues`.
+fn check_record(
+    value: &[u8],
+    marker: TypeMarker,
+    key: &GridKey,
+    features: &FormatFeatures,
+    issues: &mut Vec<VerifyIssue>,
+    mut contents: Option<&mut RecordContents>,
+) -> Result<(), DecodeError> {
+    let reader = gridstore_format::Reader::new(value);
+    let record = gridstore_format::read_phrase_record_from(&reader)?;
+
+    let mut prev_relev_score = None;
+    for rs in reader.read_var_vec(record.relev_scores)?.iter() {
+        // in the original encoding, relev codes are the high four bits of a byte, and only 0
+        // through 3 are meaningful; high-precision values are flagged and take 17 bits
+        let valid = if features.high_precision {
+            rs.relev_score & HIGH_PRECISION_FLAG != 0 && rs.relev_score >> 17 == 0
+        } else {
+            rs.relev_score >> 4 <= 3
+        };
+        if !valid {
+            issues.push(VerifyIssue::InvalidRelev {
+                marker,
+                key: key.clone(),
+                relev_score: rs.relev_score,
+            });
+        }
+        if prev_relev_score.filter(|prev| *prev <= rs.relev_score).is_some() {
+            issues.push(VerifyIssue::RelevScoresOutOfOrder { marker, key: key.clone() });
+        }
+        prev_relev_score = Some(rs.relev_score);
+
+        let mut prev_coord = None;
+        for coord in reader.read_coord_vec(rs.coords, features)?.iter() {
+            if prev_coord.filter(|prev| *prev <= coord.coord).is_some() {
+                issues.push(VerifyIssue::CoordsOutOfOrder {
+                    marker,
+                    key: key.clone(),
+                    relev_score: rs.relev_score,
+                });
+            }
+            prev_coord = Some(coord.coord);
+
+            let mut prev_id = None;
+            for id in reader.read_id_list(coord.ids, features)?.iter() {
+                if prev_id.filter(|prev| *prev <= id).is_some() {
+                    issues.push(VerifyIssue::IdsOutOfOrder {
+                        marker,
+                        key: key.clone(),
+                        relev_score: rs.relev_score,
+                        coord: coord.coord,
+                    });
+                }
+                prev_id = Some(id);
+                if let Some(contents) = contents.as_mut() {
+                    contents.insert((rs.relev_score, coord.coord, id));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_contents(value: &[u8], features: &FormatFeatures) -> Result<RecordContents, DecodeError> {
+    let mut contents = RecordContents::new();
+    check_record(
+        value,
+        TypeMarker::PrefixBin,
+        &GridKey { phrase_id: 0, lang_set: 0 },
+        features,
+        &mut Vec::new(),
+        Some(&mut contents),
+    )?;
+    Ok(contents)
+}
+
+fn check_tile_index(db_key: &[u8], value: &[u8], issues: &mut Vec<VerifyIssue>) {
+    match (read_tile_index_key(db_key), decode_id_list(value)) {
+        (Ok(tile), Ok(ids)) => {
+            if ids.windows(2).any(|pair| pair[0] >= pair[1]) {
+                issues.push(VerifyIssue::TileIndexOutOfOrder { tile });
+            }
+        }
+        _ => issues.push(VerifyIssue::MalformedTileIndex { key: db_key.to_vec() }),
+    }
+}
+
+impl GridStore {
+    /// Walks every record in the store, checking the invariants that decoding and the spatial
+    /// searches rely on, and that every prefix bin holds exactly the union of its member phrases.
+    /// Problems with the data are collected into the report; an error is only returned if the
+    /// underlying storage can't be read.
+    pub fn verify(&self) -> Result<VerifyReport, Error> {
+        let mut report = VerifyReport::default();
+
+        let mut boundaries: Vec<u32> = Vec::new();
+        if let Some(encoded) = self.storage.get(b"~BOUNDS")? {
+            let encoded = encoded.as_ref();
+            if encoded.len() % 4 != 0 {
+                report.issues.push(VerifyIssue::MalformedBounds);
+            }
+            boundaries = encoded
+                .chunks_exact(4)
+                .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect();
+            if boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+                report.issues.push(VerifyIssue::UnsortedBounds);
+                boundaries.sort();
+                boundaries.dedup();
+            }
+        }
+        // phrases below the first boundary are binned along with it, as `finish` bins them
+        let bin_for = |phrase_id: u32| match boundaries.binary_search(&phrase_id) {
+            Ok(idx) => Some(boundaries[idx]),
+            Err(0) => boundaries.first().cloned(),
+            Err(idx) => Some(boundaries[idx - 1]),
+        };
+
+        // Phrase keys sort by phrase id, so the members of each bin come out together; we union
+        // them up as we go and check the bin record as soon as we've seen all of its members.
+        let mut expected_bins: BTreeSet<GridKey> = BTreeSet::new();
+        let mut current_bin: Option<u32> = None;
+        let mut bin_contents: HashMap<u128, RecordContents> = HashMap::new();
+
+        for item in self.storage.iter_from(&[]) {
+            let (db_key, value) = item?;
+            let marker = match TypeMarker::from_key(&db_key) {
+                Some(marker) => marker,
+                // the special `~` records, which are checked separately
+                None => continue,
+            };
+            // the secondary indexes aren't keyed by phrase, so none of the checks below apply
+            match marker {
+                TypeMarker::FeatureIndex => {
+                    report.feature_index_records += 1;
+                    self.check_feature_index(&db_key, value.as_ref(), &mut report.issues)?;
+                    continue;
+                }
+                TypeMarker::TileIndex => {
+                    report.tile_index_records += 1;
+                    check_tile_index(&db_key, value.as_ref(), &mut report.issues);
+                    continue;
+                }
+                TypeMarker::SinglePhrase | TypeMarker::PrefixBin => {}
+            }
+            let key = match GridKey::read_from(&db_key) {
+                Ok(key) => key,
+                Err(_) => {
+                    report.issues.push(VerifyIssue::MalformedKey { key: db_key.to_vec() });
+                    continue;
+                }
+            };
+
+            let contents = match marker {
+                TypeMarker::SinglePhrase => {
+                    report.phrase_records += 1;
+                    let bin = bin_for(key.phrase_id);
+                    if bin != current_bin {
+                        self.check_bin(
+                            current_bin,
+                            &mut bin_contents,
+                            &mut expected_bins,
+                            &mut report.issues,
+                        )?;
+                        current_bin = bin;
+                    }
+                    // without any boundaries, phrases don't belong to any bin
+                    bin.map(|_| bin_contents.entry(key.lang_set).or_default())
+                }
+                TypeMarker::PrefixBin => {
+                    self.check_bin(
+                        current_bin.take(),
+                        &mut bin_contents,
+                        &mut expected_bins,
+                        &mut report.issues,
+                    )?;
+                    report.prefix_bin_records += 1;
+                    if !expected_bins.contains(&key) {
+                        report.issues.push(VerifyIssue::UnexpectedPrefixBin { key: key.clone() });
+                    }
+                    None
+                }
+                TypeMarker::FeatureIndex | TypeMarker::TileIndex => unreachable!("handled above"),
+            };
+
+            // a shared record is checked again for each phrase pointing to it, since they each go
+            // into their own bins
+            let value = match reference_target(value.as_ref()) {
+                Some(target) if self.format_features.shared_values => {
+                    match self.storage.get(target)? {
+                        Some(record) if reference_target(record.as_ref()).is_none() => record,
+                        _ => {
+                            report.issues.push(VerifyIssue::BrokenReference { marker, key });
+                            continue;
+                        }
+                    }
+                }
+                _ => value,
+            };
+            if let Err(error) = check_record(
+                value.as_ref(),
+                marker,
+                &key,
+                &self.format_features,
+                &mut report.issues,
+                contents,
+            ) {
+                report.issues.push(VerifyIssue::Undecodable { marker, key, error });
+            }
+        }
+        self.check_bin(current_bin, &mut bin_contents, &mut expected_bins, &mut report.issues)?;
+
+        Ok(report)
+    }
+
+    /// Checks that a feature index record decodes, and that the phrases it lists all exist.
+    fn check_feature_index(
+        &self,
+        db_key: &[u8],
+        value: &[u8],
+        issues: &mut Vec<VerifyIssue>,
+    ) -> Result<(), Error> {
+        let (id, keys) = match (read_feature_index_key(db_key), decode_phrase_list(value)) {
+            (Ok(id), Ok(keys)) => (id, keys),
+            _ => {
+                issues.push(VerifyIssue::MalformedFeatureIndex { key: db_key.to_vec() });
+                return Ok(());
+            }
+        };
+        let mut phrase_key = Vec::new();
+        for key in keys {
+            phrase_key.clear();
+            key.write_to(TypeMarker::SinglePhrase, &mut phrase_key)?;
+            if self.storage.get(&phrase_key)?.is_none() {
+                issues.push(VerifyIssue::DanglingFeatureIndex { id, key });
+            }
+        }
+        Ok(())
+    }
+
+    /// Compares the union of a bin's member phrases, per language set, against its bin records.
+    fn check_bin(
+        &self,
+        bin: Option<u32>,
+        bin_contents: &mut HashMap<u128, RecordContents>,
+        expected_bins: &mut BTreeSet<GridKey>,
+        issues: &mut Vec<VerifyIssue>,
+    ) -> Result<(), Error> {
+        let bin = match bin {
+            Some(bin) => bin,
+            None => return Ok(()),
+        };
+        for (lang_set, contents) in bin_contents.drain() {
+            let key = GridKey { phrase_id: bin, lang_set };
+            let mut db_key = Vec::new();
+            key.write_to(TypeMarker::PrefixBin, &mut db_key)?;
+            match self.storage.get(&db_key)? {
+                // undecodable bins get reported when the scan reaches them
+                Some(value) => match decode_contents(value.as_ref(), &self.format_features) {
+                    Ok(bin_record) if bin_record != contents => {
+                        issues.push(VerifyIssue::PrefixBinMismatch { key: key.clone() })
+                    }
+                    _ => {}
+                },
+                None => issues.push(VerifyIssue::MissingPrefixBin { key: key.clone() }),
+            }
+            expected_bins.insert(key);
+        }
+        Ok(())
+    }
+}
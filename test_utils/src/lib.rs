@@ -208,7 +208,7 @@ pub struct GridStorePlaceholder {
     zoom: u16,
     type_id: u16,
     coalesce_radius: f64,
-    bboxes: Vec<[u16; 4]>,
+    bboxes: Vec<[u32; 4]>,
     max_score: f64,
 }
 
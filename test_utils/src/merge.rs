@@ -0,0 +1,14 @@
+use carmen_core::gridstore::{GridStore, GridStoreBuilder};
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        panic!("Expected at least 2 arguments: an output path and one or more gridstores")
+    }
+    let stores: Vec<GridStore> =
+        args[2..].iter().map(|path| GridStore::new(path).unwrap()).collect();
+    let mut builder = GridStoreBuilder::new(&args[1]).unwrap();
+    builder.merge_stores(&stores).unwrap();
+    builder.finish().unwrap();
+}
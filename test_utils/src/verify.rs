@@ -0,0 +1,26 @@
+use carmen_core::gridstore::GridStore;
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        panic!("Expected 1 argument: a gridstore")
+    }
+    let reader = GridStore::new(&args[1]).unwrap();
+    let report = reader.verify().unwrap();
+    for issue in report.issues.iter() {
+        println!("{}", issue);
+    }
+    println!(
+        "checked {} phrase records, {} prefix bin records, {} feature index records and {} tile \
+         index records: {} issues",
+        report.phrase_records,
+        report.prefix_bin_records,
+        report.feature_index_records,
+        report.tile_index_records,
+        report.issues.len()
+    );
+    if !report.is_ok() {
+        std::process::exit(1);
+    }
+}
@@ -22,7 +22,8 @@ tape('GridStoreBuilder insert()', (t) => {
     const builder = new addon.GridStoreBuilder(tmpDir.name);
     t.throws(() => builder.insert(), 'not enough arguments');
     t.throws(() => builder.insert({}), 'not enough arguments');
-    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]);
+    t.throws(() => builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]), 'throws on an unsupported relev');
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
     builder.insert({ phrase_id: 1, lang_set: [0, 1, 2, 3] }, [{ id: 2, x: 2, y: 2, relev: 0.6, score: 3, source_phrase_hash: 0 }]);
     builder.finish();
 
@@ -38,7 +39,7 @@ tape('GridStoreBuilder append()', (t) => {
     const builder = new addon.GridStoreBuilder(tmpDir.name);
     t.throws(() => builder.append(), 'not enough arguments');
     t.throws(() => builder.append({}), 'not enough arguments');
-    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
     builder.append({ phrase_id: 1, lang_set: [0, 2] }, [{ id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 }]);
     builder.finish();
 
@@ -61,10 +62,48 @@ tape('GridStoreBuilder compactAppend()', (t) => {
     t.end();
 });
 
+tape('GridStoreBuilder setLossy()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    t.throws(() => builder.compactAppend({ phrase_id: 0, lang_set: [0] }, 1, 1, Math.pow(2, 24), 0, [[1, 1]]), 'throws on an id over 24 bits');
+    builder.setLossy(true);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.55, score: 2, source_phrase_hash: 0 }]);
+    builder.compactAppend({ phrase_id: 0, lang_set: [0] }, 1, 1, Math.pow(2, 24) + 1, 0, [[1, 1]]);
+    t.deepEquals(builder.lossyReport(), { relevs_rounded: 1, scores_clamped: 0, ids_truncated: 1, coords_out_of_range: 0 }, 'counts what was changed');
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [
+        { relev: 1, score: 1, x: 1, y: 1, id: 1, source_phrase_hash: 0 },
+        { relev: 0.6, score: 2, x: 0, y: 0, id: 0, source_phrase_hash: 0 }
+    ], 'stores the nearest values it can');
+    t.end();
+});
+
+tape('GridStoreBuilder renumberFeatures()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 3 }]);
+    builder.insert({ phrase_id: 1, lang_set: [0] }, [{ id: 1, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    t.throws(() => builder.renumberFeatures(new Uint32Array([7]).buffer), 'throws on an id missing from the mapping');
+    t.throws(() => builder.renumberFeatures(new Uint32Array([7, 7]).buffer), 'throws on two ids mapped to the same id');
+    builder.renumberFeatures(new Uint32Array([7, 5]).buffer);
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [
+        { relev: 1, score: 2, x: 0, y: 0, id: 7, source_phrase_hash: 3 }
+    ], 'keeps the source phrase hash');
+    t.deepEquals(reader.get({ phrase_id: 1, lang_set: [0] }), [
+        { relev: 1, score: 2, x: 1, y: 1, id: 5, source_phrase_hash: 0 }
+    ], 'renumbers every record');
+    t.end();
+});
+
 tape('GridStoreBuilder finish()', (t) => {
     const tmpDir = tmp.dirSync();
     const builder = new addon.GridStoreBuilder(tmpDir.name);
-    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
     builder.insert({ phrase_id: 1, lang_set: [0, 1, 2, 3] }, [{ id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 }]);
     t.throws(() => new addon.GridStore(tmpDir.name), 'throws if you attempt to read without calling finish()');
 
@@ -74,10 +113,222 @@ tape('GridStoreBuilder finish()', (t) => {
     t.end();
 });
 
+tape('GridStoreBuilder finish() progress and report', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 1, lang_set: [0] }, [{ id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 }]);
+    builder.loadBinBoundaries(new Uint32Array([0]).buffer);
+
+    const events = [];
+    const report = builder.finish((event) => events.push(event));
+    t.deepEquals(events[events.length - 1], { phase: 'compacting', records: 3 }, 'ends with compaction');
+    t.ok(events.some((event) => event.phase === 'encoding'), 'reports encoding');
+    t.equals(report.keys, 2, 'counts phrase records');
+    t.equals(report.prefix_bins, 1, 'counts prefix bins');
+    t.ok(report.bytes_written > 0, 'counts bytes written');
+    t.equals(report.largest_records.length, 3, 'lists the largest records');
+    t.equals(report.largest_records[0].marker, 'PrefixBin');
+    t.deepEquals(report.largest_records[0].key, { phrase_id: 0, lang_set: [0] }, 'the bin is largest');
+    t.end();
+});
+
+tape('GridStoreBuilder setMemoryBudget()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const spillDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    t.throws(() => builder.setMemoryBudget(), 'not enough arguments');
+    builder.setMemoryBudget(1, spillDir.name);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.append({ phrase_id: 0, lang_set: [0] }, [{ id: 1, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 1, lang_set: [0] }, [{ id: 2, x: 2, y: 2, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [
+        { relev: 1, score: 2, x: 1, y: 1, id: 1, source_phrase_hash: 0 },
+        { relev: 1, score: 2, x: 0, y: 0, id: 0, source_phrase_hash: 0 }
+    ], 'appends are merged across spilled runs');
+    t.deepEquals(reader.get({ phrase_id: 1, lang_set: [0] }), [{ relev: 1, score: 2, x: 2, y: 2, id: 2, source_phrase_hash: 0 }]);
+    t.end();
+});
+
+tape('GridStoreBuilder append mode', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.finish();
+
+    t.throws(() => new addon.GridStoreBuilder(tmpDir.name, { append: 'yes' }), 'throws on wrong option type');
+    const appending = new addon.GridStoreBuilder(tmpDir.name, { append: true });
+    appending.append({ phrase_id: 0, lang_set: [0] }, [{ id: 1, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    appending.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [
+        { relev: 1, score: 2, x: 1, y: 1, id: 1, source_phrase_hash: 0 },
+        { relev: 1, score: 2, x: 0, y: 0, id: 0, source_phrase_hash: 0 }
+    ], 'appends are merged with the records already in the store');
+    t.end();
+});
+
+tape('GridStoreBuilder setAutoBins()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    t.throws(() => builder.setAutoBins(), 'not enough arguments');
+    builder.setAutoBins(1);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 1, lang_set: [0] }, [{ id: 1, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 1, lang_set: [0] }), [{ relev: 1, score: 2, x: 1, y: 1, id: 1, source_phrase_hash: 0 }], 'phrases read back as usual');
+    t.end();
+});
+
+tape('GridStoreBuilder setFormatFeatures()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    t.throws(() => builder.setFormatFeatures(), 'not enough arguments');
+    builder.setFormatFeatures({ high_precision: true });
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.6, score: 200, source_phrase_hash: 0 }]);
+    t.throws(() => builder.setFormatFeatures({ high_precision: false }), 'throws once data has been added');
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [{ relev: 0.6, score: 200, x: 0, y: 0, id: 0, source_phrase_hash: 0 }], 'relev and score keep their precision');
+
+    const wideDir = tmp.dirSync();
+    const narrow = new addon.GridStoreBuilder(wideDir.name);
+    t.throws(() => narrow.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 2 ** 31, x: 0, y: 0, relev: 1, score: 1, source_phrase_hash: 0 }]), 'ids need more than 24 bits');
+    const wide = new addon.GridStoreBuilder(wideDir.name);
+    wide.setFormatFeatures({ wide_ids: true });
+    wide.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 2 ** 31, x: 0, y: 0, relev: 1, score: 1, source_phrase_hash: 3 }]);
+    wide.finish();
+    const wideReader = new addon.GridStore(wideDir.name);
+    t.deepEquals(wideReader.get({ phrase_id: 0, lang_set: [0] }), [{ relev: 1, score: 1, x: 0, y: 0, id: 2 ** 31, source_phrase_hash: 3 }], 'wide ids read back whole');
+    const z18Dir = tmp.dirSync();
+    const z18 = new addon.GridStoreBuilder(z18Dir.name);
+    t.throws(() => z18.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 1, x: 200000, y: 150000, relev: 1, score: 1, source_phrase_hash: 0 }]), 'coords need more than 16 bits');
+    z18.setFormatFeatures({ wide_coords: true });
+    z18.setMetadata({ zoom: 18, type_id: 1, coalesce_radius: 200, bboxes: [[0, 0, 262143, 262143]], max_score: 1 }, 'test data');
+    z18.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 1, x: 200000, y: 150000, relev: 1, score: 1, source_phrase_hash: 0 }]);
+    z18.finish();
+    const z18Reader = new addon.GridStore(z18Dir.name);
+    t.deepEquals(z18Reader.get({ phrase_id: 0, lang_set: [0] }), [{ relev: 1, score: 1, x: 200000, y: 150000, id: 1, source_phrase_hash: 0 }], 'zoom 18 coords read back whole');
+    const packedDir = tmp.dirSync();
+    const packed = new addon.GridStoreBuilder(packedDir.name);
+    packed.setFormatFeatures({ packed_vectors: true });
+    const packedEntries = [];
+    for (let x = 0; x < 20; x++) packedEntries.push({ id: 20 - x, x: 19 - x, y: 1, relev: 1, score: 1, source_phrase_hash: 0 });
+    packed.insert({ phrase_id: 0, lang_set: [0] }, packedEntries);
+    packed.finish();
+    const packedReader = new addon.GridStore(packedDir.name);
+    t.deepEquals(packedReader.get({ phrase_id: 0, lang_set: [0] }), packedEntries, 'packed coords and ids read back in order');
+    const sharedDir = tmp.dirSync();
+    const shared = new addon.GridStoreBuilder(sharedDir.name);
+    shared.setFormatFeatures({ shared_values: true });
+    shared.insert({ phrase_id: 0, lang_set: [0] }, packedEntries);
+    shared.insert({ phrase_id: 1, lang_set: [0] }, packedEntries);
+    t.equals(shared.finish().shared_values, 1, 'the synonym is stored as a reference');
+    const sharedReader = new addon.GridStore(sharedDir.name);
+    t.deepEquals(sharedReader.get({ phrase_id: 1, lang_set: [0] }), packedEntries, 'shared records read back through the reference');
+    t.end();
+});
+
+tape('GridStoreBuilder setMetadata()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    t.throws(() => builder.setMetadata(), 'not enough arguments');
+    t.throws(() => builder.setMetadata({ zoom: 14 }), 'throws on incomplete options');
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.setMetadata({ zoom: 14, type_id: 1, coalesce_radius: 200, bboxes: [[0, 0, 16383, 16383]], max_score: 1 }, 'test data');
+    builder.finish();
+
+    t.ok(new addon.GridStore(tmpDir.name), 'opens with the recorded options');
+    t.ok(new addon.GridStore(tmpDir.name, { zoom: 14, type_id: 1, coalesce_radius: 50, bboxes: [[0, 0, 16383, 16383]], max_score: 1 }), 'opens with matching explicit options');
+    t.throws(() => new addon.GridStore(tmpDir.name, { zoom: 6, type_id: 1, coalesce_radius: 200, bboxes: [[0, 0, 63, 63]], max_score: 1 }), 'throws if the zoom does not match the recorded zoom');
+    t.end();
+});
+
+tape('GridStore overlays', (t) => {
+    const baseDir = tmp.dirSync();
+    const base = new addon.GridStoreBuilder(baseDir.name);
+    base.insert({ phrase_id: 0, lang_set: [0] }, [
+        { id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 },
+        { id: 1, x: 1, y: 1, relev: 1, score: 1, source_phrase_hash: 0 }
+    ]);
+    base.finish();
+
+    const deltaDir = tmp.dirSync();
+    const delta = new addon.GridStoreBuilder(deltaDir.name);
+    delta.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 1, x: 3, y: 3, relev: 1, score: 1, source_phrase_hash: 0 }]);
+    t.throws(() => delta.addTombstones(), 'not enough arguments');
+    delta.addTombstones(Uint32Array.from([1]).buffer);
+    delta.finish();
+    t.throws(() => delta.addTombstones(Uint32Array.from([1]).buffer), 'throws after finish()');
+
+    const reader = new addon.GridStore(baseDir.name, null, [deltaDir.name]);
+    t.deepEquals(reader.get({ phrase_id: 0, lang_set: [0] }), [
+        { relev: 1, score: 2, x: 0, y: 0, id: 0, source_phrase_hash: 0 },
+        { relev: 1, score: 1, x: 3, y: 3, id: 1, source_phrase_hash: 0 }
+    ], 'tombstoned feature is replaced by the overlay\'s version');
+    t.equal(new addon.GridStore(baseDir.name).get({ phrase_id: 0, lang_set: [0] }).length, 2, 'base store is unchanged on its own');
+    t.throws(() => new addon.GridStore(baseDir.name, null, [tmp.dirSync().name]), 'throws if an overlay can\'t be opened');
+    t.end();
+});
+
+tape('GridStore phrasesForFeature()', (t) => {
+    const tmpDir = tmp.dirSync();
+    const builder = new addon.GridStoreBuilder(tmpDir.name);
+    builder.setFeatureIndex(true);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 7, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 1, lang_set: [0, 1] }, [{ id: 7, x: 2, y: 2, relev: 1, score: 3, source_phrase_hash: 0 }]);
+    builder.finish();
+
+    const reader = new addon.GridStore(tmpDir.name);
+    t.deepEquals(reader.phrasesForFeature(7), [{ phrase_id: 0, lang_set: [0] }, { phrase_id: 1, lang_set: [0, 1] }], 'lists every phrase indexing the feature');
+    t.deepEquals(reader.phrasesForFeature(8), [], 'unknown features have no phrases');
+
+    const plainDir = tmp.dirSync();
+    const plain = new addon.GridStoreBuilder(plainDir.name);
+    plain.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 7, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    plain.finish();
+    t.throws(() => new addon.GridStore(plainDir.name).phrasesForFeature(7), 'throws if the store has no feature index');
+    t.end();
+});
+
+tape('GridStore featuresAt() and featuresCovering()', (t) => {
+    const placesDir = tmp.dirSync();
+    const places = new addon.GridStoreBuilder(placesDir.name);
+    places.setTileIndex(true);
+    places.insert({ phrase_id: 0, lang_set: [0] }, [
+        { id: 1, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 },
+        { id: 2, x: 1, y: 1, relev: 1, score: 2, source_phrase_hash: 0 }
+    ]);
+    places.finish();
+
+    const addressesDir = tmp.dirSync();
+    const addresses = new addon.GridStoreBuilder(addressesDir.name);
+    addresses.setTileIndex(true);
+    addresses.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 5, x: 300, y: 300, relev: 1, score: 2, source_phrase_hash: 0 }]);
+    addresses.finish();
+
+    const placesReader = new addon.GridStore(placesDir.name, { zoom: 6, type_id: 1, coalesce_radius: 0, bboxes: [[0, 0, 63, 63]], max_score: 1 });
+    const addressesReader = new addon.GridStore(addressesDir.name, { zoom: 14, type_id: 0, coalesce_radius: 0, bboxes: [[0, 0, 16383, 16383]], max_score: 1 });
+    t.deepEquals(placesReader.featuresAt(1, 1), [1, 2], 'finds the features covering a tile');
+    t.deepEquals(placesReader.featuresAt(2, 2), [], 'nothing covers an empty tile');
+    t.deepEquals(addon.featuresCovering([placesReader, addressesReader], 6, 1, 1), [
+        { idx: 1, type_id: 0, ids: [5] },
+        { idx: 0, type_id: 1, ids: [1, 2] }
+    ], 'covering features from every store, ordered by type_id');
+    t.end();
+});
+
 tape('GridStore reader', (t) => {
     const tmpDir = tmp.dirSync();
     const builder = new addon.GridStoreBuilder(tmpDir.name);
-    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 0.5, score: 2, source_phrase_hash: 0 }]);
+    builder.insert({ phrase_id: 0, lang_set: [0] }, [{ id: 0, x: 0, y: 0, relev: 1, score: 2, source_phrase_hash: 0 }]);
     builder.insert({ phrase_id: 1, lang_set: [0, 1, 2, 3] }, [{ id: 2, x: 2, y: 2, relev: 0.8, score: 3, source_phrase_hash: 0 }]);
     builder.finish();
 
@@ -257,7 +508,7 @@ tape('Coalesce single valid stack - Valid inputs', (t) => {
                     { relev: 1, score: 1, x: 2, y: 2, id: 1, source_phrase_hash: 0 },
                     matches_language: true,
                     idx: 1,
-                    tmp_id: 16777217,
+                    tmp_id: 4294967297,
                     mask: 1,
                     distance: 0,
                     scoredist: 1,
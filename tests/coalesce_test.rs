@@ -431,7 +431,7 @@ fn coalesce_single_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777219,
+            tmp_id: 4294967299,
             mask: 1 << 0,
             distance: 0.,
             scoredist: 1.5839497841387566,
@@ -448,7 +448,7 @@ fn coalesce_single_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777217,
+            tmp_id: 4294967297,
             mask: 1 << 0,
             distance: 2.8284271247461903,
             scoredist: 1.109893833332405,
@@ -465,7 +465,7 @@ fn coalesce_single_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777218,
+            tmp_id: 4294967298,
             mask: 1 << 0,
             distance: 1.4142135623730951,
             // Has the same scoredist as 2nd result because they're both beyond proximity radius
@@ -942,7 +942,7 @@ fn coalesce_multi_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777218,
+            tmp_id: 4294967298,
             mask: 1 << 0,
             distance: 0.,
             scoredist: 3.,
@@ -987,7 +987,7 @@ fn coalesce_multi_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777219,
+            tmp_id: 4294967299,
             mask: 1 << 0,
             distance: 0.,
             scoredist: 1.,
@@ -1040,7 +1040,7 @@ fn coalesce_multi_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777219,
+            tmp_id: 4294967299,
             mask: 1 << 0,
             distance: 0.,
             scoredist: 1.5839497841387566,
@@ -1083,7 +1083,7 @@ fn coalesce_multi_test() {
             phrasematch_id: 0,
             matches_language: true,
             idx: 1,
-            tmp_id: 16777218,
+            tmp_id: 4294967298,
             mask: 1 << 0,
             distance: 1.4142135623730951,
             scoredist: 1.109893833332405,